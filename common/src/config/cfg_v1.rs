@@ -1,10 +1,16 @@
-use crate::config::{cfgparse::FlakeCfgVersionParser, itf::FlakeConfig};
+use crate::config::{
+    cfgparse::FlakeCfgVersionParser,
+    itf::FlakeConfig,
+    setup::{parse_setup, CfgAccess},
+};
 use nix::unistd::User;
 use serde::Deserialize;
 use serde_yaml::Value;
 use std::{io::Error, path::PathBuf};
 
-use super::itf::{FlakeCfgEngine, FlakeCfgPathProperties, FlakeCfgRuntime, FlakeCfgSetup, FlakeCfgStatic, InstanceMode, PathMap};
+use super::itf::{
+    AccessMode, FlakeCfgEngine, FlakeCfgEnv, FlakeCfgNetwork, FlakeCfgPathProperties, FlakeCfgResources, FlakeCfgRuntime, FlakeCfgSetup, FlakeCfgStatic, Identity, InstanceMode, PathMap,
+};
 
 #[derive(Deserialize, Debug)]
 struct CfgV1Spec {
@@ -102,11 +108,17 @@ struct CfgV1OciRuntime {
     resume: Option<bool>,
     attach: Option<bool>,
     podman: Option<Vec<String>>,
+    expand: Option<bool>,
+    host_paths: Option<CfgAccess>,
+    identity: Option<Identity>,
+    env: Option<FlakeCfgEnv>,
+    resources: Option<FlakeCfgResources>,
+    network: Option<FlakeCfgNetwork>,
 }
 
 impl CfgV1OciRuntime {
     pub(crate) fn default() -> Self {
-        CfgV1OciRuntime { runas: None, resume: None, attach: None, podman: None }
+        CfgV1OciRuntime { runas: None, resume: None, attach: None, podman: None, expand: None, host_paths: None, identity: None, env: None, resources: None, network: None }
     }
 
     fn get_runas_user(&self) -> Option<User> {
@@ -164,7 +176,7 @@ impl CfgV1Vm {
             name: "".to_string(),
             target_app_path: "".to_string(),
             host_app_path: "".to_string(),
-            runtime: CfgV1VmRuntime { runas: None, resume: None, firecracker: None },
+            runtime: CfgV1VmRuntime { runas: None, resume: None, firecracker: None, expand: None, identity: None, env: None, resources: None, network: None },
         }
     }
 
@@ -190,6 +202,11 @@ struct CfgV1VmRuntime {
     pub(crate) runas: Option<String>,
     pub(crate) resume: Option<bool>,
     pub(crate) firecracker: Option<Value>,
+    pub(crate) expand: Option<bool>,
+    pub(crate) identity: Option<Identity>,
+    pub(crate) env: Option<FlakeCfgEnv>,
+    pub(crate) resources: Option<FlakeCfgResources>,
+    pub(crate) network: Option<FlakeCfgNetwork>,
 }
 
 impl CfgV1VmRuntime {
@@ -243,7 +260,10 @@ impl FlakeCfgV1 {
         let mut paths = PathMap::default();
         paths.inner.insert(
             PathBuf::from(spec.get_container().get_host_app_path()),
-            FlakeCfgPathProperties::new(PathBuf::from(spec.get_container().get_target_app_path())),
+            FlakeCfgPathProperties {
+                host_paths: spec.get_container().get_runtime().host_paths.map(AccessMode::from),
+                ..FlakeCfgPathProperties::new(PathBuf::from(spec.get_container().get_target_app_path()))
+            },
         );
 
         FlakeConfig {
@@ -255,6 +275,11 @@ impl FlakeCfgV1 {
                 run_as: spec.get_container().get_runtime().get_runas_user(),
                 instance_mode: rt_flags,
                 paths,
+                expand: spec.get_container().get_runtime().expand.unwrap_or_default(),
+                identity: spec.get_container().get_runtime().identity.unwrap_or_default(),
+                env: spec.get_container().get_runtime().env.to_owned().unwrap_or_default(),
+                resources: spec.get_container().get_runtime().resources.to_owned().unwrap_or_default(),
+                network: spec.get_container().get_runtime().network.to_owned().unwrap_or_default(),
             },
             engine: FlakeCfgEngine {
                 pilot: "podman".to_string(),
@@ -264,7 +289,7 @@ impl FlakeCfgV1 {
                 params: None,
             },
             static_data: FlakeCfgStatic { bundles: spec.get_includes().get_tar() },
-            setup: FlakeCfgSetup {},
+            setup: FlakeCfgSetup::default(),
         }
     }
 
@@ -290,6 +315,11 @@ impl FlakeCfgV1 {
                 run_as: spec.get_vm().get_runtime().get_runas_user(),
                 instance_mode: rt_flags,
                 paths,
+                expand: spec.get_vm().get_runtime().expand.unwrap_or_default(),
+                identity: spec.get_vm().get_runtime().identity.unwrap_or_default(),
+                env: spec.get_vm().get_runtime().env.to_owned().unwrap_or_default(),
+                resources: spec.get_vm().get_runtime().resources.to_owned().unwrap_or_default(),
+                network: spec.get_vm().get_runtime().network.to_owned().unwrap_or_default(),
             },
             engine: FlakeCfgEngine {
                 pilot: "firecracker".to_string(),
@@ -297,7 +327,7 @@ impl FlakeCfgV1 {
                 params: spec.get_vm().get_runtime().get_firecracker(),
             },
             static_data: FlakeCfgStatic { bundles: None },
-            setup: FlakeCfgSetup {},
+            setup: FlakeCfgSetup::default(),
         }
     }
 }
@@ -308,9 +338,9 @@ impl FlakeCfgVersionParser for FlakeCfgV1 {
             Ok(spec) => {
                 if let Value::Mapping(content) = &self.content {
                     if content.contains_key("container") && content.get("container").is_some() {
-                        return Ok(self.as_container(spec));
+                        return Ok(FlakeConfig { setup: parse_setup(&self.content)?, ..self.as_container(spec) });
                     } else if content.contains_key("vm") && content.get("vm").is_some() {
-                        return Ok(self.as_vm(spec));
+                        return Ok(FlakeConfig { setup: parse_setup(&self.content)?, ..self.as_vm(spec) });
                     }
                 }
             }
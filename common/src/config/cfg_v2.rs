@@ -1,5 +1,11 @@
-use super::itf::{FlakeCfgEngine, FlakeCfgPathProperties, FlakeCfgRuntime, FlakeCfgSetup, FlakeCfgStatic, InstanceMode, PathMap};
-use crate::config::{cfgparse::FlakeCfgVersionParser, itf::FlakeConfig};
+use super::itf::{
+    AccessMode, FlakeCfgEngine, FlakeCfgEnv, FlakeCfgNetwork, FlakeCfgPathProperties, FlakeCfgResources, FlakeCfgRuntime, FlakeCfgSetup, FlakeCfgStatic, Identity, InstanceMode, PathMap,
+};
+use crate::config::{
+    cfgparse::FlakeCfgVersionParser,
+    itf::FlakeConfig,
+    setup::{parse_setup, CfgAccess},
+};
 use nix::unistd::User;
 use serde::Deserialize;
 use serde_yaml::Value;
@@ -24,6 +30,11 @@ struct CfgV2Runtime {
     layers: Option<Vec<String>>,
     user: Option<String>,
     instance: Option<String>,
+    expand: Option<bool>,
+    identity: Option<Identity>,
+    env: Option<FlakeCfgEnv>,
+    resources: Option<FlakeCfgResources>,
+    network: Option<FlakeCfgNetwork>,
 }
 
 impl CfgV2Runtime {
@@ -83,6 +94,7 @@ impl CfgV2Runtime {
                         exports: if rp.exports.is_none() { PathBuf::from(target) } else { PathBuf::from(rp.exports.unwrap()) },
                         run_as: if rp.user.is_some() { self.get_runas_user(rp.user) } else { None },
                         instance_mode: Some(i_mode),
+                        host_paths: rp.host_paths.map(AccessMode::from),
                     },
                 );
             }
@@ -97,6 +109,7 @@ struct CfgV2PathProperties {
     exports: Option<String>,
     user: Option<String>,
     instance: Option<String>,
+    host_paths: Option<CfgAccess>,
 }
 
 ///Engine section
@@ -127,10 +140,15 @@ impl FlakeCfgV2 {
                 run_as: spec.runtime.get_runas_user(None),
                 instance_mode: spec.runtime.get_instance(),
                 paths: spec.runtime.get_path_map(),
+                expand: spec.runtime.expand.unwrap_or_default(),
+                identity: spec.runtime.identity.unwrap_or_default(),
+                env: spec.runtime.env.to_owned().unwrap_or_default(),
+                resources: spec.runtime.resources.to_owned().unwrap_or_default(),
+                network: spec.runtime.network.to_owned().unwrap_or_default(),
             },
             engine: FlakeCfgEngine { pilot: spec.engine.pilot, args: spec.engine.args, params: spec.engine.params },
             static_data: FlakeCfgStatic { bundles: spec.static_data },
-            setup: FlakeCfgSetup {},
+            setup: FlakeCfgSetup::default(),
         }
     }
 }
@@ -138,7 +156,7 @@ impl FlakeCfgV2 {
 impl FlakeCfgVersionParser for FlakeCfgV2 {
     fn parse(&self) -> Result<FlakeConfig, Error> {
         match serde_yaml::from_value::<CfgV2Spec>(self.content.to_owned()) {
-            Ok(spec) => Ok(self.as_cfg(spec)),
+            Ok(spec) => Ok(FlakeConfig { setup: parse_setup(&self.content)?, ..self.as_cfg(spec) }),
             Err(err) => Err(Error::new(std::io::ErrorKind::InvalidData, format!("Config v2 parse error: {}", err))),
         }
     }
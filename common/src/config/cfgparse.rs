@@ -1,7 +1,7 @@
-use super::{cfg_v2::FlakeCfgV2, itf::FlakeConfig};
+use super::{cfg_v2::FlakeCfgV2, conditions::HostFacts, itf::FlakeConfig};
 use crate::config::cfg_v1::FlakeCfgV1;
 use serde::Deserialize;
-use serde_yaml::Value;
+use serde_yaml::{value::TaggedValue, Mapping, Value};
 use std::{
     fs::{self},
     io::Error,
@@ -24,6 +24,9 @@ pub trait FlakeCfgVersionParser {
 pub struct FlakeCfgParser {
     cfg_path: PathBuf,
     cfg_d_paths: Vec<PathBuf>,
+    user_d_paths: Vec<PathBuf>,
+    user_allowed: Vec<String>,
+    facts: HostFacts,
 }
 
 impl FlakeCfgParser {
@@ -37,13 +40,72 @@ impl FlakeCfgParser {
             }
         }
 
-        Ok(FlakeCfgParser { cfg_path, cfg_d_paths })
+        Ok(FlakeCfgParser { cfg_path, cfg_d_paths, user_d_paths: vec![], user_allowed: vec![], facts: HostFacts::detect() })
     }
 
-    /// Merge YAML config source
+    /// Use the given host facts to select the overlays, instead of the detected ones
+    pub fn with_host_facts(mut self, facts: HostFacts) -> Self {
+        self.facts = facts;
+        self
+    }
+
+    /// Read an overlay, returns None if its condition does not match the host
+    fn read_overlay(&self, p: &PathBuf) -> Result<Option<Value>, Error> {
+        let mut overlay = Self::read_value(p)?;
+        Ok(self.facts.applies(&mut overlay)?.then_some(overlay))
+    }
+
+    /// Add configuration overlays of a user, merged after the system overlays.
+    ///
+    /// Only keys listed in `allowed` as dotted paths (e.g. `engine.args`) are
+    /// taken from the user overlays, everything else is dropped with a warning.
+    pub fn with_user_overlay(mut self, user_d_paths: Vec<PathBuf>, allowed: Vec<String>) -> Self {
+        self.user_d_paths = user_d_paths;
+        self.user_allowed = allowed;
+        self
+    }
+
+    /// Keep only the allowed parts of a user overlay value at the dotted `path`.
+    /// Dotted paths of all taken values are collected to `taken`.
+    fn filter_allowed(value: Value, path: &str, allowed: &[String], taken: &mut Vec<String>) -> Option<Value> {
+        if !path.is_empty() && allowed.iter().any(|a| a == path) {
+            if !taken.iter().any(|t| t == path) {
+                taken.push(path.to_string());
+            }
+            return Some(value);
+        }
+
+        match value {
+            Value::Mapping(map) if path.is_empty() || allowed.iter().any(|a| a.starts_with(&format!("{path}."))) => {
+                let mut filtered = Mapping::new();
+                for (key, value) in map {
+                    let Some(name) = key.as_str() else { continue };
+                    let sub_path = if path.is_empty() { name.to_string() } else { format!("{path}.{name}") };
+                    if let Some(value) = Self::filter_allowed(value, &sub_path, allowed, taken) {
+                        filtered.insert(key, value);
+                    }
+                }
+                (!filtered.is_empty()).then_some(filtered.into())
+            }
+            _ => {
+                log::warn!("User override of \"{path}\" is not allowed");
+                None
+            }
+        }
+    }
+
+    /// Merge YAML config source.
+    ///
+    /// Mappings are merged recursively, any other value is replaced, unless
+    /// a list is tagged with one of the merge directives `!append`, `!prepend` or `!remove`.
     fn merge_values(base: Value, update: Value) -> Value {
         match (base, update) {
-            (Value::Mapping(mut base), Value::Mapping(update)) => {
+            (base, Value::Mapping(update)) => {
+                let mut base = match base {
+                    Value::Mapping(base) => base,
+                    _ => Mapping::new(),
+                };
+
                 // TODO: This could be written nicer by somebody who wants to fight with the lifetimes of `Mapping::entry`
                 for (key, value) in update {
                     let old = base.get(&key).cloned().unwrap_or_default();
@@ -51,11 +113,36 @@ impl FlakeCfgParser {
                 }
                 base.into()
             }
+            (base, Value::Tagged(update)) => Self::merge_directive(base, *update),
             (base, Value::Null) => base,
             (_, update) => update,
         }
     }
 
+    /// Apply a list merge directive to the base list.
+    /// A missing base list is taken as empty, unknown tags are left as they are.
+    fn merge_directive(base: Value, update: TaggedValue) -> Value {
+        let directive = ["append", "prepend", "remove"].into_iter().find(|d| update.tag == *d);
+        let (Some(directive), Value::Sequence(items)) = (directive, &update.value) else {
+            return Value::Tagged(Box::new(update));
+        };
+
+        let mut base = match base {
+            Value::Sequence(base) => base,
+            _ => vec![],
+        };
+
+        match directive {
+            "append" => base.extend(items.iter().cloned()),
+            "prepend" => {
+                base.splice(0..0, items.iter().cloned());
+            }
+            _ => base.retain(|item| !items.contains(item)),
+        }
+
+        Value::Sequence(base)
+    }
+
     /// Get the configuration version from the base config (explicitly ignoring the .d part)
     fn get_version(&self) -> u8 {
         if let Ok(data) = &fs::read_to_string(&self.cfg_path) {
@@ -68,49 +155,62 @@ impl FlakeCfgParser {
         1
     }
 
+    fn read_value(p: &PathBuf) -> Result<Value, Error> {
+        serde_yaml::from_str::<Value>(&fs::read_to_string(p)?).map_err(|_| {
+            std::io::Error::new(std::io::ErrorKind::InvalidData, format!("Error while parsing config: {}", p.to_str().unwrap()))
+        })
+    }
+
     fn get_config(&self) -> Result<Value, Error> {
-        let mut cfg: Option<Value> = None;
-
-        for p in vec![&self.cfg_path].into_iter().chain(&self.cfg_d_paths) {
-            let raw_data = &fs::read_to_string(p)?;
-            if let Ok(d_cfg) = serde_yaml::from_str::<Value>(raw_data) {
-                if cfg.is_none() {
-                    cfg = Some(d_cfg);
-                } else {
-                    cfg = Some(Self::merge_values(cfg.unwrap(), d_cfg));
-                }
-            } else {
-                return Err(std::io::Error::new(
-                    std::io::ErrorKind::InvalidData,
-                    format!("Error while parsing config: {}", p.to_str().unwrap()),
-                ));
+        // Merge the base config into an empty one as well to resolve its merge directives
+        let mut cfg = Self::merge_values(Value::Null, Self::read_value(&self.cfg_path)?);
+        for p in &self.cfg_d_paths {
+            if let Some(d_cfg) = self.read_overlay(p)? {
+                cfg = Self::merge_values(cfg, d_cfg);
             }
         }
 
-        if let Some(cfg) = cfg {
-            return Ok(cfg);
+        if cfg.is_null() {
+            return Err(std::io::Error::new(std::io::ErrorKind::NotFound, "No configuration found"));
+        }
+
+        Ok(cfg)
+    }
+
+    /// Get the merged raw configuration, including the user overlays,
+    /// together with the dotted paths of all values provided by the user
+    pub fn get_raw_config(&self) -> Result<(Value, Vec<String>), Error> {
+        let mut cfg = self.get_config()?;
+        let mut user_keys: Vec<String> = vec![];
+
+        for p in &self.user_d_paths {
+            let Some(u_cfg) = self.read_overlay(p)? else { continue };
+            if let Some(u_cfg) = Self::filter_allowed(u_cfg, "", &self.user_allowed, &mut user_keys) {
+                cfg = Self::merge_values(cfg, u_cfg);
+            }
         }
 
-        Err(std::io::Error::new(std::io::ErrorKind::NotFound, "No configuration found"))
+        Ok((cfg, user_keys))
     }
 
     /// Parse given config
     pub fn parse(&self) -> Option<FlakeConfig> {
-        let cfg_val = self.get_config();
+        let cfg_val = self.get_raw_config();
         if cfg_val.is_err() {
             return None;
         }
+        let (cfg_val, _) = cfg_val.unwrap();
 
         let parser: Box<dyn FlakeCfgVersionParser> = match self.get_version() {
-            1 => Box::new(FlakeCfgV1::new(cfg_val.unwrap())),
-            2 => Box::new(FlakeCfgV2::new(cfg_val.unwrap())),
+            1 => Box::new(FlakeCfgV1::new(cfg_val)),
+            2 => Box::new(FlakeCfgV2::new(cfg_val)),
             unsupported => {
                 println!("ERROR: Unsupported configuration version: {}", unsupported);
                 return None;
             }
         };
 
-        match parser.parse() {
+        match parser.parse().and_then(|cfg| cfg.check_placeholders().map(|_| cfg)) {
             Ok(cfg) => Some(cfg),
             Err(err) => panic!("Error: {}", err),
         }
@@ -0,0 +1,133 @@
+use serde::Deserialize;
+use serde_yaml::Value;
+use std::{
+    collections::HashMap,
+    env, fs,
+    io::{Error, ErrorKind},
+    path::Path,
+};
+
+/// Header key of an overlay, declaring the condition to apply it
+pub const CONDITION_KEY: &str = "when";
+
+/// One or more glob patterns, any of them must match
+#[derive(Deserialize, Debug, Clone)]
+#[serde(untagged)]
+enum Patterns {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl Patterns {
+    fn matches(&self, value: &str) -> bool {
+        match self {
+            Patterns::One(p) => glob_match(p, value),
+            Patterns::Many(ps) => ps.iter().any(|p| glob_match(p, value)),
+        }
+    }
+}
+
+/// Condition of an overlay. All specified facts must match.
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct OverlayCondition {
+    /// CPU architecture, e.g. `x86_64` or `aarch64`
+    arch: Option<Patterns>,
+
+    /// Host name glob, e.g. `board-*`
+    hostname: Option<Patterns>,
+
+    /// Presence of `/dev/kvm`
+    kvm: Option<bool>,
+
+    /// `ID` of the os-release, e.g. `ubuntu`
+    os_id: Option<Patterns>,
+
+    /// Environment variables with a value glob, use `*` to check presence only
+    env: Option<HashMap<String, String>>,
+}
+
+/// Facts of the host to match the overlay conditions against
+#[derive(Debug, Clone, Default)]
+pub struct HostFacts {
+    pub arch: String,
+    pub hostname: String,
+    pub kvm: bool,
+    pub os_id: String,
+    pub env: HashMap<String, String>,
+}
+
+impl HostFacts {
+    /// Detect facts of the running host
+    pub fn detect() -> Self {
+        HostFacts {
+            arch: env::consts::ARCH.to_string(),
+            hostname: fs::read_to_string("/proc/sys/kernel/hostname").unwrap_or_default().trim().to_string(),
+            kvm: Path::new("/dev/kvm").exists(),
+            os_id: os_release_id(),
+            env: env::vars().collect(),
+        }
+    }
+
+    /// Check if the condition matches these facts
+    pub fn matches(&self, cond: &OverlayCondition) -> bool {
+        cond.arch.as_ref().map(|p| p.matches(&self.arch)).unwrap_or(true)
+            && cond.hostname.as_ref().map(|p| p.matches(&self.hostname)).unwrap_or(true)
+            && cond.kvm.map(|kvm| kvm == self.kvm).unwrap_or(true)
+            && cond.os_id.as_ref().map(|p| p.matches(&self.os_id)).unwrap_or(true)
+            && cond
+                .env
+                .iter()
+                .flatten()
+                .all(|(name, pattern)| self.env.get(name).map(|value| glob_match(pattern, value)).unwrap_or_default())
+    }
+
+    /// Take the condition header off an overlay and check if it applies to this host.
+    /// Overlays without a condition always apply.
+    pub fn applies(&self, overlay: &mut Value) -> Result<bool, Error> {
+        let Some(cond) = overlay.as_mapping_mut().and_then(|m| m.remove(CONDITION_KEY)) else {
+            return Ok(true);
+        };
+
+        let cond = serde_yaml::from_value::<OverlayCondition>(cond)
+            .map_err(|err| Error::new(ErrorKind::InvalidData, format!("Invalid overlay condition: {}", err)))?;
+        Ok(self.matches(&cond))
+    }
+}
+
+/// Get `ID` of the os-release
+fn os_release_id() -> String {
+    ["/etc/os-release", "/usr/lib/os-release"]
+        .iter()
+        .find_map(|p| fs::read_to_string(p).ok())
+        .unwrap_or_default()
+        .lines()
+        .find_map(|line| line.strip_prefix("ID="))
+        .map(|id| id.trim_matches('"').to_string())
+        .unwrap_or_default()
+}
+
+/// Match a glob pattern with `*` and `?` wildcards
+pub(crate) fn glob_match(pattern: &str, value: &str) -> bool {
+    let (p, v): (Vec<char>, Vec<char>) = (pattern.chars().collect(), value.chars().collect());
+    let (mut pi, mut vi) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+
+    while vi < v.len() {
+        if pi < p.len() && (p[pi] == '?' || p[pi] == v[vi]) {
+            pi += 1;
+            vi += 1;
+        } else if pi < p.len() && p[pi] == '*' {
+            star = Some((pi, vi));
+            pi += 1;
+        } else if let Some((spi, svi)) = star {
+            pi = spi + 1;
+            vi = svi + 1;
+            star = Some((spi, svi + 1));
+        } else {
+            return false;
+        }
+    }
+
+    p[pi..].iter().all(|c| *c == '*')
+}
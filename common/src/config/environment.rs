@@ -0,0 +1,68 @@
+use super::{conditions::glob_match, itf::FlakeCfgEnv, placeholders::Placeholders};
+use serde::Deserialize;
+use std::{
+    collections::BTreeMap,
+    io::{Error, ErrorKind},
+};
+
+/// Environment section of the runtime, shared by all config versions
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct CfgEnv {
+    #[serde(default)]
+    set: BTreeMap<String, String>,
+    #[serde(default)]
+    pass: Vec<String>,
+    #[serde(default)]
+    deny: Vec<String>,
+}
+
+impl TryFrom<CfgEnv> for FlakeCfgEnv {
+    type Error = String;
+
+    fn try_from(value: CfgEnv) -> Result<Self, Self::Error> {
+        if let Some(name) = value.set.keys().find(|name| !is_var_name(name)) {
+            return Err(format!("Invalid environment variable name \"{name}\""));
+        }
+        Ok(FlakeCfgEnv { set: value.set, pass: value.pass, deny: value.deny })
+    }
+}
+
+/// Check if the name is a valid environment variable name
+pub fn is_var_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().map(|c| c.is_ascii_alphabetic() || c == '_').unwrap_or_default()
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+impl FlakeCfgEnv {
+    /// Returns true if nothing is passed to the app
+    pub fn is_empty(&self) -> bool {
+        self.set.is_empty() && self.pass.is_empty()
+    }
+
+    /// Resolve the environment of the app from the given caller variables, sorted by name.
+    ///
+    /// Variables matching a `pass` glob are forwarded, unless they match a `deny` glob.
+    /// Explicitly `set` values always win.
+    pub fn resolve(&self, vars: impl IntoIterator<Item = (String, String)>) -> Vec<(String, String)> {
+        let mut env: BTreeMap<String, String> = vars
+            .into_iter()
+            .filter(|(name, _)| is_var_name(name))
+            .filter(|(name, _)| self.pass.iter().any(|p| glob_match(p, name)) && !self.deny.iter().any(|p| glob_match(p, name)))
+            .collect();
+        env.extend(self.set.to_owned());
+
+        env.into_iter().collect()
+    }
+
+    /// Get a copy with all placeholders resolved in the set values
+    pub fn expanded(&self, placeholders: &Placeholders) -> Result<FlakeCfgEnv, Error> {
+        let mut env = self.to_owned();
+        for value in env.set.values_mut() {
+            *value = placeholders.expand(value).map_err(|err| Error::new(ErrorKind::InvalidData, err))?;
+        }
+
+        Ok(env)
+    }
+}
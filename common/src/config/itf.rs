@@ -1,10 +1,13 @@
+use super::placeholders::{self, Placeholders};
 use bitflags::bitflags;
-use nix::unistd::User;
+use nix::unistd::{getgid, getuid, User};
+use serde::Deserialize;
 use serde_yaml::Value;
 use std::{
-    collections::{HashMap, hash_map::Keys},
+    collections::{BTreeMap, HashMap, hash_map::Keys},
     default::Default,
     hash::Hash,
+    io::Error,
     ops::{Deref, DerefMut},
     path::PathBuf,
 };
@@ -58,6 +61,58 @@ impl FlakeConfig {
     pub fn static_data(&self) -> &FlakeCfgStatic {
         &self.static_data
     }
+
+    /// Get all values which may contain placeholders:
+    /// engine args, path map exports, bundle paths and set environment values
+    fn expandable(&self) -> impl Iterator<Item = String> + '_ {
+        self.engine
+            .args
+            .iter()
+            .flatten()
+            .cloned()
+            .chain(self.runtime.paths.values().map(|p| p.exports.to_string_lossy().to_string()))
+            .chain(self.static_data.bundles.iter().flatten().cloned())
+            .chain(self.runtime.env.set.values().cloned())
+    }
+
+    /// Check placeholders of the configuration, if expansion is enabled
+    pub fn check_placeholders(&self) -> Result<(), Error> {
+        if self.runtime.expand {
+            for value in self.expandable() {
+                placeholders::validate(&value)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Get a copy of the configuration with all placeholders resolved
+    /// in engine args, path map exports, bundle paths and set environment values.
+    ///
+    /// The configuration is returned unchanged, unless expansion is enabled by `runtime.expand`.
+    pub fn expanded(&self, placeholders: &Placeholders) -> Result<FlakeConfig, Error> {
+        let mut cfg = self.to_owned();
+        if !self.runtime.expand {
+            return Ok(cfg);
+        }
+
+        if let Some(args) = cfg.engine.args.as_mut() {
+            for arg in args.iter_mut() {
+                *arg = placeholders.expand(arg)?;
+            }
+        }
+        for props in cfg.runtime.paths.values_mut() {
+            props.exports = PathBuf::from(placeholders.expand(&props.exports.to_string_lossy())?);
+        }
+        if let Some(bundles) = cfg.static_data.bundles.as_mut() {
+            for bundle in bundles.iter_mut() {
+                *bundle = placeholders.expand(bundle)?;
+            }
+        }
+        cfg.runtime.env = cfg.runtime.env.expanded(placeholders)?;
+
+        Ok(cfg)
+    }
 }
 
 impl Default for FlakeConfig {
@@ -66,7 +121,7 @@ impl Default for FlakeConfig {
             version: 1,
             runtime: FlakeCfgRuntime::default(),
             engine: FlakeCfgEngine::default(),
-            setup: FlakeCfgSetup {},
+            setup: FlakeCfgSetup::default(),
             static_data: FlakeCfgStatic { bundles: None },
         }
     }
@@ -97,11 +152,17 @@ impl PathMap {
     }
 
     /// Get properties by a PathBuf.
+    ///
+    /// Falls back to an entry with the same file name, as flakes of a user
+    /// are called from `~/.local/bin` instead of their registered host path.
     pub fn get_by_path(&self, p: PathBuf) -> Option<&FlakeCfgPathProperties> {
         if let Some(p) = self.get(&p) {
             return Some(p);
         }
-        self.get(&PathBuf::from(p.file_name().unwrap()))
+
+        let name = p.file_name()?;
+        self.get(&PathBuf::from(name))
+            .or_else(|| self.iter().find(|(k, _)| k.file_name() == Some(name)).map(|(_, props)| props))
     }
 }
 
@@ -124,6 +185,21 @@ pub struct FlakeCfgRuntime {
 
     //pub(crate) paths: HashMap<PathBuf, FlakeCfgPathProperties>,
     pub(crate) paths: PathMap,
+
+    // Resolve placeholders like "${HOME}" at launch time
+    pub(crate) expand: bool,
+
+    // Identity of the app inside the instance
+    pub(crate) identity: Identity,
+
+    // Environment of the app inside the instance
+    pub(crate) env: FlakeCfgEnv,
+
+    // Resource limits of the instance
+    pub(crate) resources: FlakeCfgResources,
+
+    // Network of the instance
+    pub(crate) network: FlakeCfgNetwork,
 }
 
 impl FlakeCfgRuntime {
@@ -162,6 +238,32 @@ impl FlakeCfgRuntime {
         &self.paths
     }
 
+    /// Returns true if placeholders are resolved at launch time,
+    /// see [`super::placeholders::PLACEHOLDERS`].
+    pub fn expand(&self) -> bool {
+        self.expand
+    }
+
+    /// Get the identity the app runs with inside the instance
+    pub fn identity(&self) -> Identity {
+        self.identity
+    }
+
+    /// Get the environment policy of the app
+    pub fn env(&self) -> &FlakeCfgEnv {
+        &self.env
+    }
+
+    /// Get the resource limits of the instance
+    pub fn resources(&self) -> &FlakeCfgResources {
+        &self.resources
+    }
+
+    /// Get the network policy of the instance
+    pub fn network(&self) -> &FlakeCfgNetwork {
+        &self.network
+    }
+
     /// Returns a tuple containing the "proper" name of the flake and an iterator over all other paths
     /// 
     /// Returns `None` if there are not paths in the config
@@ -180,6 +282,11 @@ impl Default for FlakeCfgRuntime {
             run_as: None,
             instance_mode: InstanceMode::default(),
             paths: PathMap::default(),
+            expand: false,
+            identity: Identity::default(),
+            env: FlakeCfgEnv::default(),
+            resources: FlakeCfgResources::default(),
+            network: FlakeCfgNetwork::default(),
         }
     }
 }
@@ -189,11 +296,12 @@ pub struct FlakeCfgPathProperties {
     pub(crate) exports: PathBuf,
     pub(crate) run_as: Option<User>,
     pub(crate) instance_mode: Option<InstanceMode>,
+    pub(crate) host_paths: Option<AccessMode>,
 }
 
 impl FlakeCfgPathProperties {
     pub fn new(exports: PathBuf) -> Self {
-        FlakeCfgPathProperties { run_as: None, instance_mode: None, exports }
+        FlakeCfgPathProperties { run_as: None, instance_mode: None, exports, host_paths: None }
     }
 
     /// Returns a reference to the exports of this [`FlakeCfgPathProperties`].
@@ -210,6 +318,11 @@ impl FlakeCfgPathProperties {
     pub fn instance_mode(&self) -> Option<InstanceMode> {
         self.instance_mode
     }
+
+    /// Returns the access mode of host paths, passed as arguments, if their translation is enabled.
+    pub fn host_paths(&self) -> Option<AccessMode> {
+        self.host_paths
+    }
 }
 
 bitflags! {
@@ -256,8 +369,9 @@ pub struct FlakeCfgEngine {
 
     // Proxy-pass static arguments "as is". Argument can be anything:
     // flags, keywords like "--foo=bar" etc. These arguments do
-    // not allow to interpolate anything. For example, "--foo=$BAR"
-    // will literally pass "$" as an escaped character.
+    // not interpolate environment variables. For example, "--foo=$BAR"
+    // will literally pass "$" as an escaped character. Only the known
+    // placeholders like "${HOME}" are resolved, if "runtime.expand" is set.
     pub(crate) args: Option<Vec<String>>,
 
     // Arbitrary internal params, those are only known to a specific pilot
@@ -298,8 +412,329 @@ impl Default for FlakeCfgEngine {
 /// FlakeConfigSetup is a namespace for all configuration options
 /// related to the Flake setup, such as permission access to
 /// the media, X11, directories etc
-#[derive(Debug, Clone)]
-pub struct FlakeCfgSetup {}
+#[derive(Debug, Clone, Default)]
+pub struct FlakeCfgSetup {
+    // Share the home directory of the calling user
+    pub(crate) home: Option<AccessMode>,
+
+    // Share the current working directory and start the app in it
+    pub(crate) cwd: Option<AccessMode>,
+
+    // Extra bind mounts of host paths
+    pub(crate) mounts: Vec<BindMount>,
+
+    // Sockets of the desktop services to pass through
+    pub(crate) sockets: Vec<HostSocket>,
+
+    // Access to the D-Bus session bus
+    pub(crate) dbus: bool,
+
+    // Device nodes of the host
+    pub(crate) devices: Vec<PathBuf>,
+
+    // Trust data of the host to inject at provisioning time
+    pub(crate) host_trust: Vec<HostTrust>,
+}
+
+impl FlakeCfgSetup {
+    /// Returns true if nothing of the host is shared
+    pub fn is_empty(&self) -> bool {
+        self.home.is_none()
+            && self.cwd.is_none()
+            && self.mounts.is_empty()
+            && self.sockets.is_empty()
+            && !self.dbus
+            && self.devices.is_empty()
+            && self.host_trust.is_empty()
+    }
+
+    /// Get access mode of the shared home directory, if shared.
+    pub fn home(&self) -> Option<AccessMode> {
+        self.home
+    }
+
+    /// Get access mode of the shared current working directory, if shared.
+    pub fn cwd(&self) -> Option<AccessMode> {
+        self.cwd
+    }
+
+    /// Get extra bind mounts.
+    pub fn mounts(&self) -> &[BindMount] {
+        &self.mounts
+    }
+
+    /// Get sockets of the desktop services to pass through.
+    pub fn sockets(&self) -> &[HostSocket] {
+        &self.sockets
+    }
+
+    /// Returns true if the D-Bus session bus is accessible.
+    pub fn dbus(&self) -> bool {
+        self.dbus
+    }
+
+    /// Get device nodes of the host.
+    pub fn devices(&self) -> &[PathBuf] {
+        &self.devices
+    }
+
+    /// Get trust data of the host to inject.
+    pub fn host_trust(&self) -> &[HostTrust] {
+        &self.host_trust
+    }
+}
+
+/// Identity the app runs with inside the instance
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Identity {
+    /// User of the image, usually root
+    #[default]
+    Image,
+
+    /// Calling user, mapped into a user namespace of the instance
+    Host,
+
+    /// Calling user by its uid and gid, without a user namespace
+    User,
+}
+
+impl Identity {
+    /// Get uid and gid of the calling user, if the app runs with them
+    pub fn caller_ids(&self) -> Option<(u32, u32)> {
+        match self {
+            Identity::Image => None,
+            Identity::Host | Identity::User => Some((getuid().as_raw(), getgid().as_raw())),
+        }
+    }
+}
+
+/// Environment policy of the app. Caller variables matching `pass`
+/// are forwarded unless they match `deny`, `set` values always apply.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "super::environment::CfgEnv")]
+pub struct FlakeCfgEnv {
+    pub(crate) set: BTreeMap<String, String>,
+    pub(crate) pass: Vec<String>,
+    pub(crate) deny: Vec<String>,
+}
+
+impl FlakeCfgEnv {
+    /// Get explicitly set variables
+    pub fn set(&self) -> &BTreeMap<String, String> {
+        &self.set
+    }
+
+    /// Get names or globs of caller variables to forward
+    pub fn pass(&self) -> &[String] {
+        &self.pass
+    }
+
+    /// Get names or globs of caller variables never to forward
+    pub fn deny(&self) -> &[String] {
+        &self.deny
+    }
+}
+
+/// Resource limits of an instance, engine-neutral
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(try_from = "super::resources::CfgResources")]
+pub struct FlakeCfgResources {
+    pub(crate) cpus: Option<f64>,
+    pub(crate) cpu_shares: Option<u64>,
+    pub(crate) memory: Option<u64>,
+    pub(crate) pids: Option<u64>,
+    pub(crate) io_weight: Option<u16>,
+}
+
+impl FlakeCfgResources {
+    /// Get the CPU quota as number of CPUs, e.g. 1.5
+    pub fn cpus(&self) -> Option<f64> {
+        self.cpus
+    }
+
+    /// Get the relative CPU weight as shares (2..262144)
+    pub fn cpu_shares(&self) -> Option<u64> {
+        self.cpu_shares
+    }
+
+    /// Get the maximum memory in bytes
+    pub fn memory(&self) -> Option<u64> {
+        self.memory
+    }
+
+    /// Get the maximum number of processes
+    pub fn pids(&self) -> Option<u64> {
+        self.pids
+    }
+
+    /// Get the relative I/O weight (10..1000)
+    pub fn io_weight(&self) -> Option<u16> {
+        self.io_weight
+    }
+}
+
+/// Network policy of an instance, engine-neutral
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "super::network::CfgNetwork")]
+pub struct FlakeCfgNetwork {
+    pub(crate) mode: NetworkMode,
+    pub(crate) publish: Vec<PublishedPort>,
+}
+
+impl FlakeCfgNetwork {
+    /// Get the network mode
+    pub fn mode(&self) -> &NetworkMode {
+        &self.mode
+    }
+
+    /// Get the ports published on the host
+    pub fn publish(&self) -> &[PublishedPort] {
+        &self.publish
+    }
+}
+
+/// Network mode of an instance
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum NetworkMode {
+    /// Whatever the engine defaults to
+    #[default]
+    Default,
+
+    /// No network at all, only loopback
+    None,
+
+    /// Network of the host
+    Host,
+
+    /// Own network, connected to the outside by the engine
+    Private,
+
+    /// Own network, attached to the named bridge
+    Bridge(String),
+}
+
+/// Port of an instance, published on the host
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PublishedPort {
+    pub(crate) host_ip: Option<String>,
+    pub(crate) host_port: u16,
+    pub(crate) guest_port: u16,
+    pub(crate) protocol: Protocol,
+}
+
+impl PublishedPort {
+    /// Address on the host to bind to, all addresses if None
+    pub fn host_ip(&self) -> Option<&str> {
+        self.host_ip.as_deref()
+    }
+
+    /// Port on the host
+    pub fn host_port(&self) -> u16 {
+        self.host_port
+    }
+
+    /// Port inside the instance
+    pub fn guest_port(&self) -> u16 {
+        self.guest_port
+    }
+
+    /// Protocol of the port
+    pub fn protocol(&self) -> Protocol {
+        self.protocol
+    }
+}
+
+/// Transport protocol of a published port
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Protocol {
+    #[default]
+    Tcp,
+    Udp,
+}
+
+impl Protocol {
+    /// Get the protocol name, `tcp` or `udp`
+    pub fn as_str(&self) -> &str {
+        match self {
+            Protocol::Tcp => "tcp",
+            Protocol::Udp => "udp",
+        }
+    }
+}
+
+/// Access mode of a shared host path
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AccessMode {
+    #[default]
+    ReadOnly,
+    ReadWrite,
+}
+
+impl AccessMode {
+    /// Get the mount option of the access mode, `ro` or `rw`
+    pub fn as_str(&self) -> &str {
+        match self {
+            AccessMode::ReadOnly => "ro",
+            AccessMode::ReadWrite => "rw",
+        }
+    }
+}
+
+/// Bind mount of a host path into the instance
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BindMount {
+    pub(crate) source: PathBuf,
+    pub(crate) target: PathBuf,
+    pub(crate) mode: AccessMode,
+}
+
+impl BindMount {
+    /// Path on the host
+    pub fn source(&self) -> &PathBuf {
+        &self.source
+    }
+
+    /// Path inside the instance
+    pub fn target(&self) -> &PathBuf {
+        &self.target
+    }
+
+    /// Access mode of the mount
+    pub fn mode(&self) -> AccessMode {
+        self.mode
+    }
+}
+
+/// Socket of a desktop service on the host
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HostSocket {
+    Wayland,
+    X11,
+    PulseAudio,
+    PipeWire,
+}
+
+/// Trust data of the host, injected into the instance
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HostTrust {
+    /// CA certificate bundle
+    Certificates,
+
+    /// DNS resolver configuration
+    Resolver,
+
+    /// Static host names
+    Hosts,
+
+    /// Proxy environment variables
+    Proxy,
+}
+
+impl HostTrust {
+    /// All kinds of trust data
+    pub const ALL: [HostTrust; 4] = [HostTrust::Certificates, HostTrust::Resolver, HostTrust::Hosts, HostTrust::Proxy];
+}
 
 /// Static data.
 /// It is all kind of stuff that will be written over the rootfs
@@ -1,7 +1,12 @@
-use crate::paths::flake_dir_from;
+use crate::{
+    paths::{flake_dir_from, user_config_dir},
+    registry::{flake_name, FlakeRegistry},
+};
 
 use self::{cfgparse::FlakeCfgParser, itf::FlakeConfig};
 use lazy_static::lazy_static;
+use serde::Deserialize;
+use serde_yaml::Value;
 use std::{
     env, fs,
     io::Error,
@@ -12,13 +17,31 @@ use std::{
 pub mod cfg_v1;
 pub mod cfg_v2;
 pub mod cfgparse;
+pub mod conditions;
+pub mod environment;
 pub mod itf;
+pub mod network;
 pub mod pilots;
+pub mod placeholders;
+pub mod resources;
+pub mod setup;
 
 lazy_static! {
     /// Flake directory for all the app configurations and other shared data
     pub static ref FLAKE_DIR: PathBuf = PathBuf::from("/usr/share/flakes");
 
+    /// Flake directory of a user, relative to `$XDG_DATA_HOME`
+    pub static ref USER_FLAKE_DIR: PathBuf = PathBuf::from("flakes");
+
+    /// Directory for the configuration overrides of a user, relative to `$XDG_CONFIG_HOME`
+    pub static ref USER_CONFIG_DIR: PathBuf = PathBuf::from("flakes");
+
+    /// System-wide allow-list of the keys users may override
+    pub static ref USER_OVERRIDES: PathBuf = PathBuf::from("/etc/flakes/overrides.yaml");
+
+    /// Directory for the application links of a user, relative to the home directory
+    pub static ref USER_BIN_DIR: PathBuf = PathBuf::from(".local/bin");
+
     /// Default OCI container directory for storage etc
     pub static ref DEFAULT_CONTAINER_DIR: PathBuf = PathBuf::from("/var/lib/containers");
 
@@ -103,39 +126,85 @@ pub fn get() -> FlakeConfig {
     CFG.to_owned()
 }
 
-pub fn load_from_path(path: &Path) -> Result<FlakeConfig, Error> {
-    // Get app configuration
+#[derive(Deserialize, Default)]
+struct UserOverrides {
+    allow: Option<Vec<String>>,
+}
 
-    let cfg_d_paths: Vec<PathBuf> = std::fs::read_dir(path.with_extension("d"))
+/// Get dotted configuration keys users may override, e.g. `engine.args`.
+/// Nothing is allowed, unless the system-wide allow-list says so.
+pub fn user_overrides_allowed() -> Vec<String> {
+    fs::read_to_string(USER_OVERRIDES.as_path())
         .ok()
+        .and_then(|data| serde_yaml::from_str::<UserOverrides>(&data).ok())
+        .unwrap_or_default()
+        .allow
+        .unwrap_or_default()
+}
+
+/// Get sorted overlay files of a `.d` directory
+fn overlay_paths(dir: &Path) -> Vec<PathBuf> {
+    let mut paths: Vec<PathBuf> = fs::read_dir(dir)
         .into_iter()
         .flatten()
-        .filter_map(|entry| -> Option<PathBuf> {
-            if let Ok(entry) = entry {
-                if entry.path().is_file() {
-                    if let Some(file_name) = entry.file_name().to_str() {
-                        return Some(PathBuf::from(file_name.to_string()));
-                    }
-                }
-            }
-            None
-        })
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.is_file())
         .collect();
+    paths.sort();
+    paths
+}
 
-    match FlakeCfgParser::new(path.with_extension("yaml"), cfg_d_paths)?.parse() {
+/// Append a suffix to the path of a flake, which keeps
+/// any dots in the flake name, unlike `with_extension`
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(suffix);
+    PathBuf::from(path)
+}
+
+pub(crate) fn parser_for(path: &Path, with_user: bool) -> Result<FlakeCfgParser, Error> {
+    let parser = FlakeCfgParser::new(with_suffix(path, ".yaml"), overlay_paths(&with_suffix(path, ".d")))?;
+    if !with_user {
+        return Ok(parser);
+    }
+
+    let name = flake_name(path)?;
+    Ok(parser.with_user_overlay(overlay_paths(&user_config_dir().join(format!("{name}.d"))), user_overrides_allowed()))
+}
+
+pub(crate) fn parse_from_path(path: &Path, with_user: bool) -> Result<FlakeConfig, Error> {
+    match parser_for(path, with_user)?.parse() {
         Some(cfg) => Ok(cfg),
         None => Err(Error::new(std::io::ErrorKind::NotFound, "Unable to read configuration")),
     }
 }
 
+/// Load the configuration of a flake at `path` (without extension).
+///
+/// The system `.d` overlays are merged first, then the allowed parts
+/// of the user overlays in `~/.config/flakes/<flake>.d`.
+pub fn load_from_path(path: &Path) -> Result<FlakeConfig, Error> {
+    parse_from_path(path, true)
+}
+
+/// Load the merged raw configuration of a flake at `path` (without extension)
+/// together with the dotted keys provided by the user overlays
+pub fn load_raw_from_path(path: &Path) -> Result<(Value, Vec<String>), Error> {
+    parser_for(path, true)?.get_raw_config()
+}
+
+/// Load the configuration of a flake in the given (fake) root.
+/// User overlays are only applied to the flakes of the running system.
 pub fn load_from_target(root: Option<&Path>, app_p: &Path) -> Result<FlakeConfig, Error> {
     let app_ps = app_p.file_name().unwrap().to_str().unwrap().to_string();
-    load_from_path(&flake_dir_from(root).join(app_ps))
+    parse_from_path(&flake_dir_from(root).join(app_ps), root.is_none())
 }
 
-/// Load config for the host app path
+/// Load config for the host app path.
+///
+/// Flakes of the current user take precedence over the system ones.
 pub fn load() -> Result<FlakeConfig, Error> {
-    //pub fn load_for_app() {
-    let app_p = app_path().unwrap();
-    load_from_target(None, &app_p)
+    let name = flake_name(&app_path()?)?;
+    FlakeRegistry::lookup(&name).load(&name)
 }
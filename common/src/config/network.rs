@@ -0,0 +1,100 @@
+use super::itf::{FlakeCfgNetwork, NetworkMode, Protocol, PublishedPort};
+use serde::Deserialize;
+use std::fmt::{self, Display, Formatter};
+
+/// Network section of the runtime, shared by all config versions.
+/// Either just the mode, or the mode with published ports.
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+pub(crate) enum CfgNetwork {
+    Mode(String),
+    Full(CfgNetworkFull),
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct CfgNetworkFull {
+    mode: Option<String>,
+    #[serde(default)]
+    publish: Vec<String>,
+}
+
+impl TryFrom<CfgNetwork> for FlakeCfgNetwork {
+    type Error = String;
+
+    fn try_from(value: CfgNetwork) -> Result<Self, Self::Error> {
+        let (mode, publish) = match value {
+            CfgNetwork::Mode(mode) => (Some(mode), vec![]),
+            CfgNetwork::Full(full) => (full.mode, full.publish),
+        };
+
+        let mode = match mode {
+            Some(mode) => parse_mode(&mode)?,
+            None => NetworkMode::Default,
+        };
+        let publish = publish.iter().map(|p| parse_port(p)).collect::<Result<Vec<_>, _>>()?;
+        if !publish.is_empty() && matches!(mode, NetworkMode::None | NetworkMode::Host) {
+            return Err(format!("Ports can not be published with network mode {mode}"));
+        }
+
+        Ok(FlakeCfgNetwork { mode, publish })
+    }
+}
+
+/// Parse the network mode: `none`, `host`, `private` or `bridge:<name>`
+fn parse_mode(mode: &str) -> Result<NetworkMode, String> {
+    match mode {
+        "none" => Ok(NetworkMode::None),
+        "host" => Ok(NetworkMode::Host),
+        "private" => Ok(NetworkMode::Private),
+        _ => match mode.strip_prefix("bridge:") {
+            Some(name) if !name.is_empty() => Ok(NetworkMode::Bridge(name.to_string())),
+            _ => Err(format!("Unknown network mode \"{mode}\", use none, host, private or bridge:<name>")),
+        },
+    }
+}
+
+/// Parse a published port: `[host_ip:]host_port:guest_port[/tcp|udp]`
+fn parse_port(port: &str) -> Result<PublishedPort, String> {
+    let invalid = || format!("Invalid published port \"{port}\", use [host_ip:]host_port:guest_port[/tcp|udp]");
+
+    let (ports, protocol) = match port.split_once('/') {
+        Some((ports, "tcp")) => (ports, Protocol::Tcp),
+        Some((ports, "udp")) => (ports, Protocol::Udp),
+        Some(_) => return Err(invalid()),
+        None => (port, Protocol::Tcp),
+    };
+
+    let mut parts = ports.rsplitn(3, ':');
+    let guest_port = parts.next().and_then(|p| p.parse::<u16>().ok()).ok_or_else(invalid)?;
+    let host_port = parts.next().and_then(|p| p.parse::<u16>().ok()).ok_or_else(invalid)?;
+    let host_ip = parts.next().map(|ip| ip.trim_start_matches('[').trim_end_matches(']').to_string());
+    if host_ip.as_deref() == Some("") {
+        return Err(invalid());
+    }
+
+    Ok(PublishedPort { host_ip, host_port, guest_port, protocol })
+}
+
+impl Display for NetworkMode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            NetworkMode::Default => write!(f, "default"),
+            NetworkMode::None => write!(f, "none"),
+            NetworkMode::Host => write!(f, "host"),
+            NetworkMode::Private => write!(f, "private"),
+            NetworkMode::Bridge(name) => write!(f, "bridge:{name}"),
+        }
+    }
+}
+
+impl Display for PublishedPort {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match &self.host_ip {
+            Some(ip) if ip.contains(':') => write!(f, "[{ip}]:")?,
+            Some(ip) => write!(f, "{ip}:")?,
+            None => {}
+        }
+        write!(f, "{}:{}/{}", self.host_port, self.guest_port, self.protocol.as_str())
+    }
+}
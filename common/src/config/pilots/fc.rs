@@ -1,6 +1,6 @@
-use std::path::PathBuf;
+use std::{net::Ipv4Addr, path::PathBuf};
 
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use serde_yaml::Value;
 
 /// Additional segment of Firecracker configuration for parameters
@@ -14,6 +14,14 @@ pub struct FirecrackerRuntimeParams {
     rootfs_image_path: String,
     kernel_image_path: String,
     initrd_path: String,
+    smt: Option<bool>,
+    cpu_template: Option<CpuTemplate>,
+    balloon: Option<Balloon>,
+    entropy: Option<Entropy>,
+    drive_rate_limiter: Option<RateLimiter>,
+    network_rate_limiter: Option<NetworkRateLimiter>,
+    mmds: Option<Mmds>,
+    template: Option<PathBuf>,
 }
 
 impl FirecrackerRuntimeParams {
@@ -48,6 +56,212 @@ impl FirecrackerRuntimeParams {
     pub fn initrd_path(&self) -> PathBuf {
         PathBuf::from(self.initrd_path.to_owned())
     }
+
+    pub fn smt(&self) -> Option<bool> {
+        self.smt
+    }
+
+    pub fn cpu_template(&self) -> Option<CpuTemplate> {
+        self.cpu_template
+    }
+
+    pub fn balloon(&self) -> Option<&Balloon> {
+        self.balloon.as_ref()
+    }
+
+    pub fn entropy(&self) -> Option<&Entropy> {
+        self.entropy.as_ref()
+    }
+
+    pub fn drive_rate_limiter(&self) -> Option<&RateLimiter> {
+        self.drive_rate_limiter.as_ref()
+    }
+
+    pub fn network_rate_limiter(&self) -> Option<&NetworkRateLimiter> {
+        self.network_rate_limiter.as_ref()
+    }
+
+    pub fn mmds(&self) -> Option<&Mmds> {
+        self.mmds.as_ref()
+    }
+
+    /// Firecracker JSON template replacing the default one
+    pub fn template(&self) -> Option<&PathBuf> {
+        self.template.as_ref()
+    }
+
+    /// Check the machine options beyond their types
+    pub fn validate(&self) -> Result<(), String> {
+        validate_machine(
+            self.mem_size_mib.map(u64::from),
+            self.balloon(),
+            self.entropy(),
+            self.drive_rate_limiter(),
+            self.network_rate_limiter(),
+        )
+    }
+}
+
+/// Check the machine options of a firecracker VM with the given memory size
+pub fn validate_machine(
+    mem_size_mib: Option<u64>, balloon: Option<&Balloon>, entropy: Option<&Entropy>, drive_rate_limiter: Option<&RateLimiter>,
+    network_rate_limiter: Option<&NetworkRateLimiter>,
+) -> Result<(), String> {
+    if let Some(balloon) = balloon {
+        if let Some(mem_size_mib) = mem_size_mib {
+            if u64::from(balloon.amount_mib) >= mem_size_mib {
+                return Err(format!("Balloon of {} MiB does not fit into {} MiB of memory", balloon.amount_mib, mem_size_mib));
+            }
+        }
+    }
+    if let Some(rate_limiter) = entropy.and_then(|e| e.rate_limiter.as_ref()) {
+        rate_limiter.validate("entropy")?;
+    }
+    if let Some(rate_limiter) = drive_rate_limiter {
+        rate_limiter.validate("drive")?;
+    }
+    if let Some(network) = network_rate_limiter {
+        for rate_limiter in network.rx.iter().chain(network.tx.iter()) {
+            rate_limiter.validate("network")?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Static CPU template, masking CPU features of the host
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum CpuTemplate {
+    C3,
+    T2,
+    T2S,
+    #[serde(rename = "T2CL")]
+    T2Cl,
+    T2A,
+    V1N1,
+    None,
+}
+
+/// Balloon device to reclaim guest memory
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Balloon {
+    /// Target size of the balloon
+    amount_mib: u32,
+
+    /// Deflate the balloon when the guest runs out of memory
+    #[serde(default)]
+    deflate_on_oom: bool,
+
+    /// Interval of the balloon statistics, 0 disables them
+    #[serde(default)]
+    stats_polling_interval_s: u32,
+}
+
+impl Balloon {
+    pub fn amount_mib(&self) -> u32 {
+        self.amount_mib
+    }
+
+    pub fn deflate_on_oom(&self) -> bool {
+        self.deflate_on_oom
+    }
+}
+
+/// Virtio entropy device, feeding the guest from the host RNG
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Entropy {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rate_limiter: Option<RateLimiter>,
+}
+
+/// Rate limiter of a device, by bandwidth and by operations
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct RateLimiter {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    bandwidth: Option<TokenBucket>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ops: Option<TokenBucket>,
+}
+
+impl RateLimiter {
+    /// Check the buckets of the rate limiter of the given device
+    pub fn validate(&self, device: &str) -> Result<(), String> {
+        if self.bandwidth.is_none() && self.ops.is_none() {
+            return Err(format!("The {device} rate limiter needs a bandwidth or ops bucket"));
+        }
+        for bucket in self.bandwidth.iter().chain(self.ops.iter()) {
+            if bucket.size == 0 || bucket.refill_time == 0 {
+                return Err(format!("The {device} rate limiter needs a size and refill_time above 0"));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Token bucket of a rate limiter
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct TokenBucket {
+    /// Size of the bucket in bytes or operations
+    size: u64,
+
+    /// Initial burst on top of the size, not refilled
+    #[serde(skip_serializing_if = "Option::is_none")]
+    one_time_burst: Option<u64>,
+
+    /// Time in milliseconds to refill the bucket
+    refill_time: u64,
+}
+
+/// Rate limiters of the network interface
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct NetworkRateLimiter {
+    rx: Option<RateLimiter>,
+    tx: Option<RateLimiter>,
+}
+
+impl NetworkRateLimiter {
+    pub fn rx(&self) -> Option<&RateLimiter> {
+        self.rx.as_ref()
+    }
+
+    pub fn tx(&self) -> Option<&RateLimiter> {
+        self.tx.as_ref()
+    }
+}
+
+/// MMDS data store of the VM, reachable through the network interface
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Mmds {
+    #[serde(default)]
+    version: MmdsVersion,
+
+    /// Address of the data store in the guest, firecracker default: 169.254.169.254
+    ipv4_address: Option<Ipv4Addr>,
+}
+
+impl Mmds {
+    pub fn version(&self) -> MmdsVersion {
+        self.version
+    }
+
+    pub fn ipv4_address(&self) -> Option<Ipv4Addr> {
+        self.ipv4_address
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub enum MmdsVersion {
+    V1,
+    #[default]
+    V2,
 }
 
 impl From<Value> for FirecrackerRuntimeParams {
@@ -63,6 +277,14 @@ impl From<Value> for FirecrackerRuntimeParams {
                 rootfs_image_path: "".to_string(),
                 kernel_image_path: "".to_string(),
                 initrd_path: "".to_string(),
+                smt: None,
+                cpu_template: None,
+                balloon: None,
+                entropy: None,
+                drive_rate_limiter: None,
+                network_rate_limiter: None,
+                mmds: None,
+                template: None,
             },
         }
     }
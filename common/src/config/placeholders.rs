@@ -0,0 +1,85 @@
+use nix::unistd::{getgid, getuid, User};
+use std::{
+    collections::HashMap,
+    env,
+    io::{Error, ErrorKind},
+};
+
+/// Placeholders, which can be used as `${NAME}` in engine args,
+/// path map exports and bundle paths, if enabled by `runtime.expand`
+pub const PLACEHOLDERS: [&str; 8] = ["HOME", "USER", "UID", "GID", "PWD", "XDG_RUNTIME_DIR", "FLAKE_NAME", "INSTANCE"];
+
+/// Values of all placeholders, resolved at launch time
+#[derive(Debug, Clone, Default)]
+pub struct Placeholders {
+    values: HashMap<String, String>,
+}
+
+impl Placeholders {
+    /// Resolve placeholders for the calling user.
+    ///
+    /// The `instance` is the name of the running flake instance,
+    /// which is the flake name followed by `@NAME` if given at the command line.
+    pub fn resolve(flake_name: &str, instance: &str) -> Self {
+        let uid = getuid();
+        let user = User::from_uid(uid).ok().flatten();
+
+        let mut p = Placeholders::default();
+        p.set("HOME", env::var("HOME").ok().or_else(|| user.as_ref().map(|u| u.dir.to_string_lossy().to_string())).unwrap_or_default());
+        p.set("USER", user.as_ref().map(|u| u.name.to_owned()).or_else(|| env::var("USER").ok()).unwrap_or_default());
+        p.set("UID", uid.to_string());
+        p.set("GID", getgid().to_string());
+        p.set("PWD", env::current_dir().map(|d| d.to_string_lossy().to_string()).unwrap_or_default());
+        p.set("XDG_RUNTIME_DIR", env::var("XDG_RUNTIME_DIR").unwrap_or_else(|_| format!("/run/user/{uid}")));
+        p.set("FLAKE_NAME", flake_name);
+        p.set("INSTANCE", instance);
+        p
+    }
+
+    /// Set value of a placeholder
+    pub fn set(&mut self, name: &str, value: impl Into<String>) {
+        self.values.insert(name.to_string(), value.into());
+    }
+
+    /// Expand all placeholders in the input.
+    ///
+    /// `$$` is an escaped `$`, any other `$` not followed by `{` is taken literally.
+    pub fn expand(&self, input: &str) -> Result<String, Error> {
+        scan(input, |name| self.values.get(name).cloned())
+    }
+}
+
+/// Check that all placeholders in the input are well-formed and known
+pub fn validate(input: &str) -> Result<(), Error> {
+    scan(input, |name| PLACEHOLDERS.contains(&name).then(String::new)).map(|_| ())
+}
+
+fn scan(input: &str, lookup: impl Fn(&str) -> Option<String>) -> Result<String, Error> {
+    let mut out = String::with_capacity(input.len());
+    let mut rest = input;
+
+    while let Some(pos) = rest.find('$') {
+        out.push_str(&rest[..pos]);
+        rest = &rest[pos + 1..];
+
+        if let Some(tail) = rest.strip_prefix('$') {
+            out.push('$');
+            rest = tail;
+        } else if let Some(tail) = rest.strip_prefix('{') {
+            let end = tail
+                .find('}')
+                .ok_or_else(|| Error::new(ErrorKind::InvalidData, format!("Unterminated placeholder in \"{input}\"")))?;
+            let name = &tail[..end];
+            out.push_str(
+                &lookup(name)
+                    .ok_or_else(|| Error::new(ErrorKind::InvalidData, format!("Unknown placeholder ${{{name}}} in \"{input}\"")))?,
+            );
+            rest = &tail[end + 1..];
+        } else {
+            out.push('$');
+        }
+    }
+    out.push_str(rest);
+
+    Ok(out)
+}
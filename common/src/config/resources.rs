@@ -0,0 +1,79 @@
+use super::itf::FlakeCfgResources;
+use serde::Deserialize;
+
+/// Resources section of the runtime, shared by all config versions
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct CfgResources {
+    cpus: Option<f64>,
+    cpu_shares: Option<u64>,
+    memory: Option<CfgSize>,
+    pids: Option<u64>,
+    io_weight: Option<u16>,
+}
+
+/// Size in bytes, or a string with a binary unit like `512M` or `2GiB`
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+enum CfgSize {
+    Bytes(u64),
+    Unit(String),
+}
+
+impl TryFrom<CfgResources> for FlakeCfgResources {
+    type Error = String;
+
+    fn try_from(value: CfgResources) -> Result<Self, Self::Error> {
+        if let Some(cpus) = value.cpus.filter(|c| !(c.is_finite() && *c > 0.0)) {
+            return Err(format!("CPU quota must be a positive number of CPUs, got {cpus}"));
+        }
+        if let Some(shares) = value.cpu_shares.filter(|s| !(2..=262144).contains(s)) {
+            return Err(format!("CPU shares must be within 2..262144, got {shares}"));
+        }
+        if value.pids == Some(0) {
+            return Err("Pids limit must be at least 1".to_string());
+        }
+        if let Some(weight) = value.io_weight.filter(|w| !(10..=1000).contains(w)) {
+            return Err(format!("I/O weight must be within 10..1000, got {weight}"));
+        }
+        let memory = match value.memory {
+            Some(CfgSize::Bytes(bytes)) => Some(bytes),
+            Some(CfgSize::Unit(size)) => Some(parse_size(&size).ok_or_else(|| format!("Invalid memory size \"{size}\""))?),
+            None => None,
+        };
+        if memory == Some(0) {
+            return Err("Memory limit must not be zero".to_string());
+        }
+
+        Ok(FlakeCfgResources { cpus: value.cpus, cpu_shares: value.cpu_shares, memory, pids: value.pids, io_weight: value.io_weight })
+    }
+}
+
+/// Parse a size with an optional binary unit: `K`, `M`, `G` or `T`,
+/// optionally followed by `iB` or `B`, e.g. `512M`, `2GiB`, `1g`
+pub fn parse_size(size: &str) -> Option<u64> {
+    let size = size.trim();
+    let split = size.find(|c: char| !c.is_ascii_digit()).unwrap_or(size.len());
+    let (number, unit) = size.split_at(split);
+    let shift = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "b" => 0,
+        "k" | "kb" | "kib" => 10,
+        "m" | "mb" | "mib" => 20,
+        "g" | "gb" | "gib" => 30,
+        "t" | "tb" | "tib" => 40,
+        _ => return None,
+    };
+    number.parse::<u64>().ok()?.checked_mul(1 << shift)
+}
+
+impl FlakeCfgResources {
+    /// Returns true if no limit is set
+    pub fn is_empty(&self) -> bool {
+        self == &FlakeCfgResources::default()
+    }
+
+    /// Get the CPU shares as cgroup v2 CPU weight (1..10000)
+    pub fn cpu_weight(&self) -> Option<u64> {
+        self.cpu_shares.map(|shares| 1 + ((shares - 2) * 9999) / 262142)
+    }
+}
@@ -0,0 +1,397 @@
+use super::itf::{AccessMode, BindMount, FlakeCfgSetup, HostSocket, HostTrust};
+use nix::unistd::getuid;
+use serde::Deserialize;
+use serde_yaml::Value;
+use std::{
+    env,
+    io::{Error, ErrorKind},
+    path::{Component, Path, PathBuf},
+};
+
+/// Setup section, shared by all config versions
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+struct CfgSetup {
+    home: Option<CfgAccess>,
+    cwd: Option<CfgAccess>,
+    mounts: Option<Vec<CfgMount>>,
+    sockets: Option<Vec<CfgSocket>>,
+    dbus: Option<bool>,
+    devices: Option<Vec<String>>,
+    host_trust: Option<CfgHostTrust>,
+}
+
+/// Host trust, either all of it or a list of kinds
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+enum CfgHostTrust {
+    All(bool),
+    Some(Vec<CfgTrust>),
+}
+
+#[derive(Deserialize, Debug, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+enum CfgTrust {
+    Certificates,
+    Resolver,
+    Hosts,
+    Proxy,
+}
+
+impl From<CfgTrust> for HostTrust {
+    fn from(value: CfgTrust) -> Self {
+        match value {
+            CfgTrust::Certificates => HostTrust::Certificates,
+            CfgTrust::Resolver => HostTrust::Resolver,
+            CfgTrust::Hosts => HostTrust::Hosts,
+            CfgTrust::Proxy => HostTrust::Proxy,
+        }
+    }
+}
+
+/// Known locations of the CA bundle. The first one found on the host
+/// is injected to all of them, except the last two which are host-only.
+const CA_BUNDLES: [&str; 5] = [
+    "/etc/ssl/certs/ca-certificates.crt",
+    "/etc/pki/tls/certs/ca-bundle.crt",
+    "/var/lib/ca-certificates/ca-bundle.pem",
+    "/etc/ssl/ca-bundle.pem",
+    "/etc/ssl/cert.pem",
+];
+
+/// Resolver configuration with the upstream servers of systemd-resolved,
+/// preferred over its stub resolver which is not reachable from an instance
+const RESOLVED_CONF: &str = "/run/systemd/resolve/resolv.conf";
+
+/// Proxy variables, also taken in upper case
+const PROXY_VARS: [&str; 5] = ["http_proxy", "https_proxy", "ftp_proxy", "all_proxy", "no_proxy"];
+
+#[derive(Deserialize, Debug, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum CfgAccess {
+    Ro,
+    Rw,
+}
+
+impl From<CfgAccess> for AccessMode {
+    fn from(value: CfgAccess) -> Self {
+        match value {
+            CfgAccess::Ro => AccessMode::ReadOnly,
+            CfgAccess::Rw => AccessMode::ReadWrite,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct CfgMount {
+    source: String,
+    target: Option<String>,
+    mode: Option<CfgAccess>,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+enum CfgSocket {
+    Wayland,
+    X11,
+    PulseAudio,
+    PipeWire,
+}
+
+impl From<CfgSocket> for HostSocket {
+    fn from(value: CfgSocket) -> Self {
+        match value {
+            CfgSocket::Wayland => HostSocket::Wayland,
+            CfgSocket::X11 => HostSocket::X11,
+            CfgSocket::PulseAudio => HostSocket::PulseAudio,
+            CfgSocket::PipeWire => HostSocket::PipeWire,
+        }
+    }
+}
+
+fn absolute(path: &str, what: &str) -> Result<PathBuf, Error> {
+    let path = PathBuf::from(path);
+    if !path.is_absolute() {
+        return Err(Error::new(ErrorKind::InvalidData, format!("Setup {what} {} must be an absolute path", path.display())));
+    }
+    Ok(path)
+}
+
+/// Parse the `setup` section of a raw configuration
+pub fn parse_setup(cfg: &Value) -> Result<FlakeCfgSetup, Error> {
+    let spec = match cfg.get("setup") {
+        None | Some(Value::Null) => CfgSetup::default(),
+        Some(setup) => serde_yaml::from_value::<CfgSetup>(setup.to_owned())
+            .map_err(|err| Error::new(ErrorKind::InvalidData, format!("Setup parse error: {}", err)))?,
+    };
+
+    let mut mounts = vec![];
+    for m in spec.mounts.unwrap_or_default() {
+        let source = absolute(&m.source, "mount")?;
+        let target = match m.target {
+            Some(target) => absolute(&target, "mount")?,
+            None => source.to_owned(),
+        };
+        mounts.push(BindMount { source, target, mode: m.mode.map(AccessMode::from).unwrap_or_default() });
+    }
+
+    Ok(FlakeCfgSetup {
+        home: spec.home.map(AccessMode::from),
+        cwd: spec.cwd.map(AccessMode::from),
+        mounts,
+        sockets: spec.sockets.unwrap_or_default().into_iter().map(HostSocket::from).collect(),
+        dbus: spec.dbus.unwrap_or_default(),
+        devices: spec.devices.unwrap_or_default().iter().map(|d| absolute(d, "device")).collect::<Result<_, _>>()?,
+        host_trust: match spec.host_trust {
+            Some(CfgHostTrust::All(true)) => HostTrust::ALL.to_vec(),
+            Some(CfgHostTrust::Some(trust)) => trust.into_iter().map(HostTrust::from).collect(),
+            Some(CfgHostTrust::All(false)) | None => vec![],
+        },
+    })
+}
+
+/// Host integration of an instance, resolved from the setup section at launch time
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HostIntegration {
+    /// Bind mount of a host path
+    Mount(BindMount),
+
+    /// Device node of the host
+    Device(PathBuf),
+
+    /// Environment variable to set inside the instance
+    Env(String, String),
+
+    /// Working directory of the app inside the instance
+    Workdir(PathBuf),
+}
+
+impl HostIntegration {
+    fn mount(path: &Path, mode: AccessMode) -> Self {
+        HostIntegration::Mount(BindMount { source: path.to_owned(), target: path.to_owned(), mode })
+    }
+
+    fn env(name: &str, value: impl Into<String>) -> Self {
+        HostIntegration::Env(name.to_string(), value.into())
+    }
+}
+
+fn runtime_dir() -> PathBuf {
+    env::var_os("XDG_RUNTIME_DIR").map(PathBuf::from).unwrap_or_else(|| PathBuf::from(format!("/run/user/{}", getuid())))
+}
+
+/// Resolve a socket of a desktop service, returns None if it is not available on the host
+fn socket_integrations(socket: HostSocket) -> Option<Vec<HostIntegration>> {
+    let rt_dir = runtime_dir();
+    match socket {
+        HostSocket::Wayland => {
+            let display = env::var("WAYLAND_DISPLAY").unwrap_or_else(|_| "wayland-0".to_string());
+            let path = rt_dir.join(&display);
+            path.exists().then(|| {
+                vec![
+                    HostIntegration::mount(&path, AccessMode::ReadWrite),
+                    HostIntegration::env("XDG_RUNTIME_DIR", rt_dir.to_string_lossy()),
+                    HostIntegration::env("WAYLAND_DISPLAY", display),
+                ]
+            })
+        }
+        HostSocket::X11 => {
+            let display = env::var("DISPLAY").ok()?;
+            let path = Path::new("/tmp/.X11-unix");
+            let mut integrations = vec![HostIntegration::mount(path, AccessMode::ReadOnly), HostIntegration::env("DISPLAY", display)];
+            if let Some(xauth) = env::var_os("XAUTHORITY").map(PathBuf::from).filter(|p| p.exists()) {
+                integrations.push(HostIntegration::mount(&xauth, AccessMode::ReadOnly));
+                integrations.push(HostIntegration::env("XAUTHORITY", xauth.to_string_lossy()));
+            }
+            path.exists().then_some(integrations)
+        }
+        HostSocket::PulseAudio => {
+            let path = rt_dir.join("pulse/native");
+            path.exists().then(|| {
+                vec![
+                    HostIntegration::mount(&path, AccessMode::ReadWrite),
+                    HostIntegration::env("PULSE_SERVER", format!("unix:{}", path.display())),
+                ]
+            })
+        }
+        HostSocket::PipeWire => {
+            let path = rt_dir.join("pipewire-0");
+            path.exists().then(|| {
+                vec![
+                    HostIntegration::mount(&path, AccessMode::ReadWrite),
+                    HostIntegration::env("PIPEWIRE_REMOTE", path.to_string_lossy()),
+                ]
+            })
+        }
+    }
+}
+
+/// Resolve the D-Bus session bus, returns None if it is not available on the host
+fn dbus_integrations() -> Option<Vec<HostIntegration>> {
+    let path = env::var("DBUS_SESSION_BUS_ADDRESS")
+        .ok()
+        .and_then(|addr| addr.split(',').find_map(|p| p.strip_prefix("unix:path=").map(PathBuf::from)))
+        .unwrap_or_else(|| runtime_dir().join("bus"));
+
+    path.exists().then(|| {
+        vec![
+            HostIntegration::mount(&path, AccessMode::ReadWrite),
+            HostIntegration::env("DBUS_SESSION_BUS_ADDRESS", format!("unix:path={}", path.display())),
+        ]
+    })
+}
+
+/// Resolve trust data of the host, returns None if it is not available on the host
+fn trust_integrations(trust: HostTrust) -> Option<Vec<HostIntegration>> {
+    let file = |source: &Path, target: &str| {
+        HostIntegration::Mount(BindMount { source: source.to_owned(), target: PathBuf::from(target), mode: AccessMode::ReadOnly })
+    };
+
+    match trust {
+        HostTrust::Certificates => {
+            let bundle = CA_BUNDLES.iter().map(Path::new).find(|p| p.exists())?;
+            let mut integrations: Vec<HostIntegration> = CA_BUNDLES[..3].iter().map(|target| file(bundle, target)).collect();
+            integrations.push(HostIntegration::env("SSL_CERT_FILE", CA_BUNDLES[0]));
+            Some(integrations)
+        }
+        HostTrust::Resolver => {
+            let conf = [RESOLVED_CONF, "/etc/resolv.conf"].iter().map(Path::new).find(|p| p.exists())?;
+            Some(vec![file(conf, "/etc/resolv.conf")])
+        }
+        HostTrust::Hosts => {
+            let hosts = Path::new("/etc/hosts");
+            hosts.exists().then(|| vec![file(hosts, "/etc/hosts")])
+        }
+        HostTrust::Proxy => {
+            let integrations: Vec<HostIntegration> = PROXY_VARS
+                .iter()
+                .flat_map(|name| [name.to_string(), name.to_uppercase()])
+                .filter_map(|name| env::var(&name).ok().map(|value| HostIntegration::env(&name, value)))
+                .collect();
+            (!integrations.is_empty()).then_some(integrations)
+        }
+    }
+}
+
+impl FlakeCfgSetup {
+    /// Resolve the trust data of the host to inject into the instance.
+    ///
+    /// Files are returned as read-only mounts, the proxy as environment.
+    /// Trust data which is not available on the host is skipped with a warning.
+    pub fn trust_integrations(&self) -> Vec<HostIntegration> {
+        let mut integrations: Vec<HostIntegration> = vec![];
+        for trust in &self.host_trust {
+            match trust_integrations(*trust) {
+                Some(trust) => integrations.extend(trust),
+                None => log::warn!("Host trust data {:?} is not available on this host", trust),
+            }
+        }
+
+        integrations
+    }
+
+    /// Resolve the host integrations for the calling user.
+    ///
+    /// Sockets and the session bus which are not available
+    /// on the host are skipped with a warning.
+    pub fn integrations(&self) -> Result<Vec<HostIntegration>, Error> {
+        let mut integrations: Vec<HostIntegration> = vec![];
+
+        if let Some(mode) = self.home {
+            let home = home::home_dir().ok_or_else(|| Error::new(ErrorKind::NotFound, "Home directory not found"))?;
+            integrations.push(HostIntegration::mount(&home, mode));
+            integrations.push(HostIntegration::env("HOME", home.to_string_lossy()));
+        }
+
+        if let Some(mode) = self.cwd {
+            let cwd = env::current_dir()?;
+            integrations.push(HostIntegration::mount(&cwd, mode));
+            integrations.push(HostIntegration::Workdir(cwd));
+        }
+
+        for mount in &self.mounts {
+            if !mount.source.exists() {
+                return Err(Error::new(ErrorKind::NotFound, format!("Setup mount {} does not exist", mount.source.display())));
+            }
+            integrations.push(HostIntegration::Mount(mount.to_owned()));
+        }
+
+        for socket in &self.sockets {
+            match socket_integrations(*socket) {
+                Some(socket) => integrations.extend(socket),
+                None => log::warn!("{:?} socket is not available on this host", socket),
+            }
+        }
+
+        if self.dbus {
+            match dbus_integrations() {
+                Some(dbus) => integrations.extend(dbus),
+                None => log::warn!("D-Bus session bus is not available on this host"),
+            }
+        }
+
+        integrations.extend(self.devices.iter().cloned().map(HostIntegration::Device));
+        integrations.extend(self.trust_integrations());
+
+        Ok(integrations)
+    }
+}
+
+/// Make a path absolute to `cwd` and resolve `.` and `..` lexically
+fn normalize(path: &Path, cwd: &Path) -> PathBuf {
+    let mut out = PathBuf::new();
+    for c in cwd.join(path).components() {
+        match c {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                out.pop();
+            }
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// Translate caller arguments, which refer to existing host files or directories.
+///
+/// Each such path is mounted at the same absolute path: directories as is,
+/// files by themselves if read-only or by their parent directory if read-write,
+/// so the app can replace them. Relative paths are rewritten to absolute ones
+/// and the working directory is set to `cwd`. Options as `--out=FILE` are
+/// translated by their value.
+///
+/// Returns the host integrations and the rewritten arguments.
+pub fn translate_host_paths(args: &[String], cwd: &Path, mode: AccessMode) -> (Vec<HostIntegration>, Vec<String>) {
+    let mut mounts: Vec<PathBuf> = vec![];
+    let mut out: Vec<String> = vec![];
+
+    for arg in args {
+        let (opt, value) = match arg.split_once('=') {
+            Some((opt, value)) if opt.starts_with('-') => (format!("{opt}="), value),
+            _ => (String::new(), arg.as_str()),
+        };
+
+        let path = normalize(Path::new(value), cwd);
+        if value.is_empty() || value.starts_with('-') || !path.exists() {
+            out.push(arg.to_owned());
+            continue;
+        }
+
+        let mount = match (path.is_dir(), mode) {
+            (false, AccessMode::ReadWrite) => path.parent().map(Path::to_path_buf).unwrap_or_else(|| path.to_owned()),
+            _ => path.to_owned(),
+        };
+        if mount != Path::new("/") && !mounts.iter().any(|m| mount.starts_with(m)) {
+            mounts.retain(|m| !m.starts_with(&mount));
+            mounts.push(mount);
+        }
+
+        out.push(if Path::new(value).is_absolute() { arg.to_owned() } else { format!("{opt}{}", path.display()) });
+    }
+
+    let mut integrations: Vec<HostIntegration> = mounts.iter().map(|m| HostIntegration::mount(m, mode)).collect();
+    integrations.push(HostIntegration::Workdir(cwd.to_owned()));
+
+    (integrations, out)
+}
@@ -2,4 +2,7 @@ pub mod config;
 pub mod logger;
 pub mod user;
 pub mod paths;
+pub mod registry;
+pub mod vmlog;
+pub mod vmnet;
 pub mod yamls;
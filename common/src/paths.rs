@@ -1,6 +1,6 @@
 use std::{path::{Path, PathBuf}, borrow::Cow, ffi::OsStr};
 
-use crate::config::FLAKE_DIR;
+use crate::config::{FLAKE_DIR, USER_BIN_DIR, USER_CONFIG_DIR, USER_FLAKE_DIR};
 
 pub trait PathExt {
     fn join_ignore_abs(&self, p: impl AsRef<Path>) -> PathBuf;
@@ -29,6 +29,29 @@ pub fn flake_dir_from(root: Option<impl AsRef<Path>>) -> PathBuf {
     }.path_on_disk().to_path_buf()
 }
 
+/// Flake directory of the current user, `$XDG_DATA_HOME/flakes` or `~/.local/share/flakes`
+pub fn user_flake_dir() -> PathBuf {
+    std::env::var_os("XDG_DATA_HOME")
+        .map(PathBuf::from)
+        .filter(|p| p.is_absolute())
+        .unwrap_or_else(|| home::home_dir().unwrap_or_default().join(".local/share"))
+        .join(USER_FLAKE_DIR.as_path())
+}
+
+/// Configuration overrides of the current user, `$XDG_CONFIG_HOME/flakes` or `~/.config/flakes`
+pub fn user_config_dir() -> PathBuf {
+    std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .filter(|p| p.is_absolute())
+        .unwrap_or_else(|| home::home_dir().unwrap_or_default().join(".config"))
+        .join(USER_CONFIG_DIR.as_path())
+}
+
+/// Directory for the application links of the current user, `~/.local/bin`
+pub fn user_bin_dir() -> PathBuf {
+    home::home_dir().unwrap_or_default().join(USER_BIN_DIR.as_path())
+}
+
 /// A PathBuf that is either relative to the usual system root or to a fake root
 pub struct RootedPath {
     internal_path: PathBuf,
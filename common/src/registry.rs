@@ -0,0 +1,327 @@
+use crate::{
+    config::{itf::FlakeConfig, parse_from_path, parser_for},
+    paths::{flake_dir_from, user_bin_dir, user_flake_dir, PathExt},
+};
+use serde_yaml::Value;
+use std::{
+    env, fs,
+    io::{Error, ErrorKind},
+    os::unix::fs::symlink,
+    path::{Path, PathBuf},
+};
+
+/// Environment variable to select the registry scope for flake-ctl and its addons.
+/// Set it to `user` to work on the registry of the calling user.
+pub const SCOPE_ENV: &str = "FLAKE_SCOPE";
+
+/// Scope of a registry
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Scope {
+    /// System-wide flakes in `/usr/share/flakes`, linked to their absolute host path.
+    #[default]
+    System,
+
+    /// Flakes of the current user in `$XDG_DATA_HOME/flakes`, linked into `~/.local/bin`.
+    User,
+}
+
+impl Scope {
+    /// Get the scope requested by the [`SCOPE_ENV`] environment variable.
+    /// Defaults to [`Scope::System`].
+    pub fn from_env() -> Self {
+        match env::var(SCOPE_ENV).unwrap_or_default().as_str() {
+            "user" => Scope::User,
+            _ => Scope::System,
+        }
+    }
+}
+
+/// FlakeRegistry is the place where all the flakes are registered.
+///
+/// A flake consists of a host application path (a symlink pointing to
+/// its pilot), a configuration `<name>.yaml` and an overlay directory
+/// `<name>.d` inside the flake directory. The registry is root-aware,
+/// i.e. it can operate on a fake root, such as a packaging buildroot.
+#[derive(Debug, Clone, Default)]
+pub struct FlakeRegistry {
+    root: Option<PathBuf>,
+    scope: Scope,
+}
+
+impl FlakeRegistry {
+    /// Create a system registry for the given (fake) root.
+    /// If no root is given, the registry operates on the system root.
+    pub fn new(root: Option<impl AsRef<Path>>) -> Self {
+        FlakeRegistry { root: root.map(|r| r.as_ref().to_owned()), scope: Scope::System }
+    }
+
+    /// Create a registry of the current user
+    pub fn user() -> Self {
+        FlakeRegistry { root: None, scope: Scope::User }
+    }
+
+    /// Find the registry a flake belongs to.
+    ///
+    /// The registry of the current user takes precedence over the system one.
+    /// If the flake is not registered at all, the system registry is returned.
+    pub fn lookup(name: &str) -> Self {
+        let user = Self::user();
+        if user.exists(name) {
+            return user;
+        }
+
+        Self::default()
+    }
+
+    /// Switch this registry to the given scope
+    pub fn with_scope(self, scope: Scope) -> Self {
+        FlakeRegistry { scope, ..self }
+    }
+
+    /// Get the scope of this registry
+    pub fn scope(&self) -> Scope {
+        self.scope
+    }
+
+    /// Get the (fake) root of this registry, if any
+    pub fn root(&self) -> Option<&Path> {
+        self.root.as_deref()
+    }
+
+    /// Get the flake directory on disk
+    pub fn dir(&self) -> PathBuf {
+        match self.scope {
+            Scope::System => flake_dir_from(self.root()),
+            Scope::User => self.on_disk(&user_flake_dir()),
+        }
+    }
+
+    /// Get path of a host application on disk, respecting the (fake) root.
+    ///
+    /// Applications of the user registry are linked into `~/.local/bin` by their name.
+    pub fn app_on_disk(&self, app: &Path) -> PathBuf {
+        match (self.scope, app.file_name()) {
+            (Scope::User, Some(name)) => self.on_disk(&user_bin_dir().join(name)),
+            _ => self.on_disk(app),
+        }
+    }
+
+    fn on_disk(&self, path: &Path) -> PathBuf {
+        match self.root() {
+            Some(root) => root.join_ignore_abs(path),
+            None => path.to_owned(),
+        }
+    }
+
+    /// Get the main configuration file of a flake
+    pub fn config_file(&self, name: &str) -> PathBuf {
+        self.dir().join(format!("{name}.yaml"))
+    }
+
+    /// Get the overlay configuration directory of a flake
+    pub fn config_dir(&self, name: &str) -> PathBuf {
+        self.dir().join(format!("{name}.d"))
+    }
+
+    /// Returns true if a flake with this name is registered
+    pub fn exists(&self, name: &str) -> bool {
+        self.config_file(name).is_file()
+    }
+
+    /// Get sorted names of all registered flakes
+    pub fn app_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = fs::read_dir(self.dir())
+            .into_iter()
+            .flatten()
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| path.is_file() && path.extension().map(|e| e == "yaml").unwrap_or_default())
+            .filter_map(|path| path.file_stem().and_then(|s| s.to_str()).map(str::to_owned))
+            .collect();
+        names.sort();
+        names
+    }
+
+    /// Load configuration of a registered flake.
+    /// User overlays are only applied to the flakes of the running system.
+    pub fn load(&self, name: &str) -> Result<FlakeConfig, Error> {
+        if !self.exists(name) {
+            return Err(Error::new(ErrorKind::NotFound, format!("Flake \"{name}\" is not registered")));
+        }
+        parse_from_path(&self.dir().join(name), self.root.is_none())
+    }
+
+    /// Load the merged raw configuration of a registered flake
+    /// together with the dotted keys provided by the user
+    pub fn load_raw(&self, name: &str) -> Result<(Value, Vec<String>), Error> {
+        if !self.exists(name) {
+            return Err(Error::new(ErrorKind::NotFound, format!("Flake \"{name}\" is not registered")));
+        }
+        parser_for(&self.dir().join(name), self.root.is_none())?.get_raw_config()
+    }
+
+    /// Prepare registration of the given host application.
+    ///
+    /// Makes sure the flake directory exists (following a symlink, if it is one)
+    /// and the application path is not yet taken.
+    pub fn init(&self, app: &Path) -> Result<(), Error> {
+        if fs::symlink_metadata(self.app_on_disk(app)).is_ok() {
+            return Err(Error::new(ErrorKind::AlreadyExists, format!("App path {} already exists", app.display())));
+        }
+
+        self.init_dir()
+    }
+
+    /// Make sure the flake directory exists, following a symlink, if it is one
+    pub fn init_dir(&self) -> Result<(), Error> {
+        let flake_dir = self.dir();
+        fs::create_dir_all(fs::read_link(&flake_dir).unwrap_or(flake_dir))
+    }
+
+    /// Register host application `app`, executed as `target` inside the flake,
+    /// by creating a symlink to the `pilot` and the flake overlay directory.
+    ///
+    /// The configuration file itself is written by the engine.
+    pub fn register(&self, app: &Path, target: &Path, pilot: &Path) -> Result<(), Error> {
+        for path in [app, target] {
+            if !path.is_absolute() {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("Application {} must be specified with an absolute path", path.display()),
+                ));
+            }
+        }
+
+        let name = flake_name(app)?;
+        let app_on_disk = self.app_on_disk(app);
+        if let Some(parent) = app_on_disk.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        symlink(pilot, &app_on_disk)?;
+        fs::create_dir_all(self.config_dir(&name))
+    }
+
+    /// Remove the host application `app` together with its configuration.
+    ///
+    /// The application must be a symlink to the given `pilot`,
+    /// otherwise nothing is removed.
+    pub fn remove(&self, app: &Path, pilot: &Path) -> Result<(), Error> {
+        if !app.is_absolute() {
+            return Err(Error::new(ErrorKind::InvalidInput, "Application must be specified with an absolute path"));
+        }
+
+        let name = flake_name(app)?;
+        let app_on_disk = self.app_on_disk(app);
+        if fs::read_link(&app_on_disk)?.file_name() != pilot.file_name() {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("Symlink {} is not pointing to {}", app.display(), pilot.display()),
+            ));
+        }
+        fs::remove_file(&app_on_disk)?;
+
+        if self.config_file(&name).exists() {
+            fs::remove_file(self.config_file(&name))?;
+        }
+        if self.config_dir(&name).exists() {
+            fs::remove_dir_all(self.config_dir(&name))?;
+        }
+
+        Ok(())
+    }
+
+    /// Rename registered host application `app` to `new_app`.
+    ///
+    /// Moves the application symlink, the configuration file and the overlay directory.
+    /// References to the application path inside the configuration are updated.
+    pub fn rename(&self, app: &Path, new_app: &Path) -> Result<(), Error> {
+        if !new_app.is_absolute() {
+            return Err(Error::new(ErrorKind::InvalidInput, "Application must be specified with an absolute path"));
+        }
+
+        let (name, new_name) = (flake_name(app)?, flake_name(new_app)?);
+        if name != new_name && self.exists(&new_name) {
+            return Err(Error::new(ErrorKind::AlreadyExists, format!("Flake \"{new_name}\" is already registered")));
+        }
+        self.init(new_app)?;
+
+        let new_app_on_disk = self.app_on_disk(new_app);
+        if let Some(parent) = new_app_on_disk.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::rename(self.app_on_disk(app), new_app_on_disk)?;
+
+        if self.config_dir(&name).exists() {
+            fs::rename(self.config_dir(&name), self.config_dir(&new_name))?;
+        }
+
+        let content = fs::read_to_string(self.config_file(&name))?;
+        let mut cfg = serde_yaml::from_str::<Value>(&content).map_err(|err| Error::new(ErrorKind::InvalidData, err))?;
+        if rename_app_path(&mut cfg, app, new_app) {
+            fs::write(self.config_file(&new_name), serde_yaml::to_string(&cfg).map_err(|err| Error::new(ErrorKind::InvalidData, err))?)?;
+            if name != new_name {
+                fs::remove_file(self.config_file(&name))?;
+            }
+        } else {
+            fs::rename(self.config_file(&name), self.config_file(&new_name))?;
+        }
+
+        Ok(())
+    }
+
+    /// Check if a flake is consistently registered.
+    ///
+    /// The configuration must be readable and every exported host path
+    /// must be a symlink pointing to the pilot of the configured engine.
+    pub fn check(&self, name: &str) -> Result<FlakeConfig, Error> {
+        let cfg = self.load(name)?;
+        let pilot = format!("{}-pilot", cfg.engine().pilot());
+        for app in cfg.runtime().paths().keys() {
+            match fs::read_link(self.app_on_disk(app)) {
+                Ok(link) if link.file_name().map(|n| n == pilot.as_str()).unwrap_or_default() => {}
+                Ok(link) => {
+                    return Err(Error::new(
+                        ErrorKind::InvalidData,
+                        format!("{} points to {} instead of {}", app.display(), link.display(), pilot),
+                    ))
+                }
+                Err(err) => return Err(Error::new(err.kind(), format!("{} is not a pilot link: {}", app.display(), err))),
+            }
+        }
+
+        Ok(cfg)
+    }
+}
+
+/// Get the name of a flake from its host application path
+pub fn flake_name(app: &Path) -> Result<String, Error> {
+    app.file_name()
+        .and_then(|n| n.to_str())
+        .map(str::to_owned)
+        .ok_or_else(|| Error::new(ErrorKind::InvalidInput, format!("Malformed app path {}", app.display())))
+}
+
+/// Replace the host application path in a raw config (v1 `host_app_path` or v2 `path_map` key).
+/// Returns true if anything was changed.
+fn rename_app_path(cfg: &mut Value, app: &Path, new_app: &Path) -> bool {
+    let (app, new_app) = (app.to_string_lossy().to_string(), new_app.to_string_lossy().to_string());
+    let mut changed = false;
+
+    for section in ["container", "vm"] {
+        if let Some(host_app_path) = cfg.get_mut(section).and_then(|s| s.get_mut("host_app_path")) {
+            if host_app_path.as_str() == Some(app.as_str()) {
+                *host_app_path = Value::from(new_app.as_str());
+                changed = true;
+            }
+        }
+    }
+
+    if let Some(Value::Mapping(path_map)) = cfg.get_mut("runtime").and_then(|r| r.get_mut("path_map")) {
+        if let Some(props) = path_map.remove(app.as_str()) {
+            path_map.insert(Value::from(new_app.as_str()), props);
+            changed = true;
+        }
+    }
+
+    changed
+}
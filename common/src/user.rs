@@ -15,6 +15,11 @@ impl<'a> From<&'a str> for User<'a> {
 impl<'a> User<'a> {
     pub const ROOT: User<'static> = User { name: Some("root") };
 
+    /// Name of the user, None for the default target user of sudo
+    pub fn name(&self) -> Option<&'a str> {
+        self.name
+    }
+
     pub fn run<S: AsRef<OsStr>>(&self, command: S) -> Command {
         let mut c = Command::new("sudo");
         if let Some(name) = self.name {
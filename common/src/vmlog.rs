@@ -0,0 +1,105 @@
+//! Bounded logs of firecracker VMs
+//!
+//! The serial console and the log of firecracker are collected per VM
+//! instance (the program name plus `@NAME`) into a ring log below the log
+//! directory, the metrics of firecracker into another one. A ring log is made
+//! of the current file and its predecessor `<file>.1`. When the current file
+//! exceeds half of the size limit it replaces the predecessor, such that the
+//! log never takes more than its limit and always keeps the most recent output.
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{Error, ErrorKind, Write},
+    path::{Path, PathBuf},
+};
+
+/// Directory of the VM logs and the firecracker log and metrics FIFOs
+pub const LOG_DIR: &str = "/var/lib/firecracker/storage/log";
+
+/// Size limit of a ring log in bytes
+pub const LOG_SIZE: u64 = 1 << 20;
+
+/// Ring log of the serial console and the firecracker log of an instance
+pub fn console_log(instance: &str) -> PathBuf {
+    Path::new(LOG_DIR).join(format!("{}.log", instance))
+}
+
+/// Ring log of the firecracker metrics of an instance
+pub fn metrics_log(instance: &str) -> PathBuf {
+    Path::new(LOG_DIR).join(format!("{}.metrics", instance))
+}
+
+/// FIFO firecracker writes its log to
+pub fn log_fifo(instance: &str) -> PathBuf {
+    Path::new(LOG_DIR).join(format!("{}.log.fifo", instance))
+}
+
+/// FIFO firecracker writes its metrics to
+pub fn metrics_fifo(instance: &str) -> PathBuf {
+    Path::new(LOG_DIR).join(format!("{}.metrics.fifo", instance))
+}
+
+/// Writer of a ring log
+#[derive(Debug)]
+pub struct RingLog {
+    path: PathBuf,
+    limit: u64,
+    file: File,
+    size: u64,
+}
+
+impl RingLog {
+    /// Open the ring log at path for appending
+    pub fn open(path: &Path, limit: u64) -> Result<RingLog, Error> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let size = file.metadata()?.len();
+        Ok(RingLog { path: path.to_owned(), limit, file, size })
+    }
+
+    /// Append data, rotating the current file if it would exceed half of the limit
+    pub fn write(&mut self, data: &[u8]) -> Result<(), Error> {
+        if self.size > 0 && self.size + data.len() as u64 > self.limit / 2 {
+            fs::rename(&self.path, previous(&self.path))?;
+            self.file = OpenOptions::new().create(true).write(true).truncate(true).open(&self.path)?;
+            self.size = 0;
+        }
+        self.file.write_all(data)?;
+        self.size += data.len() as u64;
+        Ok(())
+    }
+}
+
+/// Read the content of the ring log at path, oldest first
+pub fn read(path: &Path) -> Result<Vec<u8>, Error> {
+    let mut content = match fs::read(previous(path)) {
+        Ok(content) => content,
+        Err(error) if error.kind() == ErrorKind::NotFound => Vec::new(),
+        Err(error) => return Err(error),
+    };
+    content.extend(fs::read(path)?);
+    Ok(content)
+}
+
+/// The last lines of the ring log at path, empty if there is no log
+pub fn tail(path: &Path, lines: usize) -> Vec<String> {
+    let content = read(path).unwrap_or_default();
+    let content = String::from_utf8_lossy(&content);
+    let all: Vec<&str> = content.lines().collect();
+    all[all.len().saturating_sub(lines)..].iter().map(|line| line.to_string()).collect()
+}
+
+/// Remove the ring log at path along with its predecessor
+pub fn remove(path: &Path) -> Result<(), Error> {
+    for file in [previous(path), path.to_owned()] {
+        match fs::remove_file(file) {
+            Err(error) if error.kind() != ErrorKind::NotFound => return Err(error),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+fn previous(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".1");
+    PathBuf::from(name)
+}
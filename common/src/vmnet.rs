@@ -0,0 +1,303 @@
+//! Host side network of firecracker VMs
+//!
+//! Every VM instance with a network gets its own tap device, named and
+//! addressed after the instance name (the program name plus `@NAME`).
+//! The instance name is kept as alias of the tap, such that the taps can be
+//! found and mapped back to their instances later on.
+//!
+//! In private mode the taps are attached to the flake bridge, which routes
+//! the flake subnet to the outside by NAT. Each instance leases an address of
+//! that subnet through a file in the lease directory. In bridge mode the taps
+//! are attached to the given bridge and the guest configures itself, e.g. by DHCP.
+use crate::{config::itf::NetworkMode, user::User};
+use std::{
+    fs,
+    io::{Error, ErrorKind, Write},
+    net::Ipv4Addr,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+/// Bridge of the VMs in private mode
+pub const BRIDGE: &str = "flakes0";
+
+/// Address of the bridge, the gateway of the guests in private mode
+pub const GATEWAY: Ipv4Addr = Ipv4Addr::new(10, 202, 0, 1);
+
+/// Prefix length of the flake subnet
+pub const PREFIX_LEN: u8 = 16;
+
+/// Directory of the address leases, one file per address containing the instance name
+pub const LEASE_DIR: &str = "/var/lib/firecracker/storage/network";
+
+/// Name prefix of the tap devices
+pub const TAP_PREFIX: &str = "fc-";
+
+const SYS_CLASS_NET: &str = "/sys/class/net";
+
+/// Network of a VM instance on the host
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VmNetwork {
+    /// Name of the tap device
+    pub tap: String,
+
+    /// MAC address of the guest interface
+    pub guest_mac: String,
+
+    /// Leased address of the guest in private mode
+    pub address: Option<Ipv4Addr>,
+}
+
+impl VmNetwork {
+    /// Kernel `ip=` boot arg for the guest, if the address is static
+    pub fn boot_arg(&self) -> Option<String> {
+        self.address.map(|address| format!("ip={}::{}:{}::eth0:off", address, GATEWAY, netmask()))
+    }
+}
+
+/// Tap device of a VM instance on the host
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Tap {
+    /// Name of the tap device
+    pub name: String,
+
+    /// Instance using the tap
+    pub instance: String,
+
+    /// Bridge the tap is attached to
+    pub bridge: Option<String>,
+}
+
+/// Stable 32 bit FNV-1a hash of the instance name
+fn hash(instance: &str) -> u32 {
+    instance.bytes().fold(0x811c9dc5, |h, b| (h ^ b as u32).wrapping_mul(0x01000193))
+}
+
+/// Name of the tap device of an instance.
+/// Interface names are limited to 15 characters, hence the hash.
+pub fn tap_name(instance: &str) -> String {
+    format!("{}{:08x}", TAP_PREFIX, hash(instance))
+}
+
+/// Locally administered MAC address of the guest interface of an instance
+pub fn guest_mac(instance: &str) -> String {
+    let h = hash(instance).to_be_bytes();
+    format!("AA:FC:{:02X}:{:02X}:{:02X}:{:02X}", h[0], h[1], h[2], h[3])
+}
+
+/// Netmask of the flake subnet
+pub fn netmask() -> Ipv4Addr {
+    Ipv4Addr::from(u32::MAX << (32 - PREFIX_LEN))
+}
+
+/// Get the address leased by an instance
+pub fn leased(instance: &str) -> Option<Ipv4Addr> {
+    leases().ok()?.into_iter().find(|(_, owner)| owner == instance).map(|(address, _)| address)
+}
+
+/// Get all leases as address and instance name
+pub fn leases() -> Result<Vec<(Ipv4Addr, String)>, Error> {
+    if !Path::new(LEASE_DIR).exists() {
+        return Ok(vec![]);
+    }
+
+    let mut leases: Vec<(Ipv4Addr, String)> = fs::read_dir(LEASE_DIR)?
+        .flatten()
+        .filter_map(|entry| {
+            let address = entry.file_name().to_str()?.parse::<Ipv4Addr>().ok()?;
+            Some((address, fs::read_to_string(entry.path()).ok()?.trim().to_string()))
+        })
+        .collect();
+    leases.sort();
+
+    Ok(leases)
+}
+
+/// Lease an address of the flake subnet for an instance.
+///
+/// The instance keeps its address as long as the lease exists. A new lease
+/// starts at an address derived from the instance name and takes the next
+/// free one, the lease file is created exclusively to avoid races.
+pub fn lease(instance: &str) -> Result<Ipv4Addr, Error> {
+    if let Some(address) = leased(instance) {
+        return Ok(address);
+    }
+    if !Path::new(LEASE_DIR).is_dir() {
+        // leases are shared by the pilots of all users
+        let mut mkdir = User::ROOT.run("mkdir");
+        mkdir.args(["-p", "-m", "777", LEASE_DIR]);
+        run(mkdir)?;
+    }
+
+    let network = u32::from(GATEWAY) & u32::from(netmask());
+    // all hosts of the subnet, except the gateway and broadcast
+    let hosts = (1u32 << (32 - PREFIX_LEN)) - 3;
+    let start = hash(instance) % hosts;
+    for offset in 0..hosts {
+        let address = Ipv4Addr::from(network + 2 + (start + offset) % hosts);
+        match fs::OpenOptions::new().write(true).create_new(true).open(lease_file(address)) {
+            Ok(mut file) => {
+                file.write_all(instance.as_bytes())?;
+                return Ok(address);
+            }
+            Err(error) if error.kind() == ErrorKind::AlreadyExists => continue,
+            Err(error) => return Err(error),
+        }
+    }
+
+    Err(Error::other("No free address left in the flake subnet"))
+}
+
+/// Release the address leased by an instance
+pub fn release(instance: &str) -> Result<(), Error> {
+    for (address, owner) in leases()? {
+        if owner == instance {
+            fs::remove_file(lease_file(address))?;
+        }
+    }
+
+    Ok(())
+}
+
+fn lease_file(address: Ipv4Addr) -> PathBuf {
+    Path::new(LEASE_DIR).join(address.to_string())
+}
+
+/// Get the tap devices of all VM instances on this host
+pub fn taps() -> Vec<Tap> {
+    let mut taps: Vec<Tap> = fs::read_dir(SYS_CLASS_NET)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name().to_str()?.to_string();
+            if !name.starts_with(TAP_PREFIX) {
+                return None;
+            }
+            let instance = fs::read_to_string(entry.path().join("ifalias")).unwrap_or_default().trim().to_string();
+            let bridge = fs::read_link(entry.path().join("master"))
+                .ok()
+                .and_then(|master| master.file_name().map(|b| b.to_string_lossy().to_string()));
+            Some(Tap { name, instance, bridge })
+        })
+        .collect();
+    taps.sort_by(|a, b| a.instance.cmp(&b.instance));
+
+    taps
+}
+
+/// Check if a network link exists on the host
+pub fn link_exists(name: &str) -> bool {
+    Path::new(SYS_CLASS_NET).join(name).exists()
+}
+
+/// Run a host command as root, failing with its stderr
+fn run(mut command: Command) -> Result<(), Error> {
+    log::debug!("{:?}", command.get_args());
+    let output = command.output()?;
+    if !output.status.success() {
+        return Err(Error::other(format!("{:?} failed: {}", command.get_args(), String::from_utf8_lossy(&output.stderr).trim())));
+    }
+
+    Ok(())
+}
+
+fn ip(args: &[&str]) -> Result<(), Error> {
+    let mut ip = User::ROOT.run("ip");
+    ip.args(args);
+    run(ip)
+}
+
+fn nat_rule(action: &str) -> Command {
+    let mut iptables = User::ROOT.run("iptables");
+    iptables
+        .args(["-t", "nat", action, "POSTROUTING", "-s"])
+        .arg(format!("{}/{}", Ipv4Addr::from(u32::from(GATEWAY) & u32::from(netmask())), PREFIX_LEN))
+        .args(["!", "-o", BRIDGE, "-j", "MASQUERADE"]);
+    iptables
+}
+
+/// Create the flake bridge with NAT to the outside, if not yet present
+pub fn setup_bridge() -> Result<(), Error> {
+    if !link_exists(BRIDGE) {
+        ip(&["link", "add", "name", BRIDGE, "type", "bridge"])?;
+        ip(&["addr", "add", &format!("{}/{}", GATEWAY, PREFIX_LEN), "dev", BRIDGE])?;
+        ip(&["link", "set", BRIDGE, "up"])?;
+    }
+
+    let mut forward = User::ROOT.run("sysctl");
+    forward.arg("-qw").arg("net.ipv4.ip_forward=1");
+    run(forward)?;
+
+    if run(nat_rule("-C")).is_err() {
+        run(nat_rule("-A"))?;
+    }
+
+    Ok(())
+}
+
+/// Remove the flake bridge and its NAT rule
+pub fn remove_bridge() -> Result<(), Error> {
+    if link_exists(BRIDGE) {
+        ip(&["link", "del", BRIDGE])?;
+    }
+    while run(nat_rule("-C")).is_ok() {
+        run(nat_rule("-D"))?;
+    }
+
+    Ok(())
+}
+
+/// Create the tap device of an instance and attach it according to the network mode.
+///
+/// The tap is owned by the given user, which runs firecracker. A stale tap of
+/// the same instance is replaced. The none and host modes have no tap at all.
+pub fn setup(instance: &str, mode: &NetworkMode, owner: User) -> Result<Option<VmNetwork>, Error> {
+    let bridge = match mode {
+        NetworkMode::None | NetworkMode::Host => return Ok(None),
+        NetworkMode::Bridge(name) => name.as_str(),
+        NetworkMode::Default | NetworkMode::Private => BRIDGE,
+    };
+    if bridge != BRIDGE && !link_exists(bridge) {
+        return Err(Error::new(ErrorKind::NotFound, format!("Bridge {} does not exist", bridge)));
+    }
+
+    let tap = tap_name(instance);
+    if let Some(other) = taps().into_iter().find(|t| t.name == tap) {
+        if other.instance != instance {
+            return Err(Error::new(
+                ErrorKind::AlreadyExists,
+                format!("Tap device {} is in use by instance {}", tap, other.instance),
+            ));
+        }
+        ip(&["link", "del", &tap])?;
+    }
+
+    let address = if bridge == BRIDGE {
+        setup_bridge()?;
+        Some(lease(instance)?)
+    } else {
+        None
+    };
+
+    let mut tuntap = vec!["tuntap", "add", "dev", &tap, "mode", "tap"];
+    if let Some(name) = owner.name() {
+        tuntap.extend(["user", name]);
+    }
+    ip(&tuntap)?;
+    ip(&["link", "set", "dev", &tap, "alias", instance])?;
+    ip(&["link", "set", "dev", &tap, "master", bridge])?;
+    ip(&["link", "set", "dev", &tap, "up"])?;
+
+    Ok(Some(VmNetwork { tap, guest_mac: guest_mac(instance), address }))
+}
+
+/// Remove the tap device of an instance and release its address
+pub fn teardown(instance: &str) -> Result<(), Error> {
+    let tap = tap_name(instance);
+    if taps().iter().any(|t| t.name == tap && t.instance == instance) {
+        ip(&["link", "del", &tap])?;
+    }
+
+    release(instance)
+}
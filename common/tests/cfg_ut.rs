@@ -4,7 +4,7 @@ mod ut_rt;
 #[cfg(test)]
 mod cfg_v1_ut_oci {
     use super::ut_rt;
-    use flakes::config::itf::InstanceMode;
+    use flakes::config::itf::{Identity, InstanceMode};
     use std::path::PathBuf;
 
     /// Test Firecracker configuration v1 overall parse
@@ -146,13 +146,21 @@ mod cfg_v1_ut_oci {
             );
         });
     }
+
+    /// Test identity of the container app
+    #[test]
+    fn test_cfg_v1_pdm_identity() {
+        ut_rt::tb("cfg-v1/podman.yaml".to_string(), |cfg| {
+            assert!(cfg.unwrap().runtime().identity() == Identity::User, "Container app should run as the calling user");
+        });
+    }
 }
 
 /// Unit tests for v1 config, Virtual Machines
 mod cfg_v1_ut_vm {
     use std::path::PathBuf;
 
-    use flakes::config::{itf::InstanceMode, pilots::fc::FirecrackerRuntimeParams};
+    use flakes::config::{itf::{Identity, InstanceMode}, pilots::fc::FirecrackerRuntimeParams};
 
     use crate::ut_rt;
 
@@ -201,6 +209,14 @@ mod cfg_v1_ut_vm {
         });
     }
 
+    /// Test identity of the VM app
+    #[test]
+    fn test_cfg_v1_vm_identity() {
+        ut_rt::tb("cfg-v1/firecracker.yaml".to_string(), |cfg| {
+            assert!(cfg.unwrap().runtime().identity() == Identity::Image, "VM app should run as the image user by default");
+        });
+    }
+
     /// Test VM runtime should be resumed
     #[test]
     fn test_cfg_v1_vm_mode_flags() {
@@ -3,11 +3,17 @@ mod ut_rt;
 /// Unit tests for v2 config
 #[cfg(test)]
 mod cfg_v2_ut {
-    use std::path::PathBuf;
+    use std::{env, path::PathBuf};
 
-    use flakes::config::{itf::InstanceMode, pilots::fc::FirecrackerRuntimeParams};
+    use flakes::config::{
+        cfgparse::FlakeCfgParser,
+        conditions::HostFacts,
+        itf::{AccessMode, HostSocket, HostTrust, Identity, InstanceMode},
+        pilots::fc::{CpuTemplate, FirecrackerRuntimeParams, MmdsVersion},
+        setup::{parse_setup, translate_host_paths, HostIntegration},
+    };
 
-    use super::ut_rt;
+    use super::ut_rt::{self, Overlays};
 
     /// Test v2 overall parse
     #[test]
@@ -26,6 +32,15 @@ mod cfg_v2_ut {
         });
     }
 
+    #[test]
+    fn test_cfg_v2_runtime_identity() {
+        ut_rt::tb("cfg-v2/all.yaml".to_string(), |cfg| {
+            let cfg = cfg.unwrap();
+            assert!(cfg.runtime().identity() == Identity::Host, "The app should run as the calling user");
+            assert!(cfg.runtime().identity().caller_ids().is_some(), "Caller ids should be known");
+        });
+    }
+
     /// Test v2 path map
     #[test]
     fn test_cfg_v2_path_map_present() {
@@ -227,6 +242,20 @@ mod cfg_v2_ut {
         });
     }
 
+    #[test]
+    fn test_cfg_v2_engine_params_rtp_machine() {
+        ut_rt::tb("cfg-v2/all.yaml".to_string(), |cfg| {
+            let params = FirecrackerRuntimeParams::from(cfg.unwrap().engine().params().unwrap());
+            assert!(params.smt() == Some(false), "Runtime params should disable SMT");
+            assert!(params.cpu_template() == Some(CpuTemplate::T2S), "Runtime params should have the T2S CPU template");
+            assert!(params.balloon().unwrap().amount_mib() == 512, "Runtime params should have a balloon of 512 MiB");
+            assert!(params.entropy().is_some(), "Runtime params should have an entropy device");
+            assert!(params.network_rate_limiter().unwrap().tx().is_none(), "Only rx of the network should be limited");
+            assert!(params.mmds().unwrap().version() == MmdsVersion::V2, "Runtime params should have MMDS version 2");
+            assert!(params.validate().is_ok(), "Runtime params should be valid");
+        });
+    }
+
     #[test]
     fn test_cfg_v2_engine_params_rtp_root_fs() {
         ut_rt::tb("cfg-v2/all.yaml".to_string(), |cfg| {
@@ -283,4 +312,189 @@ mod cfg_v2_ut {
             );
         });
     }
+
+    #[test]
+    fn test_cfg_v2_user_overlay() {
+        let overlays = Overlays { user: vec!["user/override.yaml"], allowed: vec!["engine.args"], ..Default::default() };
+        ut_rt::tbo("cfg-v2/all.yaml".to_string(), overlays, |parser| {
+            let (_, user_keys) = parser.get_raw_config().unwrap();
+            assert!(user_keys == vec!["engine.args".to_string()], "Only engine args should be marked as user provided");
+
+            let cfg = parser.parse().unwrap();
+            assert!(cfg.engine().args().unwrap() == vec!["--rm", "-ti"], "User should override engine args");
+            assert!(cfg.engine().pilot() == "RD2D", "User should not override the pilot");
+            assert!(cfg.runtime().run_as().is_none(), "User should not override the runtime user");
+        });
+    }
+
+    #[test]
+    fn test_cfg_v2_merge_directives() {
+        let overlays =
+            Overlays { system: vec!["cfg-v2/all.d/10-append.yaml", "cfg-v2/all.d/20-remove.yaml"], ..Default::default() };
+        ut_rt::tbo("cfg-v2/all.yaml".to_string(), overlays, |parser| {
+            let cfg = parser.parse().unwrap();
+            assert!(cfg.engine().args().unwrap() == vec!["--foo=bar", "--baz"], "Arg should be appended and removed");
+            assert!(cfg.static_data().get_bundles().unwrap().len() == 5, "Bundle should be appended");
+            assert!(cfg.runtime().layers().unwrap() == &vec!["zero", "one", "two"], "Layer should be prepended");
+        });
+    }
+
+    #[test]
+    fn test_cfg_v2_merge_without_base() {
+        let data = env::current_dir().unwrap().join("tests").join("data").join("cfg-v2");
+        let cfg = FlakeCfgParser::new(data.join("all.d/10-append.yaml"), vec![]).unwrap();
+        let (cfg, _) = cfg.get_raw_config().unwrap();
+        assert!(
+            cfg.get("engine").unwrap().get("args").unwrap() == &serde_yaml::Value::Sequence(vec!["--baz".into()]),
+            "Directive without a base list should result in a plain list"
+        );
+    }
+
+    #[test]
+    fn test_cfg_v2_overlay_condition() {
+        let hosts = [
+            ("aarch64", "board-17", 512, "Overlay should be applied on a matching host"),
+            ("x86_64", "board-17", 4096, "Overlay should be skipped on another architecture"),
+            ("aarch64", "desktop", 4096, "Overlay should be skipped on another host"),
+        ];
+        for (arch, hostname, mem_size, message) in hosts {
+            let facts = HostFacts { arch: arch.to_string(), hostname: hostname.to_string(), kvm: true, ..Default::default() };
+            let overlays = Overlays { system: vec!["cfg-v2/all.d/30-small-arm.yaml"], facts: Some(facts), ..Default::default() };
+            ut_rt::tbo("cfg-v2/all.yaml".to_string(), overlays, |parser| {
+                let params = parser.parse().unwrap().engine().params().unwrap();
+                assert!(
+                    serde_yaml::from_value::<FirecrackerRuntimeParams>(params).unwrap().mem_size_mib() == Some(mem_size),
+                    "{}",
+                    message
+                );
+            });
+        }
+    }
+
+    #[test]
+    fn test_cfg_v2_overlay_condition_env() {
+        let facts = HostFacts { env: [("BOARD".to_string(), "rev-b".to_string())].into(), ..Default::default() };
+        let mut overlay: serde_yaml::Value = serde_yaml::from_str("when:\n  env:\n    BOARD: rev-*\nfoo: bar\n").unwrap();
+        assert!(facts.applies(&mut overlay).unwrap(), "Overlay should apply with a matching env var");
+        assert!(overlay.get("when").is_none(), "Condition header should be taken off");
+
+        let mut overlay: serde_yaml::Value = serde_yaml::from_str("when:\n  env:\n    OTHER: '*'\n").unwrap();
+        assert!(!facts.applies(&mut overlay).unwrap(), "Overlay should not apply without the env var");
+    }
+
+    #[test]
+    fn test_cfg_v2_setup_empty() {
+        ut_rt::tb("cfg-v2/all.yaml".to_string(), |cfg| {
+            assert!(cfg.unwrap().setup().is_empty(), "Setup should be empty by default");
+        });
+    }
+
+    #[test]
+    fn test_cfg_v2_setup_parse() {
+        ut_rt::tb("cfg-v2/setup.yaml".to_string(), |cfg| {
+            let cfg = cfg.unwrap();
+            let setup = cfg.setup();
+            assert!(setup.home() == Some(AccessMode::ReadWrite), "Home should be shared read-write");
+            assert!(setup.cwd() == Some(AccessMode::ReadOnly), "Current directory should be shared read-only");
+            assert!(setup.sockets() == [HostSocket::Wayland, HostSocket::PulseAudio], "Sockets should be passed");
+            assert!(setup.dbus(), "D-Bus should be accessible");
+            assert!(setup.devices() == [PathBuf::from("/dev/null")], "Device should be passed");
+        });
+    }
+
+    #[test]
+    fn test_cfg_v2_setup_mounts() {
+        ut_rt::tb("cfg-v2/setup.yaml".to_string(), |cfg| {
+            let cfg = cfg.unwrap();
+            let mounts = cfg.setup().mounts();
+            assert!(mounts[0].target() == &PathBuf::from("/data"), "Mount should have its target");
+            assert!(mounts[0].mode() == AccessMode::ReadWrite, "Mount should be read-write");
+            assert!(mounts[1].target() == &PathBuf::from("/etc/hosts"), "Mount target should default to its source");
+            assert!(mounts[1].mode() == AccessMode::ReadOnly, "Mount should be read-only by default");
+        });
+    }
+
+    #[test]
+    fn test_cfg_v2_setup_integrations() {
+        ut_rt::tb("cfg-v2/setup.yaml".to_string(), |cfg| {
+            let integrations = cfg.unwrap().setup().integrations().unwrap();
+            let cwd = env::current_dir().unwrap();
+            assert!(integrations.contains(&HostIntegration::Workdir(cwd)), "App should start in the current directory");
+            assert!(integrations.contains(&HostIntegration::Device(PathBuf::from("/dev/null"))), "Device should be passed");
+            assert!(
+                integrations.iter().any(|i| matches!(i, HostIntegration::Env(name, _) if name == "HOME")),
+                "Home should be set"
+            );
+        });
+    }
+
+    #[test]
+    fn test_cfg_v2_host_paths() {
+        ut_rt::tb("cfg-v2/setup.yaml".to_string(), |cfg| {
+            let cfg = cfg.unwrap();
+            let paths = cfg.runtime().paths();
+            assert!(paths.get(&PathBuf::from("/usr/bin/banana")).unwrap().host_paths().is_none(), "Translation should be off");
+            assert!(
+                paths.get(&PathBuf::from("/usr/bin/peel")).unwrap().host_paths() == Some(AccessMode::ReadWrite),
+                "Host paths should be translated read-write"
+            );
+        });
+    }
+
+    #[test]
+    fn test_translate_host_paths() {
+        let cwd = tempfile::tempdir().unwrap();
+        std::fs::create_dir(cwd.path().join("data")).unwrap();
+        std::fs::write(cwd.path().join("data/report.csv"), "").unwrap();
+
+        let args = ["-v", "./data/report.csv", "--out=data", "missing.txt"].map(String::from);
+        let (integrations, args) = translate_host_paths(&args, cwd.path(), AccessMode::ReadOnly);
+        let data = cwd.path().join("data");
+
+        assert!(args[0] == "-v" && args[3] == "missing.txt", "Non-paths should be kept");
+        assert!(args[1] == data.join("report.csv").display().to_string(), "Relative path should be absolute");
+        assert!(args[2] == format!("--out={}", data.display()), "Option value should be absolute");
+        assert!(integrations.len() == 2, "Directory should cover the file inside it");
+        assert!(
+            matches!(&integrations[0], HostIntegration::Mount(m) if m.source() == &data && m.target() == &data && m.mode() == AccessMode::ReadOnly),
+            "Directory should be mounted at the same path"
+        );
+        assert!(integrations[1] == HostIntegration::Workdir(cwd.path().to_owned()), "App should start in the caller directory");
+    }
+
+    #[test]
+    fn test_translate_host_paths_rw_parent() {
+        let cwd = tempfile::tempdir().unwrap();
+        std::fs::write(cwd.path().join("report.csv"), "").unwrap();
+
+        let (integrations, _) = translate_host_paths(&["report.csv".to_string()], cwd.path(), AccessMode::ReadWrite);
+        assert!(
+            matches!(&integrations[0], HostIntegration::Mount(m) if m.source() == cwd.path() && m.mode() == AccessMode::ReadWrite),
+            "Read-write file should be mounted by its parent"
+        );
+    }
+
+    #[test]
+    fn test_cfg_v2_setup_host_trust() {
+        let all = parse_setup(&serde_yaml::from_str("setup: {host_trust: true}").unwrap()).unwrap();
+        assert!(all.host_trust() == HostTrust::ALL, "All trust data should be injected");
+
+        let some = parse_setup(&serde_yaml::from_str("setup: {host_trust: [hosts, proxy]}").unwrap()).unwrap();
+        assert!(some.host_trust() == [HostTrust::Hosts, HostTrust::Proxy], "Listed trust data should be injected");
+
+        let none = parse_setup(&serde_yaml::from_str("setup: {host_trust: false}").unwrap()).unwrap();
+        assert!(none.is_empty(), "No trust data should be injected");
+    }
+
+    #[test]
+    fn test_cfg_v2_setup_host_trust_integrations() {
+        let setup = parse_setup(&serde_yaml::from_str("setup: {host_trust: [hosts]}").unwrap()).unwrap();
+        assert!(
+            setup.trust_integrations().iter().all(|i| matches!(
+                i,
+                HostIntegration::Mount(m) if m.target() == &PathBuf::from("/etc/hosts") && m.mode() == AccessMode::ReadOnly
+            )),
+            "Host names should be mounted read-only"
+        );
+    }
 }
@@ -0,0 +1,58 @@
+mod ut_rt;
+
+/// Unit tests for the environment policy
+#[cfg(test)]
+mod environment_ut {
+    use flakes::config::{itf::FlakeCfgEnv, placeholders::Placeholders};
+
+    use super::ut_rt;
+
+    fn caller() -> Vec<(String, String)> {
+        [("TERM", "xterm"), ("LC_TIME", "de_DE"), ("LC_ALL", "C"), ("https_proxy", "http://proxy"), ("SECRET", "42"), ("LANG", "de_DE")]
+            .iter()
+            .map(|(n, v)| (n.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_env_parse() {
+        ut_rt::tb("cfg-v2/all.yaml".to_string(), |cfg| {
+            let cfg = cfg.unwrap();
+            let env = cfg.runtime().env();
+            assert!(env.set().get("LANG").map(String::as_str) == Some("C.UTF-8"), "LANG should be set");
+            assert!(env.pass() == ["TERM", "LC_*", "*_proxy"], "Allow-list should be parsed");
+            assert!(env.deny() == ["LC_ALL"], "Deny-list should be parsed");
+        });
+    }
+
+    #[test]
+    fn test_env_resolve() {
+        ut_rt::tb("cfg-v2/all.yaml".to_string(), |cfg| {
+            let env = cfg.unwrap().runtime().env().resolve(caller());
+            let names: Vec<&str> = env.iter().map(|(n, _)| n.as_str()).collect();
+            assert!(names == ["LANG", "LC_TIME", "TERM", "https_proxy"], "Only allowed variables should pass, sorted");
+            assert!(env[0].1 == "C.UTF-8", "Set values should win over the caller");
+        });
+    }
+
+    #[test]
+    fn test_env_default_passes_nothing() {
+        assert!(FlakeCfgEnv::default().resolve(caller()).is_empty(), "Nothing should pass by default");
+    }
+
+    #[test]
+    fn test_env_invalid_name() {
+        assert!(serde_yaml::from_str::<FlakeCfgEnv>("set: {\"A-B\": x}").is_err(), "Invalid names should be refused");
+    }
+
+    #[test]
+    fn test_env_expand() {
+        let env = serde_yaml::from_str::<FlakeCfgEnv>("set: {CACHE: \"${HOME}/.cache\"}").unwrap();
+        let mut p = Placeholders::default();
+        p.set("HOME", "/home/joe");
+        assert!(
+            env.expanded(&p).unwrap().set().get("CACHE").map(String::as_str) == Some("/home/joe/.cache"),
+            "Set values should be expanded"
+        );
+    }
+}
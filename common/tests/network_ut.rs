@@ -0,0 +1,61 @@
+mod ut_rt;
+
+/// Unit tests for the network policy
+#[cfg(test)]
+mod network_ut {
+    use flakes::config::itf::{FlakeCfgNetwork, NetworkMode, Protocol};
+
+    use super::ut_rt;
+
+    #[test]
+    fn test_network_parse() {
+        ut_rt::tb("cfg-v2/all.yaml".to_string(), |cfg| {
+            let cfg = cfg.unwrap();
+            let network = cfg.runtime().network();
+            assert!(*network.mode() == NetworkMode::Bridge("br-flakes".to_string()), "Bridge should be named");
+            assert!(network.publish().len() == 2, "Two ports should be published");
+
+            let dns = &network.publish()[1];
+            assert!(dns.host_ip() == Some("127.0.0.1"), "Host address should be set");
+            assert!(dns.host_port() == 5353 && dns.guest_port() == 53, "Ports should be host:guest");
+            assert!(dns.protocol() == Protocol::Udp, "Protocol should be udp");
+        });
+    }
+
+    #[test]
+    fn test_network_v1_mode() {
+        ut_rt::tb("cfg-v1/podman.yaml".to_string(), |cfg| {
+            assert!(*cfg.unwrap().runtime().network().mode() == NetworkMode::None, "Network should be disabled");
+        });
+    }
+
+    #[test]
+    fn test_network_default() {
+        ut_rt::tb("cfg-v2/setup.yaml".to_string(), |cfg| {
+            let cfg = cfg.unwrap();
+            assert!(*cfg.runtime().network().mode() == NetworkMode::Default, "Engine default should be kept");
+            assert!(cfg.runtime().network().publish().is_empty(), "No ports should be published by default");
+        });
+    }
+
+    #[test]
+    fn test_network_publish_format() {
+        let network = serde_yaml::from_str::<FlakeCfgNetwork>("publish: [\"8080:80\", \"[::1]:53:53/udp\"]").unwrap();
+        let ports: Vec<String> = network.publish().iter().map(|p| p.to_string()).collect();
+        assert!(ports == ["8080:80/tcp", "[::1]:53:53/udp"], "Ports should be formatted for the engine");
+    }
+
+    #[test]
+    fn test_network_invalid() {
+        for yaml in [
+            "bridge",
+            "bridge:",
+            "wifi",
+            "publish: [\"80\"]",
+            "publish: [\"8080:80/sctp\"]",
+            "{mode: none, publish: [\"80:80\"]}",
+        ] {
+            assert!(serde_yaml::from_str::<FlakeCfgNetwork>(yaml).is_err(), "\"{yaml}\" should be refused");
+        }
+    }
+}
@@ -0,0 +1,68 @@
+mod ut_rt;
+
+/// Unit tests for the placeholder expansion
+#[cfg(test)]
+mod placeholders_ut {
+    use std::path::{Path, PathBuf};
+
+    use flakes::config::placeholders::{self, Placeholders};
+
+    use super::ut_rt;
+
+    fn placeholders() -> Placeholders {
+        let mut p = Placeholders::default();
+        p.set("HOME", "/home/joe");
+        p.set("FLAKE_NAME", "banana");
+        p.set("INSTANCE", "banana@ripe");
+        p
+    }
+
+    #[test]
+    fn test_placeholders_expand_args() {
+        ut_rt::tb("cfg-v2/expand.yaml".to_string(), |cfg| {
+            let cfg = cfg.unwrap().expanded(&placeholders()).unwrap();
+            assert!(
+                cfg.engine().args().unwrap()
+                    == vec!["-v /home/joe:/home/joe", "--name=banana@ripe", "--env=COST=$5", "--env=LITERAL=$BAR"],
+                "Engine args should be expanded"
+            );
+        });
+    }
+
+    #[test]
+    fn test_placeholders_expand_exports_and_bundles() {
+        ut_rt::tb("cfg-v2/expand.yaml".to_string(), |cfg| {
+            let cfg = cfg.unwrap().expanded(&placeholders()).unwrap();
+            assert!(
+                cfg.runtime().paths().get(Path::new("/usr/bin/banana")).unwrap().exports() == &PathBuf::from("/home/joe/bin/banana"),
+                "Exports should be expanded"
+            );
+            assert!(cfg.static_data().get_bundles().unwrap() == ["banana.tar.gz"], "Bundles should be expanded");
+        });
+    }
+
+    #[test]
+    fn test_placeholders_disabled() {
+        ut_rt::tb("cfg-v2/all.yaml".to_string(), |cfg| {
+            let cfg = cfg.unwrap();
+            assert!(!cfg.runtime().expand(), "Expansion should be disabled by default");
+            assert!(
+                cfg.expanded(&placeholders()).unwrap().engine().args() == cfg.engine().args(),
+                "Nothing should be expanded"
+            );
+        });
+    }
+
+    #[test]
+    fn test_placeholders_unknown() {
+        let result = std::panic::catch_unwind(|| ut_rt::tb("cfg-v2/expand-unknown.yaml".to_string(), |_| {}));
+        assert!(result.is_err(), "Unknown placeholders should be refused");
+    }
+
+    #[test]
+    fn test_placeholders_validate() {
+        assert!(placeholders::validate("${UID}:${GID}").is_ok(), "Known placeholders should be valid");
+        assert!(placeholders::validate("$${PASSWORD}").is_ok(), "Escaped placeholders should be valid");
+        assert!(placeholders::validate("${HOME").is_err(), "Unterminated placeholders should be refused");
+    }
+}
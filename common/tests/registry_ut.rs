@@ -0,0 +1,126 @@
+/// Unit tests for the flake registry
+#[cfg(test)]
+mod registry_ut {
+    use flakes::{
+        paths::{user_bin_dir, PathExt},
+        registry::{FlakeRegistry, Scope},
+    };
+    use std::{env, fs, path::Path};
+    use tempfile::TempDir;
+
+    /// Setup a fake root with a registered podman flake
+    fn setup() -> (TempDir, FlakeRegistry) {
+        let root = tempfile::tempdir().unwrap();
+        let registry = FlakeRegistry::new(Some(root.path()));
+        let app = Path::new("/usr/bin/banana");
+
+        registry.init(app).unwrap();
+        registry.register(app, app, Path::new("/usr/bin/podman-pilot")).unwrap();
+        fs::copy(env::current_dir().unwrap().join("tests/data/cfg-v1/podman.yaml"), registry.config_file("banana")).unwrap();
+
+        (root, registry)
+    }
+
+    #[test]
+    fn test_registry_register() {
+        let (root, registry) = setup();
+        assert!(root.path().join("usr/bin/banana").is_symlink(), "Banana should be linked in the fake root");
+        assert!(registry.config_dir("banana").is_dir(), "Banana should have an overlay directory");
+        assert!(registry.app_names() == vec!["banana".to_string()], "Only banana should be registered");
+    }
+
+    #[test]
+    fn test_registry_register_twice() {
+        let (_root, registry) = setup();
+        assert!(registry.init(Path::new("/usr/bin/banana")).is_err(), "Banana should not be registered twice");
+    }
+
+    #[test]
+    fn test_registry_register_relative() {
+        let (_root, registry) = setup();
+        assert!(
+            registry.register(Path::new("apple"), Path::new("apple"), Path::new("/usr/bin/podman-pilot")).is_err(),
+            "Relative application paths should be refused"
+        );
+    }
+
+    #[test]
+    fn test_registry_load() {
+        let (_root, registry) = setup();
+        assert!(registry.load("banana").is_ok(), "Banana config should be loaded");
+        assert!(registry.load("apple").is_err(), "Apple is not registered");
+    }
+
+    #[test]
+    fn test_registry_load_dotted_name() {
+        let (_root, registry) = setup();
+        let app = Path::new("/usr/bin/python3.11");
+        registry.init(app).unwrap();
+        registry.register(app, app, Path::new("/usr/bin/podman-pilot")).unwrap();
+        fs::copy(env::current_dir().unwrap().join("tests/data/cfg-v1/podman.yaml"), registry.config_file("python3.11")).unwrap();
+
+        assert!(registry.load("python3.11").is_ok(), "Dotted flake names should be loaded");
+        assert!(registry.load_raw("python3.11").is_ok(), "Dotted flake names should be loaded raw");
+    }
+
+    #[test]
+    fn test_registry_remove_wrong_pilot() {
+        let (_root, registry) = setup();
+        assert!(
+            registry.remove(Path::new("/usr/bin/banana"), Path::new("/usr/bin/firecracker-pilot")).is_err(),
+            "Podman flakes should not be removed as firecracker flakes"
+        );
+        assert!(registry.exists("banana"), "Banana should still be registered");
+    }
+
+    #[test]
+    fn test_registry_remove() {
+        let (root, registry) = setup();
+        registry.remove(Path::new("/usr/bin/banana"), Path::new("/usr/bin/podman-pilot")).unwrap();
+        assert!(!root.path().join("usr/bin/banana").exists(), "Banana link should be removed");
+        assert!(!registry.config_dir("banana").exists(), "Banana overlay directory should be removed");
+        assert!(registry.app_names().is_empty(), "No flakes should be left");
+    }
+
+    #[test]
+    fn test_registry_rename() {
+        let (root, registry) = setup();
+        registry.rename(Path::new("/usr/bin/banana"), Path::new("/usr/bin/apple")).unwrap();
+        assert!(root.path().join("usr/bin/apple").is_symlink(), "Apple should be linked in the fake root");
+        assert!(!registry.exists("banana"), "Banana should be gone");
+        assert!(registry.config_dir("apple").is_dir(), "Apple should have an overlay directory");
+
+        let cfg = registry.load("apple").unwrap();
+        assert!(cfg.runtime().paths().get(Path::new("/usr/bin/apple")).is_some(), "Apple should be exported");
+    }
+
+    #[test]
+    fn test_registry_check() {
+        let (root, registry) = setup();
+        assert!(registry.check("banana").is_ok(), "Banana should be consistently registered");
+
+        fs::rename(root.path().join("usr/bin/banana"), root.path().join("usr/bin/sandbox")).unwrap();
+        assert!(registry.check("banana").is_err(), "Banana without its link should not pass the check");
+    }
+
+    #[test]
+    fn test_registry_user_scope() {
+        let root = tempfile::tempdir().unwrap();
+        let registry = FlakeRegistry::new(Some(root.path())).with_scope(Scope::User);
+        let app = Path::new("/usr/bin/banana");
+
+        registry.init(app).unwrap();
+        registry.register(app, app, Path::new("/usr/bin/podman-pilot")).unwrap();
+        fs::copy(env::current_dir().unwrap().join("tests/data/cfg-v1/podman.yaml"), registry.config_file("banana")).unwrap();
+
+        assert!(!root.path().join("usr/bin/banana").exists(), "User flakes should not be linked to the host path");
+        assert!(root.path().join_ignore_abs(user_bin_dir()).join("banana").is_symlink(), "Banana should be linked for the user");
+        assert!(registry.check("banana").is_ok(), "Banana should be consistently registered for the user");
+
+        let cfg = registry.load("banana").unwrap();
+        assert!(
+            cfg.runtime().paths().get_by_path(user_bin_dir().join("banana")).is_some(),
+            "Banana should be found by its user link"
+        );
+    }
+}
@@ -0,0 +1,52 @@
+mod ut_rt;
+
+/// Unit tests for the resource limits
+#[cfg(test)]
+mod resources_ut {
+    use flakes::config::{itf::FlakeCfgResources, resources::parse_size};
+
+    use super::ut_rt;
+
+    #[test]
+    fn test_resources_parse() {
+        ut_rt::tb("cfg-v2/all.yaml".to_string(), |cfg| {
+            let cfg = cfg.unwrap();
+            let resources = cfg.runtime().resources();
+            assert!(resources.cpus() == Some(1.5), "CPU quota should be set");
+            assert!(resources.cpu_shares() == Some(512), "CPU shares should be set");
+            assert!(resources.memory() == Some(2 << 30), "Memory should be in bytes");
+            assert!(resources.pids() == Some(256), "Pids limit should be set");
+            assert!(resources.io_weight() == Some(100), "I/O weight should be set");
+        });
+    }
+
+    #[test]
+    fn test_resources_default() {
+        ut_rt::tb("cfg-v2/setup.yaml".to_string(), |cfg| {
+            assert!(cfg.unwrap().runtime().resources().is_empty(), "No limits should be set by default");
+        });
+    }
+
+    #[test]
+    fn test_resources_invalid() {
+        for yaml in ["cpus: 0", "cpu_shares: 1", "pids: 0", "io_weight: 5000", "memory: 2 lightyears", "memory: 0"] {
+            assert!(serde_yaml::from_str::<FlakeCfgResources>(yaml).is_err(), "\"{yaml}\" should be refused");
+        }
+    }
+
+    #[test]
+    fn test_resources_cpu_weight() {
+        let min = serde_yaml::from_str::<FlakeCfgResources>("cpu_shares: 2").unwrap();
+        let max = serde_yaml::from_str::<FlakeCfgResources>("cpu_shares: 262144").unwrap();
+        assert!(min.cpu_weight() == Some(1) && max.cpu_weight() == Some(10000), "Shares should map to the cgroup v2 weight");
+    }
+
+    #[test]
+    fn test_parse_size() {
+        assert!(parse_size("1024") == Some(1024), "Plain number should be bytes");
+        assert!(parse_size("512M") == Some(512 << 20), "M should be MiB");
+        assert!(parse_size("2GiB") == Some(2 << 30), "GiB should be accepted");
+        assert!(parse_size("1g") == Some(1 << 30), "Units should be case-insensitive");
+        assert!(parse_size("1.5G").is_none(), "Fractions are not supported");
+    }
+}
@@ -1,6 +1,6 @@
 use core::panic;
-use flakes::config::{cfgparse::FlakeCfgParser, itf::FlakeConfig};
-use std::env;
+use flakes::config::{cfgparse::FlakeCfgParser, conditions::HostFacts, itf::FlakeConfig};
+use std::{env, path::PathBuf};
 
 /// Setup the test
 fn setup(cfg_path: String) -> Option<FlakeConfig> {
@@ -18,3 +18,36 @@ where
 {
     probe(setup(cfg_path));
 }
+
+/// Overlays of a test bundle, relative to the test data
+#[allow(dead_code)]
+#[derive(Default)]
+pub struct Overlays {
+    /// System overlays of the `.d` directory
+    pub system: Vec<&'static str>,
+
+    /// User overlays and the keys users are allowed to override
+    pub user: Vec<&'static str>,
+    pub allowed: Vec<&'static str>,
+
+    /// Facts of the host the config is loaded on
+    pub facts: Option<HostFacts>,
+}
+
+/// Run a test bundle on a base config with its overlays
+#[allow(dead_code)]
+pub fn tbo<T>(cfg_path: String, overlays: Overlays, probe: T)
+where
+    T: Fn(FlakeCfgParser) + panic::UnwindSafe,
+{
+    let data = env::current_dir().unwrap().join("tests").join("data");
+    let paths = |files: &[&str]| files.iter().map(|file| data.join(file)).collect::<Vec<PathBuf>>();
+    let mut parser = FlakeCfgParser::new(data.join(&cfg_path), paths(&overlays.system)).unwrap();
+    if !overlays.user.is_empty() {
+        parser = parser.with_user_overlay(paths(&overlays.user), overlays.allowed.iter().map(|key| key.to_string()).collect());
+    }
+    if let Some(facts) = overlays.facts {
+        parser = parser.with_host_facts(facts);
+    }
+    probe(parser);
+}
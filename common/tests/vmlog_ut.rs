@@ -0,0 +1,31 @@
+/// Unit tests for the bounded logs of VMs
+#[cfg(test)]
+mod vmlog_ut {
+    use flakes::vmlog::{self, RingLog};
+
+    #[test]
+    fn test_vmlog_paths() {
+        assert!(vmlog::console_log("banana@1").ends_with("banana@1.log"), "Console log should be named after the instance");
+        assert!(vmlog::metrics_log("banana@1").ends_with("banana@1.metrics"), "Metrics should be kept apart");
+        assert!(vmlog::log_fifo("banana").starts_with(vmlog::LOG_DIR), "FIFOs should live in the log directory");
+    }
+
+    #[test]
+    fn test_vmlog_ring() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("banana.log");
+        let mut log = RingLog::open(&path, 40).unwrap();
+        for line in ["one\n", "two\n", "three\n", "four\n", "five\n", "six\n", "seven\n", "eight\n", "nine\n", "ten\n"] {
+            log.write(line.as_bytes()).unwrap();
+        }
+        let content = vmlog::read(&path).unwrap();
+        assert!(content.len() <= 40, "Ring log should stay within its limit");
+        assert!(content.ends_with(b"nine\nten\n"), "Ring log should keep the recent output");
+        assert!(!content.starts_with(b"one\n"), "Ring log should drop the old output");
+        assert!(vmlog::tail(&path, 2) == ["nine", "ten"], "Tail should return the last lines");
+
+        vmlog::remove(&path).unwrap();
+        assert!(vmlog::read(&path).is_err(), "Ring log should be gone");
+        assert!(vmlog::tail(&path, 2).is_empty(), "Tail of a missing log should be empty");
+    }
+}
@@ -0,0 +1,38 @@
+/// Unit tests for the host side network of VMs
+#[cfg(test)]
+mod vmnet_ut {
+    use flakes::vmnet::{self, VmNetwork};
+    use std::net::Ipv4Addr;
+
+    #[test]
+    fn test_vmnet_tap_name() {
+        let tap = vmnet::tap_name("banana@long-instance-name");
+        assert!(tap.len() <= 15, "Tap name {tap} exceeds the interface name limit");
+        assert!(tap.starts_with(vmnet::TAP_PREFIX), "Tap name should carry the prefix");
+        assert!(tap == vmnet::tap_name("banana@long-instance-name"), "Tap name should be stable");
+        assert!(tap != vmnet::tap_name("banana@other"), "Instances should get their own tap");
+    }
+
+    #[test]
+    fn test_vmnet_guest_mac() {
+        let mac = vmnet::guest_mac("banana");
+        assert!(mac.starts_with("AA:FC:") && mac.len() == 17, "MAC {mac} should be locally administered");
+        assert!(mac != vmnet::guest_mac("banana@1"), "Instances should get their own MAC");
+    }
+
+    #[test]
+    fn test_vmnet_boot_arg() {
+        assert!(vmnet::netmask() == Ipv4Addr::new(255, 255, 0, 0), "Netmask should match the prefix");
+
+        let network = VmNetwork {
+            tap: vmnet::tap_name("banana"),
+            guest_mac: vmnet::guest_mac("banana"),
+            address: Some(Ipv4Addr::new(10, 202, 3, 4)),
+        };
+        assert!(
+            network.boot_arg().as_deref() == Some("ip=10.202.3.4::10.202.0.1:255.255.0.0::eth0:off"),
+            "Static address should be passed to the guest kernel"
+        );
+        assert!(VmNetwork { address: None, ..network }.boot_arg().is_none(), "Bridged guests configure themselves");
+    }
+}
@@ -54,6 +54,8 @@ pub struct AppFireCracker {
 pub struct AppFireCrackerRuntime {
     pub runas: Option<String>,
     pub resume: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub network: Option<String>,
     pub firecracker: Option<AppFireCrackerEngine>,
 }
 #[derive(Debug, Serialize, Deserialize)]
@@ -166,6 +168,7 @@ impl AppConfig {
                 }
             }
             firecracker_section.boot_args = Some(boot_args);
+            vm_config.runtime.as_mut().unwrap().network = Some("none".to_string());
         }
 
         if resume {
@@ -136,8 +136,9 @@ pub enum Firecracker {
 
         /// Name of a tar file to be included on top of
         /// the VM instance. This option can be
-        /// specified multiple times.
-        #[clap(long, multiple = true, requires = "overlay-size")]
+        /// specified multiple times. Without an overlay
+        /// the includes are attached as read-only drive.
+        #[clap(long, multiple = true)]
         include_tar: Option<Vec<String>>,
     },
     /// Remove application registration or entire VM
@@ -155,6 +156,41 @@ pub enum Firecracker {
         #[clap(long)]
         app: Option<String>,
     },
+    /// Show or reset the host network of the VMs
+    Network {
+        /// Remove taps and address leases of instances which
+        /// are no longer running, and the flake bridge if no
+        /// instance is left on it
+        #[clap(long)]
+        reset: bool,
+    },
+    /// Show the console and firecracker log of a VM instance
+    Logs {
+        /// Name of the flake, with @NAME for a named instance
+        flake: String,
+
+        /// Show the firecracker metrics instead
+        #[clap(long)]
+        metrics: bool,
+
+        /// Show the given number of last lines only
+        #[clap(long)]
+        tail: Option<usize>,
+    },
+    /// Stop a running resume type VM instance
+    Stop {
+        /// Name of the flake, with @NAME for a named instance
+        flake: String,
+
+        /// Kill firecracker if the VM does not shut down in time
+        #[clap(long)]
+        force: bool,
+    },
+    /// Show the commands running in a resume type VM instance
+    Sessions {
+        /// Name of the flake, with @NAME for a named instance
+        flake: String,
+    },
     /// Print the info string for flake-ctl
     About
 
@@ -21,8 +21,6 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 //
-pub const FLAKE_DIR: &str =
-    "/usr/share/flakes";
 pub const FIRECRACKER_PILOT: &str =
     "/usr/bin/firecracker-pilot";
 pub const FLAKE_TEMPLATE_FIRECRACKER:&str =
@@ -39,3 +37,17 @@ pub const FIRECRACKER_ROOTFS_NAME:&str =
     "rootfs";
 pub const FIRECRACKER_SCI:&str =
     "/usr/lib/flake-pilot/sci";
+pub const FIRECRACKER_VMID_DIR: &str =
+    "/var/lib/firecracker/storage/tmp/flakes";
+pub const SOCAT: &str =
+    "/usr/bin/socat";
+pub const VM_PORT: u32 =
+    52;
+pub const VM_QUIT: &str =
+    "sci_quit";
+pub const VM_SESSIONS: &str =
+    "sci_sessions";
+pub const VM_STOP_TIMEOUT_MSEC: u64 =
+    10000;
+pub const VM_WAIT_MSEC: u64 =
+    100;
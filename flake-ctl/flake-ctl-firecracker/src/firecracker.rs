@@ -23,13 +23,17 @@
 //
 use std::ffi::OsStr;
 use std::os::unix::fs::PermissionsExt;
-use std::process::Command;
+use std::process::{Command, Stdio};
+use std::io::Write;
+use std::{thread, time};
 use log::{error, info};
 use tempfile::tempdir;
 use std::path::Path;
 use std::borrow::Cow;
 use std::fs;
 
+use flakes::{vmlog, vmnet};
+
 use crate::defaults;
 use crate::{app, app_config};
 
@@ -444,25 +448,24 @@ pub fn purge_vm(vm: &str) {
     to the VM. Delete all app registrations for this
     VM and also delete the VM from the local registry
     !*/
-    for app_name in app::app_names() {
-        let config_file = format!(
-            "{}/{}.yaml", defaults::FLAKE_DIR, app_name
-        );
-        match app_config::AppConfig::init_from_file(Path::new(&config_file)) {
-            Ok(mut app_conf) => {
-                if app_conf.vm.is_some() &&
-                    vm == app_conf.vm.as_mut().unwrap().name
-                {
-                    app::remove(
-                        &app_conf.vm.as_mut().unwrap().host_app_path,
-                        defaults::FIRECRACKER_PILOT, false
-                    );
+    let registry = app::registry();
+    for app_name in registry.app_names() {
+        let config_file = registry.config_file(&app_name);
+        match app_config::AppConfig::init_from_file(&config_file) {
+            Ok(app_conf) => {
+                if let Some(app_vm) = app_conf.vm {
+                    if vm == app_vm.name {
+                        app::remove(
+                            &app_vm.host_app_path,
+                            defaults::FIRECRACKER_PILOT, false
+                        );
+                    }
                 }
             },
             Err(error) => {
                 error!(
                     "Ignoring error on load or parse flake config {}: {:?}",
-                    config_file, error
+                    config_file.display(), error
                 );
             }
         };
@@ -475,3 +478,256 @@ pub fn purge_vm(vm: &str) {
         }
     }
 }
+
+pub fn instance_running(instance: &str) -> bool {
+    /*!
+    Check if the VM of the given instance is running
+    according to its VM ID file
+    !*/
+    let vm_id_file = format!(
+        "{}/{}.vmid", defaults::FIRECRACKER_VMID_DIR, instance
+    );
+    match fs::read_to_string(vm_id_file) {
+        Ok(vmid) => {
+            let vmid = vmid.trim();
+            vmid != "0" && Path::new(&format!("/proc/{}", vmid)).exists()
+        },
+        Err(_) => false
+    }
+}
+
+pub fn print_network() {
+    /*!
+    Show the flake bridge and the taps and address leases
+    of the VM instances on this host
+    !*/
+    let bridge_state = if vmnet::link_exists(vmnet::BRIDGE) {
+        "up"
+    } else {
+        "absent"
+    };
+    println!(
+        "Bridge {}: {}/{} ({})",
+        vmnet::BRIDGE, vmnet::GATEWAY, vmnet::PREFIX_LEN, bridge_state
+    );
+
+    let leases = vmnet::leases().unwrap_or_else(|error| {
+        error!("Failed to read address leases: {}", error);
+        Vec::new()
+    });
+    let mut instances: Vec<String> = vmnet::taps().into_iter()
+        .map(|tap| tap.instance)
+        .chain(leases.iter().map(|(_, instance)| instance.clone()))
+        .collect();
+    instances.sort();
+    instances.dedup();
+
+    println!(
+        "{:<32} {:<12} {:<12} {:<16} STATE",
+        "INSTANCE", "TAP", "BRIDGE", "ADDRESS"
+    );
+    let taps = vmnet::taps();
+    for instance in instances {
+        let tap = taps.iter().find(|tap| tap.instance == instance);
+        let address = leases.iter()
+            .find(|(_, owner)| *owner == instance)
+            .map(|(address, _)| address.to_string());
+        let state = if instance_running(&instance) {
+            "running"
+        } else {
+            "stale"
+        };
+        println!(
+            "{:<32} {:<12} {:<12} {:<16} {}",
+            instance,
+            tap.map(|tap| tap.name.as_str()).unwrap_or("-"),
+            tap.and_then(|tap| tap.bridge.as_deref()).unwrap_or("-"),
+            address.as_deref().unwrap_or("-"),
+            state
+        );
+    }
+}
+
+pub fn print_logs(instance: &str, metrics: bool, tail: Option<usize>) -> bool {
+    /*!
+    Show the ring log of the console and the firecracker log,
+    or of the firecracker metrics, of the given VM instance
+    !*/
+    let log = if metrics {
+        vmlog::metrics_log(instance)
+    } else {
+        vmlog::console_log(instance)
+    };
+    if let Some(lines) = tail {
+        for line in vmlog::tail(&log, lines) {
+            println!("{}", line);
+        }
+        return true
+    }
+    match vmlog::read(&log) {
+        Ok(content) => {
+            print!("{}", String::from_utf8_lossy(&content));
+            true
+        },
+        Err(error) => {
+            error!("No log of instance {}: {}", instance, error);
+            false
+        }
+    }
+}
+
+pub fn stop_instance(instance: &str, force: bool) -> bool {
+    /*!
+    Ask sci of the given resume type VM instance to terminate
+    its commands and shutdown the VM. With force firecracker
+    is terminated if sci can not be reached or the VM does not
+    shut down in time
+    !*/
+    if ! instance_running(instance) {
+        info!("Instance {} is not running", instance);
+        return true
+    }
+    match sci_request(instance, defaults::VM_QUIT) {
+        Some(_) => info!("Stopping instance {}", instance),
+        None => {
+            error!("sci of instance {} is not reachable", instance);
+            if ! force {
+                return false
+            }
+        }
+    }
+    let wait = time::Duration::from_millis(defaults::VM_WAIT_MSEC);
+    for _ in 0..defaults::VM_STOP_TIMEOUT_MSEC / defaults::VM_WAIT_MSEC {
+        if ! instance_running(instance) {
+            return true
+        }
+        thread::sleep(wait);
+    }
+    if ! force {
+        error!("Instance {} did not shut down in time", instance);
+        return false
+    }
+    let vm_id_file = format!(
+        "{}/{}.vmid", defaults::FIRECRACKER_VMID_DIR, instance
+    );
+    let vmid = fs::read_to_string(vm_id_file).unwrap_or_default();
+    info!("Terminating firecracker of instance {}", instance);
+    let mut kill = Command::new("kill");
+    kill.arg(vmid.trim());
+    match kill.status() {
+        Ok(status) if status.success() => true,
+        _ => {
+            error!("Failed to terminate instance {}", instance);
+            false
+        }
+    }
+}
+
+pub fn print_sessions(instance: &str) -> bool {
+    /*!
+    Show the commands sci runs in the given
+    resume type VM instance
+    !*/
+    if ! instance_running(instance) {
+        error!("Instance {} is not running", instance);
+        return false
+    }
+    match sci_request(instance, defaults::VM_SESSIONS) {
+        Some(sessions) => {
+            println!("{:<8} {:<6} COMMAND", "PID", "PORT");
+            for session in sessions.lines() {
+                let mut fields = session.splitn(3, ' ');
+                println!(
+                    "{:<8} {:<6} {}",
+                    fields.next().unwrap_or_default(),
+                    fields.next().unwrap_or_default(),
+                    fields.next().unwrap_or_default()
+                );
+            }
+            true
+        },
+        None => {
+            error!("sci of instance {} is not reachable", instance);
+            false
+        }
+    }
+}
+
+fn sci_request(instance: &str, request: &str) -> Option<String> {
+    /*!
+    Send a request to sci of the given VM instance through
+    the vsock UDS recorded by firecracker-pilot, and return
+    the reply of sci
+    !*/
+    let uds_file = format!(
+        "{}/{}.uds", defaults::FIRECRACKER_VMID_DIR, instance
+    );
+    let uds_path = fs::read_to_string(uds_file).unwrap_or_else(
+        |_| format!("/run/sci_cmd_{}.sock", instance)
+    );
+    let mut socat = Command::new(defaults::SOCAT);
+    socat.arg("-").arg(format!("UNIX-CONNECT:{}", uds_path.trim()))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null());
+    let mut child = socat.spawn().ok()?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(
+            format!("CONNECT {}\n{}\n", defaults::VM_PORT, request).as_bytes()
+        ).ok()?;
+    }
+    let output = child.wait_with_output().ok()?;
+    // firecracker confirms the connection with OK <port>
+    let output = String::from_utf8_lossy(&output.stdout).to_string();
+    let (status, reply) = output.split_once('\n')?;
+    if ! status.starts_with("OK") {
+        return None
+    }
+    Some(reply.to_string())
+}
+
+pub fn reset_network() -> bool {
+    /*!
+    Remove taps and address leases of VM instances which are
+    no longer running. If no instance is left on the flake
+    bridge, also remove the bridge and its NAT rule
+    !*/
+    let mut ok = true;
+    let mut instances: Vec<String> = vmnet::taps().into_iter()
+        .map(|tap| tap.instance)
+        .collect();
+    match vmnet::leases() {
+        Ok(leases) => {
+            instances.extend(leases.into_iter().map(|(_, instance)| instance))
+        },
+        Err(error) => {
+            error!("Failed to read address leases: {}", error);
+            ok = false
+        }
+    }
+    instances.sort();
+    instances.dedup();
+
+    for instance in instances {
+        if instance_running(&instance) {
+            info!("Keeping network of running instance {}", instance);
+            continue
+        }
+        info!("Removing network of instance {}", instance);
+        if let Err(error) = vmnet::teardown(&instance) {
+            error!("Failed to remove network of {}: {}", instance, error);
+            ok = false
+        }
+    }
+
+    let bridge_in_use = vmnet::taps().iter()
+        .any(|tap| tap.bridge.as_deref() == Some(vmnet::BRIDGE));
+    if ! bridge_in_use {
+        info!("Removing bridge {}", vmnet::BRIDGE);
+        if let Err(error) = vmnet::remove_bridge() {
+            error!("Failed to remove bridge {}: {}", vmnet::BRIDGE, error);
+            ok = false
+        }
+    }
+    ok
+}
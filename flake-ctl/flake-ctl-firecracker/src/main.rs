@@ -104,6 +104,34 @@ async fn main() -> Result<ExitCode, Box<dyn std::error::Error>> {
                         );
                     }
                 },
+                // network
+                cli::Firecracker::Network { reset } => {
+                    if *reset {
+                        if ! firecracker::reset_network() {
+                            return Ok(ExitCode::FAILURE)
+                        }
+                    } else {
+                        firecracker::print_network();
+                    }
+                },
+                // logs
+                cli::Firecracker::Logs { flake, metrics, tail } => {
+                    if ! firecracker::print_logs(flake, *metrics, *tail) {
+                        return Ok(ExitCode::FAILURE)
+                    }
+                },
+                // stop
+                cli::Firecracker::Stop { flake, force } => {
+                    if ! firecracker::stop_instance(flake, *force) {
+                        return Ok(ExitCode::FAILURE)
+                    }
+                },
+                // sessions
+                cli::Firecracker::Sessions { flake } => {
+                    if ! firecracker::print_sessions(flake) {
+                        return Ok(ExitCode::FAILURE)
+                    }
+                },
                 cli::Firecracker::About => {
                     println!("Manage firecracker micro vm flakes;ENGINE");
                 }
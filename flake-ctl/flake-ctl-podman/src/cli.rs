@@ -2,10 +2,10 @@ use std::{fs::OpenOptions, path::{Path, PathBuf}};
 
 use anyhow::{bail, Context, Result};
 use clap::{ArgGroup, Args, Parser};
-use flakes::paths::flake_dir_from;
+use flakes::registry::{flake_name, FlakeRegistry, Scope};
+use log::info;
 
 use crate::{
-    app,
     app_config::{AppConfig, AppContainer, AppContainerRuntime, AppInclude},
     defaults, podman,
 };
@@ -186,8 +186,14 @@ impl Register {
             bail!("Layer(s) specified without a base");
         }
 
-        app::init(&self.root, &app)?;
-        app::register(&self.root, &app, self.target.as_ref().unwrap_or(&app), defaults::PODMAN_PILOT)?;
+        let registry = FlakeRegistry::new(Some(&self.root)).with_scope(Scope::from_env());
+        let target = self.target.as_ref().unwrap_or(&app);
+        registry.init(Path::new(&app)).context("Could not initialize flake registry")?;
+
+        info!("Registering application: {}", app);
+        registry
+            .register(Path::new(&app), Path::new(target), Path::new(defaults::PODMAN_PILOT))
+            .context(format!("Could not register {app}"))?;
 
         let config = AppConfig {
             include: AppInclude { tar: self.include_tar },
@@ -206,13 +212,12 @@ impl Register {
             },
         };
 
-        let base_name = Path::new(&app).file_stem().unwrap_or_default();
-
         serde_yaml::to_writer(
             OpenOptions::new()
                 .write(true)
                 .create(true)
-                .open(flake_dir_from(Some(self.root)).join(base_name).with_extension("yaml"))
+                .truncate(true)
+                .open(registry.config_file(&flake_name(Path::new(&app))?))
                 .context("Could not open yaml file")?,
             &config,
         )?;
@@ -29,7 +29,6 @@ use std::{process::{exit, ExitCode}, path::Path};
 
 pub mod cli;
 pub mod podman;
-pub mod app;
 // pub mod deb;
 pub mod app_config;
 pub mod defaults;
@@ -45,7 +44,7 @@ fn main() -> Result<ExitCode> {
         Podman::Pull { uri } => exit(podman::pull(&uri)),
         Podman::Load { oci } => exit(podman::load(&oci)),
         Podman::Register(reg) => reg.call(),
-        Podman::Remove { app: Some(app), root, .. } => app::remove(&root, Path::new(&app)),
+        Podman::Remove { app: Some(app), root, .. } => podman::remove(&root, Path::new(&app)),
         Podman::Remove { container: Some(container), root, .. } => podman::purge_container(&root, &container),
         Podman::Remove { .. } => unreachable!(),
         cli::Podman::BuildDeb { oci, app, repo, arch } => {
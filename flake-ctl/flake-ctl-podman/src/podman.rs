@@ -22,16 +22,16 @@
 // SOFTWARE.
 //
 use anyhow::{bail, Context, Result};
-use flakes::config::{load_from_target, FLAKE_DIR};
 use flakes::paths::PathExt;
+use flakes::registry::{FlakeRegistry, Scope};
 use log::{error, info, warn};
 use std::fs;
 use std::path::Path;
 use std::process::{Command, Stdio};
 use tempfile::tempdir;
 
+use crate::app_config;
 use crate::defaults;
-use crate::{app, app_config};
 
 pub fn pull(uri: &String) -> i32 {
     /*!
@@ -155,12 +155,13 @@ pub fn purge_container(root: &Path, container: &str) -> Result<()> {
     container and also delete the container from the local
     registry
     !*/
-    for app_name in app::app_names() {
-        let config_file = Path::new(defaults::FLAKE_DIR).join(app_name).with_extension("yaml");
+    let registry = FlakeRegistry::new(Some(root)).with_scope(Scope::from_env());
+    for app_name in registry.app_names() {
+        let config_file = registry.config_file(&app_name);
         match app_config::AppConfig::from_file(&config_file) {
             Ok(app_conf) if app_conf.container.name == container => {
                 let path = &app_conf.container.host_app_path;
-                app::remove(root, Path::new(path)).map_err(|err| warn!("Could not delete {path}: {err}")).ok();
+                remove(root, Path::new(path)).map_err(|err| warn!("Could not delete {path}: {err}")).ok();
             }
             Ok(_) => (),
             Err(error) => warn!("Error in flake \"{}\": {}", config_file.to_string_lossy(), error),
@@ -170,6 +171,17 @@ pub fn purge_container(root: &Path, container: &str) -> Result<()> {
     Ok(())
 }
 
+pub fn remove(root: &Path, app: &Path) -> Result<()> {
+    /*!
+    Delete application link and config files
+    !*/
+    info!("Removing application: {}", app.to_string_lossy());
+    FlakeRegistry::new(Some(root))
+        .with_scope(Scope::from_env())
+        .remove(app, Path::new(defaults::PODMAN_PILOT))
+        .context(format!("Could not remove {}", app.to_string_lossy()))
+}
+
 pub fn print_container_info(container: &str) -> Result<()> {
     /*!
     Print app info file
@@ -191,7 +203,11 @@ pub fn print_container_info(container: &str) -> Result<()> {
 }
 
 pub fn export(root: &Path, flake: &str, target: &Path) -> Result<()> {
-    let config = load_from_target(Some(root), &FLAKE_DIR.join(flake)).context("failed to read flake config")?;
+    let name = Path::new(flake).file_name().and_then(|name| name.to_str()).unwrap_or(flake);
+    let config = FlakeRegistry::new(Some(root))
+        .with_scope(Scope::from_env())
+        .load(name)
+        .context("failed to read flake config")?;
     if config.engine().pilot() != "podman" {
         bail!("Can only export podman flakes. This is a {} flake", config.engine().pilot())
     }
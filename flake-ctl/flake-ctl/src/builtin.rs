@@ -1,16 +1,106 @@
-use std::{fs, process::ExitCode};
+use std::process::ExitCode;
 
-use itertools::Itertools;
+use flakes::{
+    config::itf::FlakeCfgResources,
+    registry::{FlakeRegistry, Scope},
+};
+use serde_yaml::Value;
 
 pub fn list() -> ExitCode {
-    match fs::read_dir("/usr/share/flakes") {
-        Ok(dir) => dir
-            .filter_map(Result::ok)
-            .filter_map(|x| x.path().file_stem().map(ToOwned::to_owned))
-            .unique()
-            .for_each(|x| println!("{}", x.to_str().unwrap_or_default())),
-        Err(_) => return ExitCode::FAILURE,
+    let registry = FlakeRegistry::default().with_scope(Scope::from_env());
+    if !registry.dir().is_dir() {
+        return ExitCode::FAILURE;
     }
 
+    registry.app_names().iter().for_each(|x| println!("{x}"));
+
     ExitCode::SUCCESS
 }
+
+/// Print the effective configuration of a flake, marking values provided by the user
+pub fn show(name: &str) -> ExitCode {
+    let registry = match Scope::from_env() {
+        Scope::User => FlakeRegistry::user(),
+        Scope::System => FlakeRegistry::lookup(name),
+    };
+
+    match registry.load_raw(name) {
+        Ok((cfg, user_keys)) => {
+            print_value(&cfg, "", 0, &user_keys, false);
+            if let Ok(cfg) = registry.load(name) {
+                print_resources(cfg.runtime().resources());
+            }
+            ExitCode::SUCCESS
+        }
+        Err(error) => {
+            eprintln!("Could not load flake \"{name}\": {error}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+const USER_MARKER: &str = "  # user";
+
+/// Print the effective resource limits as a trailing comment
+fn print_resources(resources: &FlakeCfgResources) {
+    if resources.is_empty() {
+        println!("# Resource limits: none");
+        return;
+    }
+
+    println!("# Resource limits:");
+    if let Some(cpus) = resources.cpus() {
+        println!("#   cpus: {cpus}");
+    }
+    if let Some(shares) = resources.cpu_shares() {
+        println!("#   cpu_shares: {shares}");
+    }
+    if let Some(memory) = resources.memory() {
+        match memory % (1 << 20) {
+            0 => println!("#   memory: {} MiB", memory >> 20),
+            _ => println!("#   memory: {memory} bytes"),
+        }
+    }
+    if let Some(pids) = resources.pids() {
+        println!("#   pids: {pids}");
+    }
+    if let Some(weight) = resources.io_weight() {
+        println!("#   io_weight: {weight}");
+    }
+}
+
+/// Print a yaml fragment, indenting all lines after the first one
+fn print_yaml(prefix: &str, value: &Value, indent: usize, marker: &str) {
+    let yaml = serde_yaml::to_string(value).unwrap_or_default();
+    let mut lines = yaml.trim_end().lines();
+    println!("{prefix}{}{marker}", lines.next().unwrap_or_default());
+    lines.for_each(|line| println!("{:indent$}{line}", ""));
+}
+
+fn print_value(value: &Value, path: &str, indent: usize, user_keys: &[String], user: bool) {
+    let Value::Mapping(map) = value else {
+        print_yaml(&format!("{:indent$}", ""), value, indent, if user { USER_MARKER } else { "" });
+        return;
+    };
+
+    for (key, value) in map {
+        let key = key.as_str().map(str::to_owned).unwrap_or_else(|| serde_yaml::to_string(key).unwrap_or_default().trim_end().to_owned());
+        let sub_path = if path.is_empty() { key.clone() } else { format!("{path}.{key}") };
+        let user = user || user_keys.contains(&sub_path);
+        let marker = if user { USER_MARKER } else { "" };
+
+        match value {
+            Value::Mapping(m) if !m.is_empty() => {
+                println!("{:indent$}{key}:{marker}", "");
+                print_value(value, &sub_path, indent + 2, user_keys, user);
+            }
+            Value::Sequence(s) if !s.is_empty() => {
+                println!("{:indent$}{key}:{marker}", "");
+                for item in s {
+                    print_yaml(&format!("{:indent$}- ", ""), item, indent + 2, "");
+                }
+            }
+            _ => print_yaml(&format!("{:indent$}{key}: ", ""), value, indent + 2, marker),
+        }
+    }
+}
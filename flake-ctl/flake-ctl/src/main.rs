@@ -6,9 +6,10 @@ use std::{
     process::{Command, ExitCode},
 };
 
-use builtin::list;
-use clap::arg;
+use builtin::{list, show};
+use clap::{arg, ArgMatches};
 use colored::Colorize;
+use flakes::registry::SCOPE_ENV;
 
 fn main() -> ExitCode {
     let addons = addons::find_addons();
@@ -18,7 +19,13 @@ fn main() -> ExitCode {
         .arg_required_else_help(true)
         .version("2.0.0")
         .about("Manage Flake Applications")
-        .subcommand(clap::Command::new("list").about(Some("List all registered flakes")));
+        .arg(arg!(--user "Manage the flakes of the current user instead of the system ones"))
+        .subcommand(clap::Command::new("list").about(Some("List all registered flakes")))
+        .subcommand(
+            clap::Command::new("show")
+                .about(Some("Show the configuration of a flake, marking values provided by the user"))
+                .arg(arg!(<flake> "name of the flake")),
+        );
 
     let mut after_help = String::new();
     
@@ -44,16 +51,24 @@ fn main() -> ExitCode {
 
     args = args.after_help(after_help);
 
-    match args.get_matches().subcommand() {
+    let matches = args.get_matches();
+    if matches.get_flag("user") {
+        // Picked up by the builtins and inherited by all addons
+        env::set_var(SCOPE_ENV, "user");
+    }
+
+    match matches.subcommand() {
         Some(("list", _)) => list(),
-        Some((name, _)) => external(name),
+        Some(("show", sub)) => show(sub.get_one::<String>("flake").unwrap()),
+        Some((name, sub)) => external(name, sub),
         _ => ExitCode::FAILURE,
     }
 }
 
-fn external(name: &str) -> ExitCode {
+fn external(name: &str, matches: &ArgMatches) -> ExitCode {
     let full_name = format!("flake-ctl-{name}");
-    match Command::new(full_name).args(env::args().skip(2)).status() {
+    let args = matches.get_many::<String>("cmd").into_iter().flatten();
+    match Command::new(full_name).args(args).status() {
         Ok(output) => (output.code().unwrap_or_default() as u8).into(),
         Err(error) => {
             match error.kind() {
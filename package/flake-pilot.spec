@@ -174,11 +174,13 @@ install -m 755 %{buildroot}/usr/sbin/sci \
 %defattr(-,root,root)
 %dir /usr/share/flakes
 %dir /etc/flakes
+%config(noreplace) /etc/flakes/overrides.yaml
 /usr/bin/flake-ctl
 /usr/bin/flake-ctl-podman
 /usr/bin/flake-ctl-firecracker
 %doc /usr/share/man/man8/flake-ctl.8.gz
 %doc /usr/share/man/man8/flake-ctl-list.8.gz
+%doc /usr/share/man/man8/flake-ctl-show.8.gz
 
 %files -n flake-pilot-podman
 %config /etc/flakes/podman.yaml
@@ -200,6 +202,10 @@ install -m 755 %{buildroot}/usr/sbin/sci \
 %config /etc/flakes/firecracker.json
 %doc /usr/share/man/man8/flake-ctl-firecracker-pull.8.gz
 %doc /usr/share/man/man8/flake-ctl-firecracker-remove.8.gz
+%doc /usr/share/man/man8/flake-ctl-firecracker-network.8.gz
+%doc /usr/share/man/man8/flake-ctl-firecracker-logs.8.gz
+%doc /usr/share/man/man8/flake-ctl-firecracker-stop.8.gz
+%doc /usr/share/man/man8/flake-ctl-firecracker-sessions.8.gz
 %doc /usr/share/man/man8/flake-ctl-firecracker-register.8.gz
 /usr/bin/firecracker-service
 /usr/bin/firecracker-pilot
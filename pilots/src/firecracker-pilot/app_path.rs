@@ -31,7 +31,7 @@ use std::fs;
 use yaml_rust::Yaml;
 use yaml_rust::YamlLoader;
 
-use crate::defaults;
+use flakes::registry::FlakeRegistry;
 
 pub fn program_abs_path() -> String {
     /*!
@@ -55,27 +55,27 @@ pub fn basename(program_path: &String) -> String {
     program_name
 }
 
-pub fn program_config_file(program_basename: &String) -> String {
+pub fn program_config_file(program_basename: &str) -> String {
     /*!
     Provide expected config file path for the given program_basename
     !*/
-    let config_file = &format!(
-        "{}/{}.yaml", defaults::FIRECRACKER_FLAKE_DIR, program_basename
-    );
-    config_file.to_string()
+    FlakeRegistry::lookup(program_basename)
+        .config_file(program_basename)
+        .to_string_lossy()
+        .to_string()
 }
 
-pub fn program_config_dir(program_basename: &String) -> String {
+pub fn program_config_dir(program_basename: &str) -> String {
     /*!
     Provide expected config directory for the given program_basename
     !*/
-    let config_dir = &format!(
-        "{}/{}.d", defaults::FIRECRACKER_FLAKE_DIR, program_basename
-    );
-    config_dir.to_string()
+    FlakeRegistry::lookup(program_basename)
+        .config_dir(program_basename)
+        .to_string_lossy()
+        .to_string()
 }
 
-pub fn program_config(program_basename: &String) -> Vec<Yaml> {
+pub fn program_config(program_basename: &str) -> Vec<Yaml> {
     /*!
     Read vm runtime configuration for given program
 
@@ -1,14 +1,27 @@
-use flakes::user::User;
+use flakes::{
+    config::{
+        itf::{FlakeCfgEnv, FlakeCfgNetwork, FlakeCfgResources, FlakeCfgSetup, Identity, NetworkMode},
+        pilots::fc::{validate_machine, Balloon, CpuTemplate, Entropy, Mmds, NetworkRateLimiter, RateLimiter},
+        placeholders::{self, Placeholders},
+        setup::{parse_setup, HostIntegration},
+    },
+    registry::FlakeRegistry,
+    user::User,
+};
 use lazy_static::lazy_static;
 use serde::Deserialize;
 use strum::Display;
 
-use std::{env, fs, path::PathBuf};
+use std::{collections::BTreeMap, env, path::PathBuf};
 
-use crate::defaults;
+use crate::firecracker::{get_meta_name, load_template};
 
 lazy_static! {
     static ref CONFIG: Config<'static> = load_config();
+    static ref PLACEHOLDERS: Placeholders = {
+        let program_name = get_base_path().file_name().unwrap().to_str().unwrap().to_string();
+        Placeholders::resolve(&program_name, &get_meta_name(&program_name))
+    };
 }
 
 /// Returns the config singleton
@@ -32,27 +45,15 @@ fn load_config() -> Config<'static> {
        └── program_name.yaml
 
     Config files below program_name.d are read in alpha sort order
-    and attached to the master program_name.yaml file. The result
-    is send to the Yaml parser
+    and merged into the master program_name.yaml file by the common
+    flake config loader. Overlays for other hosts are skipped. The
+    result is send to the Yaml parser
     !*/
     let base_path = get_base_path();
     let base_path = base_path.file_name().unwrap().to_str().unwrap();
-    let base_yaml = fs::read_to_string(config_file(base_path));
+    let (full_yaml, _) = FlakeRegistry::lookup(base_path).load_raw(base_path).unwrap();
 
-    let mut extra_yamls: Vec<_> = fs::read_dir(config_dir(base_path))
-        .into_iter()
-        .flatten()
-        .flatten()
-        .map(|x| x.path())
-        .collect();
-
-    extra_yamls.sort();
-
-    let full_yaml: String = base_yaml
-        .into_iter()
-        .chain(extra_yamls.into_iter().flat_map(fs::read_to_string))
-        .collect();
-    config_from_str(&full_yaml)
+    config_from_str(&serde_yaml::to_string(&full_yaml).unwrap())
 }
 
 fn config_from_str(input: &str) -> Config<'static> {
@@ -68,15 +69,22 @@ fn config_from_str(input: &str) -> Config<'static> {
     // Safety: This does not cause a reocurring memory leak since `load_config` is only called once
     let content = Box::leak(buffer.into_boxed_str());
 
-    serde_yaml::from_str(content).unwrap()
-}
-
-fn config_file(program: &str) -> String {
-    format!("{}/{}.yaml", defaults::FIRECRACKER_FLAKE_DIR, program)
-}
-
-fn config_dir(program: &str) -> String {
-    format!("{}/{}.d", defaults::FIRECRACKER_FLAKE_DIR, program)
+    let mut config: Config = serde_yaml::from_str(content).unwrap();
+    config.setup = parse_setup(&serde_yaml::from_str(content).unwrap()).unwrap();
+    let runtime = config.runtime();
+    runtime.firecracker.validate().unwrap();
+    if runtime.firecracker.mmds.is_some() && *runtime.network.mode() == NetworkMode::None {
+        panic!("The MMDS data store needs a network")
+    }
+    if config.runtime().expand {
+        for value in config.vm.target_app_path.iter().chain(config.include.tar.iter().flatten()) {
+            placeholders::validate(value).unwrap();
+        }
+        for value in config.runtime().env.set().values() {
+            placeholders::validate(value).unwrap();
+        }
+    }
+    config
 }
 
 #[derive(Deserialize)]
@@ -85,6 +93,10 @@ pub struct Config<'a> {
     pub vm: VMSection<'a>,
     #[serde(borrow)]
     pub include: IncludeSection<'a>,
+
+    /// Host integrations, shared with all other pilots
+    #[serde(skip)]
+    pub setup: FlakeCfgSetup,
 }
 
 impl<'a> Config<'a> {
@@ -92,8 +104,37 @@ impl<'a> Config<'a> {
         self.vm.runtime.as_ref().cloned().unwrap_or_default()
     }
 
-    pub fn tars(&self) -> Vec<&'a str> {
-        self.include.tar.as_ref().cloned().unwrap_or_default()
+    pub fn tars(&self) -> Vec<String> {
+        self.include.tar.iter().flatten().map(|tar| self.expand(tar)).collect()
+    }
+
+    /// Get the environment of the app from the caller environment and
+    /// the host trust data, with placeholders resolved in the set values if enabled
+    pub fn env(&self) -> Vec<(String, String)> {
+        let env = self.runtime().env;
+        let env = if self.runtime().expand { env.expanded(&PLACEHOLDERS).unwrap() } else { env };
+
+        // environment of the host trust data, e.g. the proxy
+        let mut vars: BTreeMap<String, String> = self
+            .setup
+            .trust_integrations()
+            .into_iter()
+            .filter_map(|i| match i {
+                HostIntegration::Env(name, value) => Some((name, value)),
+                _ => None,
+            })
+            .collect();
+        vars.extend(env.resolve(env::vars()));
+        vars.into_iter().collect()
+    }
+
+    /// Resolve placeholders like "${HOME}" in the given value,
+    /// if enabled by `vm.runtime.expand`
+    pub fn expand(&self, value: &str) -> String {
+        if !self.runtime().expand {
+            return value.to_owned();
+        }
+        PLACEHOLDERS.expand(value).unwrap()
     }
 }
 
@@ -141,6 +182,36 @@ pub struct RuntimeSection<'a> {
     #[serde(default)]
     pub resume: bool,
 
+    /// Resolve placeholders like "${HOME}" in the target
+    /// app path and the tar includes at launch time
+    ///
+    /// Default: false
+    #[serde(default)]
+    pub expand: bool,
+
+    /// Run the app inside of the VM with the uid and gid
+    /// of the calling user (host, user) or as root (image)
+    ///
+    /// Default: image
+    #[serde(default)]
+    pub identity: Identity,
+
+    /// Environment of the app inside of the VM, made of
+    /// explicitly set values and allow-listed caller variables
+    #[serde(default)]
+    pub env: FlakeCfgEnv,
+
+    /// Resource limits of the VM. CPUs and memory size the
+    /// machine, unless given by the firecracker section, and
+    /// all limits apply to a cgroup around the firecracker process
+    #[serde(default)]
+    pub resources: FlakeCfgResources,
+
+    /// Network of the VM. Without network (none) the VM gets
+    /// no tap device and the network boot args are dropped
+    #[serde(default)]
+    pub network: FlakeCfgNetwork,
+
     pub firecracker: EngineSection<'a>,
 }
 
@@ -165,11 +236,247 @@ pub struct EngineSection<'a> {
     pub initrd_path: Option<&'a str>,
 
     pub boot_args: Vec<&'a str>,
+
+    /// Connections relayed between the host and the guest
+    /// over the vsock device, usable without a network
+    #[serde(default)]
+    pub forward: Vec<Forward>,
+
+    /// Extra data drives, mounted by sci in the guest
+    #[serde(default)]
+    pub drives: Vec<Drive>,
+
+    /// Simultaneous multithreading of the vCPUs
+    pub smt: Option<bool>,
+
+    /// Static CPU template, masking CPU features of the host
+    pub cpu_template: Option<CpuTemplate>,
+
+    /// Balloon device to reclaim guest memory
+    pub balloon: Option<Balloon>,
+
+    /// Virtio entropy device
+    pub entropy: Option<Entropy>,
+
+    /// Rate limiter of all drives, unless set per data drive
+    pub drive_rate_limiter: Option<RateLimiter>,
+
+    /// Rate limiters of the network interface
+    pub network_rate_limiter: Option<NetworkRateLimiter>,
+
+    /// MMDS data store, requires a network
+    pub mmds: Option<Mmds>,
+
+    /// Firecracker JSON template of this flake, in place
+    /// of the default /etc/flakes/firecracker.json
+    pub template: Option<PathBuf>,
+
+    /// Launch firecracker through the jailer
+    pub jailer: Option<Jailer>,
+}
+
+impl<'a> EngineSection<'a> {
+    /// Check the machine options beyond their types
+    pub fn validate(&self) -> Result<(), String> {
+        validate_machine(
+            self.mem_size_mib.map(|mem| mem as u64),
+            self.balloon.as_ref(),
+            self.entropy.as_ref(),
+            self.drive_rate_limiter.as_ref(),
+            self.network_rate_limiter.as_ref(),
+        )?;
+        for drive in &self.drives {
+            if let Some(rate_limiter) = &drive.rate_limiter {
+                rate_limiter.validate("drive")?;
+            }
+        }
+        if let Some(template) = &self.template {
+            load_template(template)?;
+        }
+        if let Some(jailer) = &self.jailer {
+            if !matches!(jailer.cgroup_version, None | Some(1) | Some(2)) {
+                return Err("The jailer cgroup_version must be 1 or 2".to_string());
+            }
+            if let Seccomp::Filter(filter) = &jailer.seccomp {
+                if !filter.is_absolute() {
+                    return Err(format!("Seccomp filter {} must be an absolute path", filter.display()));
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Isolation of firecracker by the jailer, in a chroot
+/// per instance with its own uid, gid and cgroups
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Jailer {
+    /// uid of firecracker in the jail
+    pub uid: u32,
+
+    /// gid of firecracker in the jail
+    pub gid: u32,
+
+    /// Base directory of the jails
+    ///
+    /// Default: /srv/jailer
+    pub chroot_base_dir: Option<PathBuf>,
+
+    /// cgroup version of the host, 1 or 2
+    ///
+    /// Default: 1
+    pub cgroup_version: Option<u8>,
+
+    /// Parent cgroup of the jails
+    ///
+    /// Default: firecracker
+    pub parent_cgroup: Option<String>,
+
+    /// cgroup settings of the jail, e.g. cpu.max: "50000 100000"
+    #[serde(default)]
+    pub cgroups: BTreeMap<String, String>,
+
+    /// Seccomp filter of firecracker: default, none or a filter file
+    ///
+    /// Default: default
+    #[serde(default)]
+    pub seccomp: Seccomp,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Deserialize)]
+#[serde(from = "String")]
+pub enum Seccomp {
+    /// The filter built into firecracker
+    #[default]
+    Default,
+
+    /// No filter at all, for debugging only
+    None,
+
+    /// A compiled BPF filter file
+    Filter(PathBuf),
+}
+
+impl From<String> for Seccomp {
+    fn from(value: String) -> Self {
+        match value.as_str() {
+            "default" => Seccomp::Default,
+            "none" => Seccomp::None,
+            _ => Seccomp::Filter(PathBuf::from(value)),
+        }
+    }
+}
+
+/// An extra data drive of the VM, either a disk image or a
+/// host directory which is packed into a disk image before boot
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Drive {
+    /// Disk image on the host
+    pub path: Option<PathBuf>,
+
+    /// Directory on the host. Changes of read-write drives
+    /// are synced back to it after the VM has exited
+    pub dir: Option<PathBuf>,
+
+    /// Mount point in the guest
+    pub mount: PathBuf,
+
+    /// Default: false
+    #[serde(default)]
+    pub read_only: bool,
+
+    /// Default: cache_type of the firecracker section
+    pub cache_type: Option<CacheType>,
+
+    /// Size of the image of a host directory
+    ///
+    /// Default: size of the directory plus a reserve
+    pub size: Option<String>,
+
+    /// Default: drive_rate_limiter of the firecracker section
+    pub rate_limiter: Option<RateLimiter>,
+}
+
+/// A connection relay between an endpoint on the host
+/// and a TCP port on the loopback of the guest
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Forward {
+    /// Endpoint on the host: unix:<path> or tcp:[<address>:]<port>
+    pub host: HostEndpoint,
+
+    /// TCP port on the loopback of the guest
+    pub guest: u16,
+
+    /// Side which accepts the connections
+    ///
+    /// Default: host
+    #[serde(default)]
+    pub to: ForwardTarget,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Display)]
+#[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase")]
+pub enum ForwardTarget {
+    /// The guest connects to a service of the host
+    #[default]
+    Host,
+
+    /// The host connects to a service of the guest
+    Guest,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub enum HostEndpoint {
+    Unix(PathBuf),
+    Tcp(String, u16),
+}
+
+impl TryFrom<String> for HostEndpoint {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        if let Some(path) = value.strip_prefix("unix:") {
+            if path.starts_with('/') {
+                return Ok(HostEndpoint::Unix(PathBuf::from(path)));
+            }
+        } else if let Some(address) = value.strip_prefix("tcp:") {
+            let (host, port) = address.rsplit_once(':').unwrap_or(("127.0.0.1", address));
+            if let Ok(port) = port.parse::<u16>() {
+                return Ok(HostEndpoint::Tcp(host.to_string(), port));
+            }
+        }
+        Err(format!("Invalid host endpoint \"{}\", use unix:<path> or tcp:[<address>:]<port>", value))
+    }
+}
+
+impl HostEndpoint {
+    /// socat address to accept connections on this endpoint
+    pub fn listen_address(&self) -> String {
+        match self {
+            HostEndpoint::Unix(path) => format!("UNIX-LISTEN:{},unlink-early,fork", path.display()),
+            HostEndpoint::Tcp(host, port) => format!("TCP-LISTEN:{},bind={},reuseaddr,fork", port, host),
+        }
+    }
+
+    /// socat address to connect to this endpoint
+    pub fn connect_address(&self) -> String {
+        match self {
+            HostEndpoint::Unix(path) => format!("UNIX-CONNECT:{}", path.display()),
+            HostEndpoint::Tcp(host, port) => format!("TCP:{}:{}", host, port),
+        }
+    }
 }
 
 #[derive(Debug, Deserialize, Clone, Display)]
 pub enum CacheType {
     Writeback,
+    Unsafe,
 }
 
 impl Default for CacheType {
@@ -180,7 +487,8 @@ impl Default for CacheType {
 
 #[cfg(test)]
 mod test {
-    use crate::config::config_file;
+    use crate::config::{ForwardTarget, HostEndpoint, Seccomp};
+    use flakes::registry::FlakeRegistry;
 
     use super::config_from_str;
 
@@ -213,9 +521,140 @@ vm:
         assert_eq!(cfg.vm.name, "Dio");
     }
 
+    #[test]
+    fn setup_config() {
+        let cfg = config_from_str(
+            r#"vm:
+ name: JoJo
+ host_app_path: /myapp
+include:
+ tar: ~
+setup:
+ mounts:
+  - source: /var/lib/data.img
+    mode: rw
+"#,
+        );
+        assert_eq!(cfg.setup.mounts().len(), 1);
+    }
+
+    #[test]
+    fn forward_config() {
+        let cfg = config_from_str(
+            r#"vm:
+ name: JoJo
+ host_app_path: /myapp
+ runtime:
+  firecracker:
+   rootfs_image_path: /rootfs
+   kernel_image_path: /kernel
+   boot_args: []
+   forward:
+    - host: unix:/run/license.sock
+      guest: 27000
+    - host: tcp:8080
+      guest: 80
+      to: guest
+include:
+ tar: ~
+"#,
+        );
+        let forward = cfg.runtime().firecracker.forward;
+        assert_eq!(forward.len(), 2);
+        assert_eq!(forward[0].to, ForwardTarget::Host);
+        assert_eq!(forward[0].host.connect_address(), "UNIX-CONNECT:/run/license.sock");
+        assert_eq!(forward[1].host, HostEndpoint::Tcp("127.0.0.1".to_string(), 8080));
+        assert_eq!(forward[1].host.listen_address(), "TCP-LISTEN:8080,bind=127.0.0.1,reuseaddr,fork");
+    }
+
+    #[test]
+    fn drives_config() {
+        let cfg = config_from_str(
+            r#"vm:
+ name: JoJo
+ host_app_path: /myapp
+ runtime:
+  firecracker:
+   rootfs_image_path: /rootfs
+   kernel_image_path: /kernel
+   boot_args: []
+   drives:
+    - path: /var/lib/data.img
+      mount: /data
+      read_only: true
+      cache_type: Unsafe
+    - dir: /home/user/project
+      mount: /project
+      size: 1GiB
+include:
+ tar: ~
+"#,
+        );
+        let drives = cfg.runtime().firecracker.drives;
+        assert_eq!(drives.len(), 2);
+        assert!(drives[0].read_only);
+        assert_eq!(drives[0].cache_type.as_ref().unwrap().to_string(), "Unsafe");
+        assert_eq!(drives[1].dir, Some(std::path::PathBuf::from("/home/user/project")));
+        assert!(!drives[1].read_only);
+        assert_eq!(drives[1].size.as_deref(), Some("1GiB"));
+    }
+
+    #[test]
+    #[should_panic(expected = "does not fit")]
+    fn machine_config_balloon_too_large() {
+        config_from_str(
+            r#"vm:
+ name: JoJo
+ host_app_path: /myapp
+ runtime:
+  firecracker:
+   rootfs_image_path: /rootfs
+   kernel_image_path: /kernel
+   boot_args: []
+   mem_size_mib: 1024
+   balloon:
+    amount_mib: 2048
+include:
+ tar: ~
+"#,
+        );
+    }
+
+    #[test]
+    fn jailer_config() {
+        let cfg = config_from_str(
+            r#"vm:
+ name: JoJo
+ host_app_path: /myapp
+ runtime:
+  firecracker:
+   rootfs_image_path: /rootfs
+   kernel_image_path: /kernel
+   boot_args: []
+   jailer:
+    uid: 123
+    gid: 100
+    cgroup_version: 2
+    cgroups:
+     cpu.max: "50000 100000"
+    seccomp: /etc/flakes/seccomp.bpf
+include:
+ tar: ~
+"#,
+        );
+        let jailer = cfg.runtime().firecracker.jailer.unwrap();
+        assert_eq!(jailer.uid, 123);
+        assert_eq!(jailer.cgroup_version, Some(2));
+        assert_eq!(jailer.cgroups.get("cpu.max").map(String::as_str), Some("50000 100000"));
+        assert_eq!(jailer.seccomp, Seccomp::Filter(std::path::PathBuf::from("/etc/flakes/seccomp.bpf")));
+    }
+
     #[test]
     fn test_program_config_file() {
-        let config_file = config_file("app");
-        assert_eq!("/usr/share/flakes/app.yaml", config_file);
+        // an empty user registry, whatever flakes the caller has
+        let data_home = tempfile::tempdir().unwrap();
+        std::env::set_var("XDG_DATA_HOME", data_home.path());
+        let config_file = FlakeRegistry::lookup("app").config_file("app");
+        assert_eq!(std::path::PathBuf::from("/usr/share/flakes/app.yaml"), config_file);
     }
 }
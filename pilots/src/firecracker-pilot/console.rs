@@ -0,0 +1,165 @@
+//
+// Copyright (c) 2022 Elektrobit Automotive GmbH
+//
+// This file is part of flake-pilot
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+//
+use crate::defaults;
+use flakes::vmlog::{self, RingLog};
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::{self, ErrorKind, Read, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::{thread, time};
+
+type SharedLog = Arc<Mutex<Option<RingLog>>>;
+
+/// Create the FIFOs firecracker writes its log and metrics to. They
+/// are writable for everybody, such that firecracker can open them
+/// as any user and from inside of a jail.
+pub fn create_fifos(instance: &str) -> bool {
+    if let Err(error) = fs::create_dir_all(vmlog::LOG_DIR) {
+        error!("Failed to create {}: {}", vmlog::LOG_DIR, error);
+        return false;
+    }
+    for fifo in [vmlog::log_fifo(instance), vmlog::metrics_fifo(instance)] {
+        let _ = fs::remove_file(&fifo);
+        let mut mkfifo = Command::new("mkfifo");
+        mkfifo.arg("-m").arg("0622").arg(&fifo);
+        match mkfifo.output() {
+            Ok(output) if output.status.success() => {}
+            Ok(output) => {
+                error!("Failed to create {}: {}", fifo.display(), String::from_utf8_lossy(&output.stderr));
+                return false;
+            }
+            Err(error) => {
+                error!("Failed to execute mkfifo: {:?}", error);
+                return false;
+            }
+        }
+    }
+    true
+}
+
+/// Spawn the log collector of an instance, reading the console
+/// from its stdin. With echo the console is passed on to stdout.
+pub fn spawn_collector(instance: &str, console: Stdio, echo: bool) -> Option<std::process::Child> {
+    let mut collector = Command::new(env::current_exe().ok()?);
+    collector.env(defaults::LOG_COLLECTOR, instance).stdin(console);
+    if echo {
+        collector.arg("--echo");
+    } else {
+        collector.stdout(Stdio::null()).stderr(Stdio::null());
+    }
+    match collector.spawn() {
+        Ok(child) => Some(child),
+        Err(error) => {
+            error!("Failed to start the log collector: {:?}", error);
+            None
+        }
+    }
+}
+
+/// Run the log collector of an instance
+///
+/// The console is read from stdin until firecracker has exited,
+/// the firecracker log and metrics from their FIFOs. Output is
+/// still drained if the ring logs can not be written, such that
+/// firecracker never blocks on the console.
+pub fn collect(instance: &str) -> i32 {
+    let echo = env::args().any(|arg| arg == "--echo");
+    let console_log: SharedLog = Arc::new(Mutex::new(open_log(&vmlog::console_log(instance))));
+    let metrics_log: SharedLog = Arc::new(Mutex::new(open_log(&vmlog::metrics_log(instance))));
+    let done = Arc::new(AtomicBool::new(false));
+
+    let readers = [(vmlog::log_fifo(instance), console_log.clone()), (vmlog::metrics_fifo(instance), metrics_log)]
+        .into_iter()
+        .map(|(fifo, log)| {
+            let done = done.clone();
+            thread::spawn(move || read_fifo(&fifo, &log, &done))
+        })
+        .collect::<Vec<_>>();
+
+    let mut buffer = [0; 4096];
+    let mut stdin = io::stdin().lock();
+    let mut stdout = io::stdout().lock();
+    loop {
+        match stdin.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(count) => {
+                if echo {
+                    let _ = stdout.write_all(&buffer[..count]);
+                    let _ = stdout.flush();
+                }
+                append(&console_log, &buffer[..count]);
+            }
+            Err(error) if error.kind() == ErrorKind::Interrupted => continue,
+            Err(_) => break,
+        }
+    }
+
+    done.store(true, Ordering::Relaxed);
+    for reader in readers {
+        let _ = reader.join();
+    }
+    0
+}
+
+/// Read a FIFO until the console is done. The FIFO is read
+/// non-blocking as firecracker might never open it.
+fn read_fifo(fifo: &Path, log: &SharedLog, done: &AtomicBool) {
+    let mut fifo = match OpenOptions::new().read(true).custom_flags(libc::O_NONBLOCK).open(fifo) {
+        Ok(fifo) => fifo,
+        Err(_) => return,
+    };
+    let mut buffer = [0; 4096];
+    loop {
+        match fifo.read(&mut buffer) {
+            Ok(count) if count > 0 => append(log, &buffer[..count]),
+            Err(error) if error.kind() == ErrorKind::Interrupted => {}
+            _ => {
+                if done.load(Ordering::Relaxed) {
+                    break;
+                }
+                thread::sleep(time::Duration::from_millis(defaults::LOG_POLL_MSEC));
+            }
+        }
+    }
+}
+
+fn open_log(path: &Path) -> Option<RingLog> {
+    fs::create_dir_all(vmlog::LOG_DIR).ok()?;
+    RingLog::open(path, vmlog::LOG_SIZE).ok()
+}
+
+fn append(log: &SharedLog, data: &[u8]) {
+    if let Ok(mut log) = log.lock() {
+        if let Some(ring_log) = log.as_mut() {
+            if ring_log.write(data).is_err() {
+                // stop logging, e.g. on a full disk
+                *log = None;
+            }
+        }
+    }
+}
@@ -23,34 +23,74 @@
 //
 use std::env;
 
-pub const IMAGE_ROOT: &str =
-    "image";
-pub const IMAGE_OVERLAY: &str =
-    "overlayroot";
-pub const OVERLAY_ROOT: &str =
-    "overlayroot/rootfs";
-pub const OVERLAY_UPPER: &str =
-    "overlayroot/rootfs_upper";
-pub const OVERLAY_WORK: &str =
-    "overlayroot/rootfs_work";
+pub const OVERLAY_UPPER_DIR: &str =
+    "rootfs_upper";
+pub const OVERLAY_WORK_DIR: &str =
+    "rootfs_work";
 pub const FIRECRACKER_OVERLAY_DIR:&str =
     "/var/lib/firecracker/storage";
 pub const FIRECRACKER_TEMPLATE:&str =
     "/etc/flakes/firecracker.json";
+pub const FIRECRACKER: &str =
+    "/usr/bin/firecracker";
+pub const JAILER: &str =
+    "/usr/bin/jailer";
+pub const JAILER_BASE_DIR: &str =
+    "/srv/jailer";
 pub const FIRECRACKER_FLAKE_DIR: &str =
     "/usr/share/flakes";
 pub const FIRECRACKER_VMID_DIR: &str =
     "/var/lib/firecracker/storage/tmp/flakes";
+pub const FIRECRACKER_FORWARD_DIR: &str =
+    "/var/lib/firecracker/storage/tmp/forward";
+pub const FIRECRACKER_EXEC_DIR: &str =
+    "/var/lib/firecracker/storage/tmp/exec";
 pub const GC_THRESHOLD: i32 = 20;
 pub const VM_CID: u32 = 3;
 pub const VM_PORT: u32 =
     52;
+pub const EXEC_PORT_MIN: u32 =
+    49200;
+pub const EXEC_PORT_MAX: u32 =
+    60000;
+pub const KERNEL_CMDLINE_MAX: usize =
+    2048;
+pub const MMDS_ADDRESS: &str =
+    "169.254.169.254";
+pub const FORWARD_PORT: u32 =
+    10000;
 pub const SOCAT: &str =
     "/usr/bin/socat";
 pub const RETRIES: u32 =
     60;
 pub const VM_WAIT_TIMEOUT_MSEC: u64 =
     1000;
+pub const INCLUDE_IMAGE_RESERVE_MIB: u64 =
+    4;
+pub const DRIVE_IMAGE_RESERVE_MIB: u64 =
+    64;
+pub const RSYNC: &str =
+    "/usr/bin/rsync";
+pub const UNSHARE: &str =
+    "/usr/bin/unshare";
+pub const DEBUGFS: &str =
+    "/usr/sbin/debugfs";
+pub const SUBUID: &str =
+    "/etc/subuid";
+pub const SUBGID: &str =
+    "/etc/subgid";
+pub const SYSTEMD_RUN: &str =
+    "/usr/bin/systemd-run";
+pub const VMM_MEMORY_OVERHEAD_MIB: u64 =
+    128;
+pub const LOG_COLLECTOR: &str =
+    "FLAKE_LOG_COLLECTOR";
+pub const VSOCK_RELAY: &str =
+    "--vsock-relay";
+pub const LOG_POLL_MSEC: u64 =
+    100;
+pub const LOG_TAIL_LINES: usize =
+    20;
 
 pub fn is_debug() -> bool {
     env::var("PILOT_DEBUG").is_ok()
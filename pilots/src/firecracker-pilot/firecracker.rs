@@ -21,19 +21,33 @@
 /// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 /// SOFTWARE.
 ///
-use crate::config::{config, RuntimeSection};
+use crate::config::{config, Drive, ForwardTarget, Jailer, RuntimeSection};
 use crate::defaults::{debug, is_debug};
+use crate::{console, jailer};
+use base64::{engine::general_purpose, Engine as _};
+use flakes::config::{
+    itf::{AccessMode, NetworkMode},
+    pilots::fc::{Balloon, CpuTemplate, Entropy, MmdsVersion, RateLimiter},
+    setup::HostIntegration,
+};
 use flakes::user::User;
-use rand::Rng;
+use flakes::{vmlog, vmnet};
+use nix::errno::Errno;
+use nix::fcntl::{flock, FlockArg, OFlag};
+use nix::unistd::{geteuid, pipe2, User as SystemUser};
 use serde::{Deserialize, Serialize};
-use serde_json::{self};
+use serde_json::{self, json};
 use spinoff::{spinners, Color, Spinner};
 use std::env;
 use std::fs;
 use std::fs::File;
-use std::io::{Seek, SeekFrom, Write};
-use std::path::Path;
-use std::process::{exit, id, Stdio};
+use std::io::{self, Read, Write};
+use std::net::Shutdown;
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::process::{exit, id, Command, Stdio};
 use std::{thread, time};
 use tempfile::{tempdir, NamedTempFile};
 use ubyte::ByteUnit;
@@ -51,6 +65,30 @@ pub struct FireCrackerConfig {
     #[serde(rename = "machine-config")]
     pub machine_config: FireCrackerMachine,
     pub vsock: FireCrackerVsock,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub balloon: Option<Balloon>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub entropy: Option<Entropy>,
+    #[serde(rename = "mmds-config", skip_serializing_if = "Option::is_none")]
+    pub mmds_config: Option<FireCrackerMmdsConfig>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub logger: Option<FireCrackerLogger>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metrics: Option<FireCrackerMetrics>,
+    /// Other sections of the template, passed through as is
+    #[serde(flatten)]
+    pub other: serde_json::Map<String, serde_json::Value>,
+}
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FireCrackerLogger {
+    pub log_path: String,
+    /// Other settings of the template, e.g. level
+    #[serde(flatten)]
+    pub other: serde_json::Map<String, serde_json::Value>,
+}
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FireCrackerMetrics {
+    pub metrics_path: String,
 }
 #[derive(Debug, Serialize, Deserialize)]
 pub struct FireCrackerBootSource {
@@ -66,23 +104,57 @@ pub struct FireCrackerDrive {
     pub is_root_device: bool,
     pub is_read_only: bool,
     pub cache_type: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rate_limiter: Option<RateLimiter>,
 }
 #[derive(Debug, Serialize, Deserialize)]
 pub struct FireCrackerNetworkInterface {
     pub iface_id: String,
     pub guest_mac: String,
     pub host_dev_name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rx_rate_limiter: Option<RateLimiter>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tx_rate_limiter: Option<RateLimiter>,
 }
 #[derive(Debug, Serialize, Deserialize)]
 pub struct FireCrackerMachine {
     pub vcpu_count: i64,
     pub mem_size_mib: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub smt: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cpu_template: Option<CpuTemplate>,
 }
 #[derive(Debug, Serialize, Deserialize)]
 pub struct FireCrackerVsock {
     pub guest_cid: u32,
     pub uds_path: String,
 }
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FireCrackerMmdsConfig {
+    pub version: MmdsVersion,
+    pub network_interfaces: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ipv4_address: Option<String>,
+}
+
+/// Load a firecracker JSON template
+///
+/// The generated config relies on the rootfs as first
+/// drive and on the network interface of the template.
+pub fn load_template(template: &Path) -> Result<FireCrackerConfig, String> {
+    let file = File::open(template).map_err(|error| format!("Failed to open {}: {}", template.display(), error))?;
+    let firecracker_config: FireCrackerConfig = serde_json::from_reader(file)
+        .map_err(|error| format!("Failed to import config template {}: {}", template.display(), error))?;
+    if !firecracker_config.drives.first().is_some_and(|drive| drive.is_root_device) {
+        return Err(format!("Template {} must have the rootfs as first drive", template.display()));
+    }
+    if firecracker_config.network_interfaces.is_empty() {
+        return Err(format!("Template {} must have a network interface", template.display()));
+    }
+    Ok(firecracker_config)
+}
 
 /// Create VM for later execution of program_name.
 ///     The VM name and all other settings to run the program
@@ -215,71 +287,57 @@ pub fn create(program_name: &String) -> Vec<String> {
     }
 
     // Setup root overlay if configured
-    let mut provision_ok = false;
     let vm_overlay_file = get_meta_file_name(program_name, defaults::FIRECRACKER_OVERLAY_DIR, "ext2");
     if let Some(overlay_size) = engine_section.overlay_size {
         let overlay_size = overlay_size.parse::<ByteUnit>().expect("could not parse overlay size").as_u64();
         if !Path::new(&vm_overlay_file).exists() || !resume {
-            match std::fs::File::create(&vm_overlay_file) {
-                Ok(mut vm_overlay_file_fd) => match vm_overlay_file_fd.seek(SeekFrom::Start(overlay_size - 1)) {
-                    Ok(_) => match vm_overlay_file_fd.write_all(&[0]) {
-                        Ok(_) => {}
-                        Err(error) => {
-                            panic!("Write failed with: {}", error)
-                        }
-                    },
-                    Err(error) => {
-                        panic!("No space left on device: {}", error)
-                    }
-                },
+            // Stage the overlay content in a temporary tree
+            // and create the filesystem from it
+            let provision_ok = match tempdir() {
+                Ok(staging) => {
+                    let staged = stage_overlay(staging.path(), engine_section.rootfs_image_path, has_includes)
+                        && create_image(&vm_overlay_file, overlay_size, None, staging.path());
+                    remove_staging(staging.path());
+                    staged
+                }
                 Err(error) => {
-                    panic!("Failed to create overlay image: {}", error);
+                    error!("Failed to create temporary directory: {}", error);
+                    false
                 }
+            };
+            if !provision_ok {
+                spinner.fail("Flake launch has failed");
+                panic!("Failed to provision VM")
             }
-            // Create filesystem
-            let mut mkfs = runas.run("mkfs.ext2");
-            mkfs.arg("-F").arg(&vm_overlay_file);
-            debug(&format!("sudo {:?}", mkfs.get_args()));
-            match mkfs.output() {
-                Ok(output) => {
-                    if !output.status.success() {
-                        panic!("Failed to create overlay filesystem: {}", String::from_utf8_lossy(&output.stderr));
-                    }
+        }
+    } else if has_include_drive() {
+        // Without an overlay the static data is packed into
+        // a read-only image, attached as extra drive
+        let vm_include_file = get_meta_file_name(program_name, defaults::FIRECRACKER_OVERLAY_DIR, "include");
+        if !Path::new(&vm_include_file).exists() || !resume {
+            let provision_ok = match tempdir() {
+                Ok(staging) => {
+                    let staged = stage_root(staging.path(), engine_section.rootfs_image_path, has_includes)
+                        && create_include_image(&vm_include_file, staging.path());
+                    remove_staging(staging.path());
+                    staged
                 }
                 Err(error) => {
-                    panic!("Failed to execute mkfs {:?}", error)
+                    error!("Failed to create temporary directory: {}", error);
+                    false
                 }
+            };
+            if !provision_ok {
+                spinner.fail("Flake launch has failed");
+                panic!("Failed to provision VM")
             }
-            provision_ok = true;
         }
     }
 
-    // Provision VM
-    if provision_ok {
-        let vm_image_file = engine_section.rootfs_image_path;
-        match tempdir() {
-            Ok(tmp_dir) => {
-                let vm_mount_point = mount_vm(tmp_dir.path().to_str().unwrap(), vm_image_file, &vm_overlay_file, User::ROOT);
-                if !vm_mount_point.is_empty() {
-                    // Handle includes
-                    if has_includes {
-                        debug("Syncing includes...");
-                        provision_ok = sync_includes(&vm_mount_point, User::ROOT);
-                    }
-                } else {
-                    provision_ok = false
-                }
-                umount_vm(tmp_dir.path().to_str().unwrap(), User::ROOT);
-            }
-            Err(error) => {
-                error!("Failed to create temporary file: {}", error);
-                provision_ok = false
-            }
-        }
-        if !provision_ok {
-            spinner.fail("Flake launch has failed");
-            panic!("Failed to provision VM")
-        }
+    // Pack the host directories of the data drives into images
+    if !create_drive_images(program_name) {
+        spinner.fail("Flake launch has failed");
+        panic!("Failed to provision VM")
     }
 
     spinner.success("Launching flake");
@@ -305,19 +363,35 @@ pub fn start(program_name: &String, vm: Vec<String>) {
 
     if is_running {
         // 1. Execute app in running VM
-        status_code = execute_command_at_instance(program_name, runas, get_exec_port());
+        status_code = match get_exec_port(program_name) {
+            Ok(exec_port) => execute_command_at_instance(program_name, runas, exec_port.port),
+            Err(status_code) => status_code,
+        };
     } else {
         match NamedTempFile::new() {
             Ok(firecracker_config) => {
-                create_firecracker_config(program_name, &firecracker_config);
+                let metadata = create_firecracker_config(program_name, &firecracker_config);
+                record_vsock_uds_path(program_name);
+                if !start_forwards(program_name, runas) {
+                    exit(1)
+                }
                 if resume {
                     // 2. Startup resume type VM and execute app
                     is_blocking = false;
-                    call_instance(&firecracker_config, vm_id_file, runas, is_blocking);
-                    status_code = execute_command_at_instance(program_name, runas, get_exec_port());
+                    call_instance(&firecracker_config, metadata.as_ref(), vm_id_file, runas, is_blocking);
+                    status_code = match get_exec_port(program_name) {
+                        Ok(exec_port) => execute_command_at_instance(program_name, runas, exec_port.port),
+                        Err(status_code) => status_code,
+                    };
                 } else {
                     // 3. Startup VM and execute app
-                    status_code = call_instance(&firecracker_config, vm_id_file, runas, is_blocking);
+                    status_code = call_instance(&firecracker_config, metadata.as_ref(), vm_id_file, runas, is_blocking);
+                    sync_drives(program_name);
+                    remove_jail(&get_meta_name(program_name));
+                    stop_forwards(&get_meta_name(program_name), runas);
+                    if let Err(error) = vmnet::teardown(&get_meta_name(program_name)) {
+                        error!("Failed to remove VM network: {}", error)
+                    }
                 }
             }
             Err(error) => {
@@ -328,20 +402,102 @@ pub fn start(program_name: &String, vm: Vec<String>) {
     exit(status_code)
 }
 
+/// Get the firecracker command, placed into a cgroup
+/// by a transient systemd scope if resource limits are set
+fn firecracker_command(user: User) -> Command {
+    let RuntimeSection { resources, firecracker: engine_section, .. } = config().runtime();
+    let program = if engine_section.jailer.is_some() { defaults::JAILER } else { "firecracker" };
+    if resources.is_empty() {
+        return user.run(program);
+    }
+
+    let mut properties: Vec<String> = Vec::new();
+    if let Some(cpus) = resources.cpus() {
+        properties.push(format!("CPUQuota={}%", (cpus * 100.0).round()));
+    }
+    if let Some(weight) = resources.cpu_weight() {
+        properties.push(format!("CPUWeight={}", weight));
+    }
+    if let Some(memory) = resources.memory() {
+        // the VMM needs some memory on top of the guest
+        properties.push(format!("MemoryMax={}", memory + (defaults::VMM_MEMORY_OVERHEAD_MIB << 20)));
+    }
+    if let Some(pids) = resources.pids() {
+        properties.push(format!("TasksMax={}", pids));
+    }
+    if let Some(weight) = resources.io_weight() {
+        properties.push(format!("IOWeight={}", weight));
+    }
+
+    let mut systemd_run = user.run(defaults::SYSTEMD_RUN);
+    systemd_run.arg("--scope").arg("--quiet").arg("--collect");
+    for property in properties {
+        systemd_run.arg("--property").arg(property);
+    }
+    systemd_run.arg(program);
+    systemd_run
+}
+
 /// Run firecracker with specified configuration
-pub fn call_instance(config_file: &NamedTempFile, vm_id_file: &String, user: User, is_blocking: bool) -> i32 {
+pub fn call_instance(
+    config_file: &NamedTempFile, metadata_file: Option<&NamedTempFile>, vm_id_file: &String, user: User, is_blocking: bool,
+) -> i32 {
     let mut status_code = 0;
 
-    let mut firecracker = user.run("firecracker");
+    let instance = Path::new(vm_id_file).file_stem().unwrap().to_string_lossy().to_string();
+
+    // the console and, unless debugging, the output of
+    // firecracker are written to the log of the instance
+    let mut firecracker = firecracker_command(user);
+    let (console, console_writer) = match pipe2(OFlag::O_CLOEXEC) {
+        // the new descriptors are owned by the files
+        Ok((reader, writer)) => unsafe { (File::from_raw_fd(reader), File::from_raw_fd(writer)) },
+        Err(error) => panic!("Failed to create console pipe: {}", error),
+    };
     if !is_debug() {
-        firecracker.stderr(Stdio::null());
+        match console_writer.try_clone() {
+            Ok(writer) => firecracker.stderr(writer),
+            Err(error) => panic!("Failed to create console pipe: {}", error),
+        };
     }
-    if !is_debug() && !is_blocking {
-        firecracker.stdin(Stdio::piped()).stdout(Stdio::piped());
+    if !is_blocking {
+        firecracker.stdin(Stdio::piped());
+    }
+    firecracker.stdout(console_writer);
+    let mut collector = match console::spawn_collector(&instance, Stdio::from(console), is_blocking || is_debug()) {
+        Some(collector) => collector,
+        None => panic!("Failed to start the log collector"),
+    };
+    if let Some(jailer) = &config().runtime().firecracker.jailer {
+        // the jailer sets the ID of firecracker, which reads
+        // its config files from inside of the jail
+        firecracker.args(jailer::jailer_args(jailer, &instance)).arg("--");
+        match jailer::install_file(jailer, &instance, config_file.path(), "firecracker.json") {
+            Some(config_file) => firecracker.arg("--no-api").arg("--config-file").arg(config_file),
+            None => panic!("Failed to copy the firecracker config into the jail"),
+        };
+        if let Some(metadata_file) = metadata_file {
+            match jailer::install_file(jailer, &instance, metadata_file.path(), "metadata.json") {
+                Some(metadata_file) => firecracker.arg("--metadata").arg(metadata_file),
+                None => panic!("Failed to copy the flake metadata into the jail"),
+            };
+        }
+        match jailer::seccomp_args(jailer, &instance) {
+            Some(seccomp_args) => firecracker.args(seccomp_args),
+            None => panic!("Failed to place the seccomp filter into the jail"),
+        };
+    } else {
+        firecracker.arg("--no-api").arg("--id").arg(id().to_string()).arg("--config-file").arg(config_file.path());
+        if let Some(metadata_file) = metadata_file {
+            firecracker.arg("--metadata").arg(metadata_file.path());
+        }
     }
-    firecracker.arg("--no-api").arg("--id").arg(id().to_string()).arg("--config-file").arg(config_file.path());
     debug(&format!("sudo {:?}", firecracker.get_args()));
-    match firecracker.spawn() {
+    let spawned = firecracker.spawn();
+    // close the write end of the console, the collector
+    // stops when firecracker has exited
+    drop(firecracker);
+    match spawned {
         Ok(mut child) => {
             let pid = child.id();
             debug(&format!("PID {}", pid));
@@ -367,6 +523,8 @@ pub fn call_instance(config_file: &NamedTempFile, vm_id_file: &String, user: Use
                         panic!("firecracker failed with: {}", error);
                     }
                 }
+                // wait for the rest of the console output
+                let _ = collector.wait();
             }
         }
         Err(error) => {
@@ -376,16 +534,84 @@ pub fn call_instance(config_file: &NamedTempFile, vm_id_file: &String, user: Use
     status_code
 }
 
-/// Create random execution port
-pub fn get_exec_port() -> u32 {
-    let mut random = rand::thread_rng();
+/// Print the last lines of the console log of the instance,
+/// if sci could not be reached
+fn print_log_tail(program_name: &String) {
+    let instance = get_meta_name(program_name);
+    let lines = vmlog::tail(&vmlog::console_log(&instance), defaults::LOG_TAIL_LINES);
+    if lines.is_empty() {
+        error!("VM instance {} is not reachable and has no console log", instance);
+        return;
+    }
+    error!("VM instance {} is not reachable, last console output:", instance);
+    for line in lines {
+        eprintln!("{}", line);
+    }
+}
 
-    // FIXME: A more stable version
-    // should check for already running socket connections
-    // and if the same number is used for an already running one
-    // another rand should be called
+/// Execution port of a command in a running VM instance,
+/// reserved by a locked file until it is dropped
+pub struct ExecPort {
+    pub port: u32,
+    lock_file: PathBuf,
+    _lock: File,
+}
 
-    random.gen_range(49200..60000)
+impl Drop for ExecPort {
+    fn drop(&mut self) {
+        // removed while still locked, such that nobody
+        // else holds a lock of the same file
+        if let Err(error) = fs::remove_file(&self.lock_file) {
+            error!("Failed to remove {}: {}", self.lock_file.display(), error)
+        }
+    }
+}
+
+/// Reserve the lowest free execution port of the VM instance
+///
+/// A port is taken by locking its lock file, the lock is released
+/// by the system if the pilot is gone. Ports with a listener socket
+/// of another command are skipped. Returns the status code on error.
+pub fn get_exec_port(program_name: &String) -> Result<ExecPort, i32> {
+    let instance = get_meta_name(program_name);
+    let vsock_uds_path = get_vsock_uds_path(program_name);
+    for port in defaults::EXEC_PORT_MIN..defaults::EXEC_PORT_MAX {
+        let lock_file = PathBuf::from(format!("{}/{}_{}.lock", defaults::FIRECRACKER_EXEC_DIR, instance, port));
+        match lock_exec_port(&lock_file) {
+            Ok(Some(lock)) => {
+                let exec_port = ExecPort { port, lock_file, _lock: lock };
+                if !Path::new(&format!("{}_{}", vsock_uds_path, port)).exists() {
+                    debug(&format!("Using execution port {}", port));
+                    return Ok(exec_port);
+                }
+            }
+            Ok(None) => debug(&format!("Execution port {} is taken", port)),
+            Err(error) => {
+                error!("Failed to lock {}: {}", lock_file.display(), error);
+                return Err(1);
+            }
+        }
+    }
+    error!("No free execution port left for {}", instance);
+    Err(1)
+}
+
+/// Lock the lock file of an execution port, None
+/// if it is locked by another pilot
+fn lock_exec_port(lock_file: &Path) -> io::Result<Option<File>> {
+    let lock = fs::OpenOptions::new().write(true).create(true).truncate(false).open(lock_file)?;
+    match flock(lock.as_raw_fd(), FlockArg::LockExclusiveNonblock) {
+        Ok(_) => {}
+        Err(Errno::EWOULDBLOCK) => return Ok(None),
+        Err(error) => return Err(error.into()),
+    }
+    // the file might have been removed by its former owner
+    // in between, the lock of a removed file is worthless
+    let locked = lock.metadata()?;
+    match fs::metadata(lock_file) {
+        Ok(current) if current.ino() == locked.ino() => Ok(Some(lock)),
+        _ => Ok(None),
+    }
 }
 
 pub fn check_connected(program_name: &String, user: User) -> i32 {
@@ -394,34 +620,15 @@ pub fn check_connected(program_name: &String, user: User) -> i32 {
     !*/
     let mut status_code;
     let mut retry_count = 0;
-    let vsock_uds_path = format!("/run/sci_cmd_{}.sock", get_meta_name(program_name));
+    let vsock_uds_path = get_vsock_uds_path(program_name);
     loop {
         if retry_count == defaults::RETRIES {
             debug("Max retries for VM connection check exceeded");
             status_code = 1;
             return status_code;
         }
-        let mut vm_command = user.run("bash");
-        vm_command.arg("-c").arg(&format!(
-            "echo -e 'CONNECT {}'|{} UNIX-CONNECT:{} -",
-            defaults::VM_PORT,
-            defaults::SOCAT,
-            vsock_uds_path
-        ));
-        debug(&format!("sudo {:?}", vm_command.get_args()));
-        match vm_command.output() {
-            Ok(output) => {
-                if String::from_utf8_lossy(&output.stdout).starts_with("OK") {
-                    status_code = 0
-                } else {
-                    status_code = 1
-                }
-            }
-            Err(error) => {
-                error!("UNIX-CONNECT failed with: {:?}", error);
-                status_code = 1
-            }
-        }
+        // an empty request only checks the connection
+        status_code = send_vm_request(&vsock_uds_path, &user, "");
         if status_code == 0 {
             // connection OK
             break;
@@ -439,37 +646,15 @@ pub fn check_connected(program_name: &String, user: User) -> i32 {
 pub fn send_command_to_instance(program_name: &String, user: User, exec_port: u32) -> i32 {
     let mut status_code;
     let mut retry_count = 0;
-    let run = get_run_cmdline(program_name, false);
-    let vsock_uds_path = format!("/run/sci_cmd_{}.sock", get_meta_name(program_name));
+    let request = exec_request(&get_run_cmdline(program_name), exec_port);
+    let vsock_uds_path = get_vsock_uds_path(program_name);
     loop {
         if retry_count == defaults::RETRIES {
             debug("Max retries for VM command transfer exceeded");
             status_code = 1;
             return status_code;
         }
-        let mut vm_command = user.run("bash");
-        vm_command.arg("-c").arg(&format!(
-            "echo -e 'CONNECT {}\n{} {}\n'|{} UNIX-CONNECT:{} -",
-            defaults::VM_PORT,
-            run.join(" "),
-            exec_port,
-            defaults::SOCAT,
-            vsock_uds_path
-        ));
-        debug(&format!("sudo {:?}", vm_command.get_args()));
-        match vm_command.output() {
-            Ok(output) => {
-                if String::from_utf8_lossy(&output.stdout).starts_with("OK") {
-                    status_code = 0
-                } else {
-                    status_code = 1
-                }
-            }
-            Err(error) => {
-                error!("UNIX-CONNECT failed with: {:?}", error);
-                status_code = 1
-            }
-        }
+        status_code = send_vm_request(&vsock_uds_path, &user, &request);
         if status_code == 0 {
             // command transfered
             break;
@@ -483,16 +668,56 @@ pub fn send_command_to_instance(program_name: &String, user: User, exec_port: u3
     status_code
 }
 
+/// Request for sci to run the command at the given execution port.
+/// The command travels encoded, such that neither sci nor socat
+/// split or interpret it.
+pub fn exec_request(run: &[String], exec_port: u32) -> String {
+    format!("exec {} {}", exec_port, encode_run(run))
+}
+
+/// Send a request to sci through the vsock UDS of the instance.
+/// The request is written to the stdin of socat, no shell is
+/// involved. Returns 0 if firecracker accepted the connection.
+fn send_vm_request(vsock_uds_path: &str, user: &User, request: &str) -> i32 {
+    let mut vm_command = user.run(defaults::SOCAT);
+    vm_command
+        .arg("-")
+        .arg(format!("UNIX-CONNECT:{}", vsock_uds_path))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null());
+    debug(&format!("sudo {:?} <<< {}", vm_command.get_args(), request));
+    let mut child = match vm_command.spawn() {
+        Ok(child) => child,
+        Err(error) => {
+            error!("UNIX-CONNECT failed with: {:?}", error);
+            return 1;
+        }
+    };
+    if let Some(mut stdin) = child.stdin.take() {
+        let _ = stdin.write_all(format!("CONNECT {}\n{}\n", defaults::VM_PORT, request).as_bytes());
+    }
+    match child.wait_with_output() {
+        Ok(output) if String::from_utf8_lossy(&output.stdout).starts_with("OK") => 0,
+        Ok(_) => 1,
+        Err(error) => {
+            error!("UNIX-CONNECT failed with: {:?}", error);
+            1
+        }
+    }
+}
+
 /// Send command to a vsoc connected to a running instance
 pub fn execute_command_at_instance(program_name: &String, user: User, exec_port: u32) -> i32 {
     let mut status_code;
     let mut retry_count = 0;
-    let vsock_uds_path = format!("/run/sci_cmd_{}.sock", get_meta_name(program_name));
+    let vsock_uds_path = get_vsock_uds_path(program_name);
 
     // wait for UDS socket to appear
     loop {
         if retry_count == defaults::RETRIES {
             debug("Max retries for UDS socket lookup exceeded");
+            print_log_tail(program_name);
             status_code = 1;
             return status_code;
         }
@@ -506,12 +731,13 @@ pub fn execute_command_at_instance(program_name: &String, user: User, exec_port:
 
     // make sure instance can be contacted
     if check_connected(program_name, user) != 0 {
+        print_log_tail(program_name);
         return 1;
     }
 
     // spawn the listener and wait for sci to run the command
     let mut vm_exec = user.run(defaults::SOCAT);
-    vm_exec.arg("-t").arg("0").arg("-").arg(&format!("UNIX-LISTEN:{}_{}", vsock_uds_path, exec_port));
+    vm_exec.arg("-t").arg("0").arg("-").arg(&format!("UNIX-LISTEN:{}_{}{}", vsock_uds_path, exec_port, uds_listen_options()));
     debug(&format!("sudo {:?}", vm_exec.get_args()));
     match vm_exec.spawn() {
         Ok(mut child) => {
@@ -533,116 +759,627 @@ pub fn execute_command_at_instance(program_name: &String, user: User, exec_port:
     status_code
 }
 
-/// Create json config to call firecracker
-pub fn create_firecracker_config(program_name: &String, config_file: &NamedTempFile) {
-    match std::fs::File::open(defaults::FIRECRACKER_TEMPLATE) {
-        Ok(template) => {
-            match serde_json::from_reader::<File, FireCrackerConfig>(template) {
-                Ok(mut firecracker_config) => {
-                    let mut boot_args: Vec<String> = Vec::new();
-                    let RuntimeSection { resume, firecracker: engine_section, .. } = config().runtime();
-
-                    // set kernel_image_path
-                    firecracker_config.boot_source.kernel_image_path = engine_section.kernel_image_path.to_owned();
-
-                    // set initrd_path
-                    if let Some(initrd_path) = engine_section.initrd_path {
-                        firecracker_config.boot_source.initrd_path = initrd_path.to_owned();
+/// vsock port of a forward, by its position in the configuration
+fn forward_port(index: usize) -> u32 {
+    defaults::FORWARD_PORT + index as u32
+}
+
+/// Start the host side relays of the configured forwards
+///
+/// Connections to the host are accepted by firecracker on the vsock
+/// UDS with the port as suffix, connections to the guest are relayed
+/// by the pilot after the CONNECT handshake. The relays outlive
+/// the pilot for resume type VMs, their process IDs are kept in a
+/// meta file until the VM is gone.
+pub fn start_forwards(program_name: &String, user: User) -> bool {
+    let forwards = config().runtime().firecracker.forward;
+    if forwards.is_empty() {
+        return true;
+    }
+    let vsock_uds_path = get_vsock_uds_path(program_name);
+    let mut pids: Vec<String> = Vec::new();
+    let mut ok = true;
+    for (index, forward) in forwards.iter().enumerate() {
+        let port = forward_port(index);
+        let mut relay = user.run(defaults::SOCAT);
+        match forward.to {
+            ForwardTarget::Host => {
+                relay
+                    .arg(format!("UNIX-LISTEN:{}_{},unlink-early,fork{}", vsock_uds_path, port, uds_listen_options()))
+                    .arg(forward.host.connect_address());
+            }
+            ForwardTarget::Guest => {
+                // each connection is relayed by the pilot, no shell involved
+                let pilot = match env::current_exe() {
+                    Ok(pilot) => pilot,
+                    Err(error) => {
+                        error!("Failed to find the pilot executable: {:?}", error);
+                        ok = false;
+                        break;
                     }
+                };
+                relay.arg(forward.host.listen_address()).arg(format!(
+                    "EXEC:{} {} {} {}",
+                    pilot.display(),
+                    defaults::VSOCK_RELAY,
+                    vsock_uds_path,
+                    port
+                ));
+            }
+        }
+        relay.stdin(Stdio::null()).stdout(Stdio::null());
+        if !is_debug() {
+            relay.stderr(Stdio::null());
+        }
+        debug(&format!("sudo {:?}", relay.get_args()));
+        match relay.spawn() {
+            Ok(child) => pids.push(child.id().to_string()),
+            Err(error) => {
+                error!("Failed to start relay for {:?}: {:?}", forward.host, error);
+                ok = false;
+                break;
+            }
+        }
+    }
+    let forward_file = get_meta_file_name(program_name, defaults::FIRECRACKER_FORWARD_DIR, "pids");
+    if ok {
+        if let Err(error) = fs::write(&forward_file, pids.join("\n")) {
+            error!("Failed to write {}: {}", forward_file, error);
+            ok = false;
+        }
+    }
+    if !ok {
+        kill_relays(&pids, user);
+    }
+    ok
+}
 
-                    // setup run commandline for the command call
-                    let run = get_run_cmdline(program_name, true);
+/// Get the vsock UDS path and port if the pilot is called as
+/// relay of a forward into the VM. The pilot is called by its
+/// own name then, not by the name of a registered app.
+pub fn relay_args() -> Option<(String, String)> {
+    let args: Vec<String> = env::args().collect();
+    let pilot = env::current_exe().ok()?;
+    match &args[..] {
+        [name, mode, vsock_uds_path, port]
+            if mode == defaults::VSOCK_RELAY && Path::new(name).file_name() == pilot.file_name() =>
+        {
+            Some((vsock_uds_path.to_string(), port.to_string()))
+        }
+        _ => None,
+    }
+}
 
-                    // set boot_args
-                    if is_debug() {
-                        boot_args.push("PILOT_DEBUG=1".to_string());
-                    }
-                    if engine_section.overlay_size.is_some() {
-                        boot_args.push("overlay_root=/dev/vdb".to_string());
-                    }
-                    for boot_option in engine_section.boot_args {
-                        if resume && !is_debug() && boot_option.starts_with("console=") {
-                            // in resume mode the communication is handled
-                            // through vsocks. Thus we don't need a serial
-                            // console and only provide one in debug mode
-                            boot_args.push("console=".to_string());
-                        } else {
-                            boot_args.push(boot_option.to_owned());
-                        }
-                    }
-                    if !firecracker_config.boot_source.boot_args.is_empty() {
-                        firecracker_config.boot_source.boot_args.push(' ');
-                    }
-                    firecracker_config.boot_source.boot_args.push_str(&boot_args.join(" "));
-                    if resume {
-                        firecracker_config.boot_source.boot_args.push_str(" run=vsock")
-                    } else {
-                        firecracker_config.boot_source.boot_args.push_str(&format!(" run=\"{}\"", run.join(" ")))
-                    }
+/// Relay stdin and stdout to the given vsock port of the guest
+///
+/// The connection is made through the vsock UDS of firecracker,
+/// which confirms the CONNECT request with an OK line before any
+/// data of the guest is passed.
+pub fn relay_to_guest(vsock_uds_path: &str, port: &str) -> i32 {
+    let mut stream = match UnixStream::connect(vsock_uds_path) {
+        Ok(stream) => stream,
+        Err(error) => {
+            error!("Failed to connect to {}: {}", vsock_uds_path, error);
+            return 1;
+        }
+    };
+    if let Err(error) = stream.write_all(format!("CONNECT {}\n", port).as_bytes()) {
+        error!("Failed to connect to port {}: {}", port, error);
+        return 1;
+    }
+    // read the reply byte by byte, such that no data of the guest is consumed
+    let mut reply: Vec<u8> = Vec::new();
+    let mut byte = [0; 1];
+    while reply.last() != Some(&b'\n') {
+        match stream.read(&mut byte) {
+            Ok(1) => reply.push(byte[0]),
+            _ => break,
+        }
+    }
+    if !reply.starts_with(b"OK") {
+        error!("Port {} refused the connection: {}", port, String::from_utf8_lossy(&reply).trim());
+        return 1;
+    }
+    let mut to_guest = match stream.try_clone() {
+        Ok(to_guest) => to_guest,
+        Err(error) => {
+            error!("Failed to relay to port {}: {}", port, error);
+            return 1;
+        }
+    };
+    thread::spawn(move || {
+        let _ = io::copy(&mut io::stdin(), &mut to_guest);
+        let _ = to_guest.shutdown(Shutdown::Write);
+    });
+    let _ = io::copy(&mut stream, &mut io::stdout());
+    0
+}
 
-                    // set path_on_host for rootfs
-                    firecracker_config.drives[0].path_on_host = engine_section.rootfs_image_path.to_owned();
+/// Check that the instance name, the program name and the optional
+/// @NAME, is safe to be used in file and socket paths
+pub fn check_instance_name(program_name: &String) -> bool {
+    let instance = get_meta_name(program_name);
+    if !valid_instance_name(&instance) {
+        error!("Invalid instance name {}, only letters, digits, '_', '.' and '-' are allowed", instance);
+        return false;
+    }
+    true
+}
 
-                    // set drive section for overlay
-                    if engine_section.overlay_size.is_some() {
-                        let vm_overlay_file = get_meta_file_name(program_name, defaults::FIRECRACKER_OVERLAY_DIR, "ext2");
+fn valid_instance_name(instance: &str) -> bool {
+    instance.split('@').all(|name| !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || "_.-".contains(c)))
+}
 
-                        let cache_type = engine_section.cache_type.unwrap_or_default().to_string();
+/// Kill relay processes
+fn kill_relays(pids: &[String], user: User) {
+    for pid in pids {
+        let mut kill = user.run("kill");
+        kill.arg(pid).stderr(Stdio::null());
+        debug(&format!("{:?}", kill.get_args()));
+        if let Err(error) = kill.status() {
+            error!("Failed to execute kill: {:?}", error)
+        }
+    }
+}
 
-                        let drive = FireCrackerDrive {
-                            drive_id: "overlay".to_string(),
-                            path_on_host: vm_overlay_file,
-                            is_root_device: false,
-                            is_read_only: false,
-                            cache_type,
-                        };
-                        firecracker_config.drives.push(drive);
-                    }
+/// Stop the host side relays of a VM instance
+pub fn stop_forwards(instance: &str, user: User) {
+    let forward_file = format!("{}/{}.pids", defaults::FIRECRACKER_FORWARD_DIR, instance);
+    if let Ok(pids) = fs::read_to_string(&forward_file) {
+        kill_relays(&pids.lines().map(str::to_string).collect::<Vec<String>>(), user);
+        if let Err(error) = fs::remove_file(&forward_file) {
+            error!("Failed to remove {}: {}", forward_file, error)
+        }
+    }
+}
 
-                    // set tap device name
-                    firecracker_config.network_interfaces[0].host_dev_name = format!("tap-{}", get_meta_name(program_name));
+/// Get the host path of the vsock UDS of the VM, which
+/// is inside of the jail root if firecracker runs jailed
+pub fn get_vsock_uds_path(program_name: &String) -> String {
+    let uds_path = format!("/run/sci_cmd_{}.sock", get_meta_name(program_name));
+    match &config().runtime().firecracker.jailer {
+        Some(jailer) => jailer::host_path(jailer, &get_meta_name(program_name), &uds_path).display().to_string(),
+        None => uds_path,
+    }
+}
 
-                    // set vsock name
-                    firecracker_config.vsock.guest_cid = defaults::VM_CID;
-                    firecracker_config.vsock.uds_path = format!("/run/sci_cmd_{}.sock", get_meta_name(program_name));
+/// Record the host path of the vsock UDS next to the VM ID
+/// file, such that flake-ctl can reach sci of the instance
+fn record_vsock_uds_path(program_name: &String) {
+    let uds_file = get_meta_file_name(program_name, defaults::FIRECRACKER_VMID_DIR, "uds");
+    if let Err(error) = fs::write(&uds_file, get_vsock_uds_path(program_name)) {
+        error!("Failed to write {}: {}", uds_file, error)
+    }
+}
 
-                    // set mem_size_mib
-                    if let Some(mem_size_mib) = engine_section.mem_size_mib {
-                        firecracker_config.machine_config.mem_size_mib = mem_size_mib
-                    }
+/// socat options of the vsock UDS listeners, such that
+/// firecracker can connect to them from inside of the jail
+fn uds_listen_options() -> String {
+    match &config().runtime().firecracker.jailer {
+        Some(jailer) => format!(",user={},group={}", jailer.uid, jailer.gid),
+        None => String::new(),
+    }
+}
 
-                    // set vcpu_count
-                    if let Some(vcpu_count) = engine_section.vcpu_count {
-                        firecracker_config.machine_config.vcpu_count = vcpu_count;
-                    }
+/// Create the jail of an instance and place the kernel,
+/// initrd and drives of the config into it
+fn jail_config(firecracker_config: &mut FireCrackerConfig, jailer: &Jailer, instance: &str) -> bool {
+    if !jailer::create_jail(jailer, instance) {
+        return false;
+    }
+    let boot_source = &mut firecracker_config.boot_source;
+    match jailer::link_file(jailer, instance, Path::new(&boot_source.kernel_image_path), "kernel", false) {
+        Some(path) => boot_source.kernel_image_path = path,
+        None => return false,
+    }
+    if !boot_source.initrd_path.is_empty() {
+        match jailer::link_file(jailer, instance, Path::new(&boot_source.initrd_path), "initrd", false) {
+            Some(path) => boot_source.initrd_path = path,
+            None => return false,
+        }
+    }
+    for drive in firecracker_config.drives.iter_mut() {
+        match jailer::link_file(jailer, instance, Path::new(&drive.path_on_host), &drive.drive_id, !drive.is_read_only) {
+            Some(path) => drive.path_on_host = path,
+            None => return false,
+        }
+    }
+    if let Some(logger) = firecracker_config.logger.as_mut() {
+        match jailer::link_file(jailer, instance, Path::new(&logger.log_path), "logger", false) {
+            Some(path) => logger.log_path = path,
+            None => return false,
+        }
+    }
+    if let Some(metrics) = firecracker_config.metrics.as_mut() {
+        match jailer::link_file(jailer, instance, Path::new(&metrics.metrics_path), "metrics", false) {
+            Some(path) => metrics.metrics_path = path,
+            None => return false,
+        }
+    }
+    true
+}
 
-                    debug(&serde_json::to_string(&firecracker_config).unwrap());
-                    serde_json::to_writer(config_file, &firecracker_config).unwrap();
-                }
+/// Remove the jail of a VM instance, if firecracker runs jailed
+pub fn remove_jail(instance: &str) {
+    if let Some(jailer) = &config().runtime().firecracker.jailer {
+        jailer::remove_jail(jailer, instance);
+    }
+}
+
+/// Create json config to call firecracker
+///
+/// If the VM has a network interface, the flake metadata is published
+/// to the guest through MMDS and the returned file holds its content.
+pub fn create_firecracker_config(program_name: &String, config_file: &NamedTempFile) -> Option<NamedTempFile> {
+    let template = config().runtime().firecracker.template.unwrap_or_else(|| PathBuf::from(defaults::FIRECRACKER_TEMPLATE));
+    match load_template(&template) {
+        Ok(mut firecracker_config) => {
+            let mut boot_args: Vec<String> = Vec::new();
+            let RuntimeSection { resume, identity, resources, network, firecracker: engine_section, .. } = config().runtime();
+
+            // set kernel_image_path
+            firecracker_config.boot_source.kernel_image_path = engine_section.kernel_image_path.to_owned();
+
+            // set initrd_path
+            if let Some(initrd_path) = engine_section.initrd_path {
+                firecracker_config.boot_source.initrd_path = initrd_path.to_owned();
+            }
+
+            // setup run commandline for the command call
+            let run = get_run_cmdline(program_name);
+
+            // setup tap device and guest address on the host
+            // the tap device is used by firecracker as the jail user
+            let jail_uid = engine_section.jailer.as_ref().map(|jailer| jailer.uid.to_string());
+            let tap_owner = jail_uid.as_deref().map(User::from).unwrap_or(config().runtime().runas);
+            let vm_network = match vmnet::setup(&get_meta_name(program_name), network.mode(), tap_owner) {
+                Ok(vm_network) => vm_network,
                 Err(error) => {
-                    panic!("Failed to import config template: {}", error);
+                    panic!("Failed to setup VM network: {}", error)
+                }
+            };
+
+            // address of the MMDS data store, if the guest can reach it
+            let mmds_address = vm_network.as_ref().map(|_| {
+                engine_section
+                    .mmds
+                    .as_ref()
+                    .and_then(|mmds| mmds.ipv4_address())
+                    .map(|address| address.to_string())
+                    .unwrap_or_else(|| defaults::MMDS_ADDRESS.to_string())
+            });
+
+            // set boot_args
+            if is_debug() {
+                boot_args.push("PILOT_DEBUG=1".to_string());
+            }
+            if engine_section.overlay_size.is_some() {
+                boot_args.push("overlay_root=/dev/vdb".to_string());
+            } else if has_include_drive() {
+                boot_args.push("include_root=/dev/vdb".to_string());
+            }
+            if let Some(address) = &mmds_address {
+                // run, identity and drives are part of the metadata
+                boot_args.push(format!("sci_mmds={}", address));
+            } else if let Some((uid, gid)) = identity.caller_ids() {
+                boot_args.push(format!("sci_identity={}:{}", uid, gid));
+            }
+            for boot_option in engine_section.boot_args {
+                if *network.mode() == NetworkMode::None
+                    && (boot_option.starts_with("ip=") || boot_option.starts_with("rd.neednet="))
+                {
+                    // without network there is nothing to configure
+                    continue;
+                } else if boot_option.starts_with("ip=") && vm_network.as_ref().and_then(|n| n.boot_arg()).is_some() {
+                    // the leased address replaces the configured one
+                    continue;
+                } else {
+                    boot_args.push(boot_option.to_owned());
+                }
+            }
+            if let Some(ip) = vm_network.as_ref().and_then(|n| n.boot_arg()) {
+                boot_args.push(ip);
+            }
+            if !engine_section.forward.is_empty() {
+                let forward: Vec<String> = engine_section
+                    .forward
+                    .iter()
+                    .enumerate()
+                    .map(|(index, forward)| format!("{}:{}:{}", forward.to, forward_port(index), forward.guest))
+                    .collect();
+                boot_args.push(format!("sci_forward={}", forward.join(",")));
+            }
+            let guest_mounts = get_guest_mounts();
+            if !guest_mounts.is_empty() && mmds_address.is_none() {
+                let drives: Vec<String> = guest_mounts
+                    .iter()
+                    .map(|(drive, target, read_only)| {
+                        format!("{}:{}:{}", drive, target.display(), if *read_only { "ro" } else { "rw" })
+                    })
+                    .collect();
+                boot_args.push(format!("sci_drives={}", drives.join(",")));
+            }
+            if !firecracker_config.boot_source.boot_args.is_empty() {
+                firecracker_config.boot_source.boot_args.push(' ');
+            }
+            firecracker_config.boot_source.boot_args.push_str(&boot_args.join(" "));
+            // with MMDS the command is part of the metadata
+            if mmds_address.is_none() {
+                if resume {
+                    firecracker_config.boot_source.boot_args.push_str(" run=vsock")
+                } else {
+                    firecracker_config.boot_source.boot_args.push_str(&format!(" run_b64={}", encode_run(&run)))
                 }
             }
+            if firecracker_config.boot_source.boot_args.len() >= defaults::KERNEL_CMDLINE_MAX {
+                error!(
+                    "Kernel cmdline of {} bytes exceeds the limit of {} bytes, shorten the command arguments",
+                    firecracker_config.boot_source.boot_args.len(),
+                    defaults::KERNEL_CMDLINE_MAX
+                );
+                if let Err(error) = vmnet::teardown(&get_meta_name(program_name)) {
+                    error!("Failed to remove VM network: {}", error)
+                }
+                exit(1)
+            }
+
+            // set path_on_host for rootfs
+            firecracker_config.drives[0].path_on_host = engine_section.rootfs_image_path.to_owned();
+
+            // set drive section for overlay
+            if engine_section.overlay_size.is_some() {
+                let vm_overlay_file = get_meta_file_name(program_name, defaults::FIRECRACKER_OVERLAY_DIR, "ext2");
+
+                let cache_type = engine_section.cache_type.clone().unwrap_or_default().to_string();
+
+                let drive = FireCrackerDrive {
+                    drive_id: "overlay".to_string(),
+                    path_on_host: vm_overlay_file,
+                    is_root_device: false,
+                    is_read_only: false,
+                    cache_type,
+                    rate_limiter: None,
+                };
+                firecracker_config.drives.push(drive);
+            }
+
+            // set drive section for the static data of a VM without overlay
+            if has_include_drive() {
+                firecracker_config.drives.push(FireCrackerDrive {
+                    drive_id: "include".to_string(),
+                    path_on_host: get_meta_file_name(program_name, defaults::FIRECRACKER_OVERLAY_DIR, "include"),
+                    is_root_device: false,
+                    is_read_only: true,
+                    cache_type: engine_section.cache_type.clone().unwrap_or_default().to_string(),
+                    rate_limiter: None,
+                });
+            }
+
+            // set drive sections for the disk images of the setup
+            for (index, mount) in config().setup.mounts().iter().enumerate() {
+                firecracker_config.drives.push(FireCrackerDrive {
+                    drive_id: format!("setup{}", index),
+                    path_on_host: mount.source().to_string_lossy().to_string(),
+                    is_root_device: false,
+                    is_read_only: mount.mode() == AccessMode::ReadOnly,
+                    cache_type: engine_section.cache_type.clone().unwrap_or_default().to_string(),
+                    rate_limiter: None,
+                });
+            }
+
+            // set drive sections for the extra data drives
+            for (index, drive) in engine_section.drives.iter().enumerate() {
+                firecracker_config.drives.push(FireCrackerDrive {
+                    drive_id: format!("data{}", index),
+                    path_on_host: get_drive_image(program_name, index, drive).to_string_lossy().to_string(),
+                    is_root_device: false,
+                    is_read_only: drive.read_only,
+                    cache_type: drive
+                        .cache_type
+                        .clone()
+                        .or_else(|| engine_section.cache_type.clone())
+                        .unwrap_or_default()
+                        .to_string(),
+                    rate_limiter: drive.rate_limiter.clone(),
+                });
+            }
+
+            // set the rate limiter of all other drives
+            for drive in firecracker_config.drives.iter_mut().filter(|drive| drive.rate_limiter.is_none()) {
+                drive.rate_limiter = engine_section.drive_rate_limiter.clone();
+            }
+
+            // set tap device and guest MAC, or drop the interface without network
+            match vm_network {
+                Some(vm_network) => {
+                    firecracker_config.network_interfaces[0].host_dev_name = vm_network.tap;
+                    firecracker_config.network_interfaces[0].guest_mac = vm_network.guest_mac;
+                    if let Some(rate_limiter) = &engine_section.network_rate_limiter {
+                        firecracker_config.network_interfaces[0].rx_rate_limiter = rate_limiter.rx().cloned();
+                        firecracker_config.network_interfaces[0].tx_rate_limiter = rate_limiter.tx().cloned();
+                    }
+                }
+                None => firecracker_config.network_interfaces.clear(),
+            }
+
+            // set MMDS data store on the network interface
+            if mmds_address.is_some() {
+                firecracker_config.mmds_config = Some(FireCrackerMmdsConfig {
+                    version: engine_section.mmds.as_ref().map(|mmds| mmds.version()).unwrap_or_default(),
+                    network_interfaces: firecracker_config.network_interfaces.iter().map(|i| i.iface_id.clone()).collect(),
+                    ipv4_address: mmds_address.clone(),
+                });
+            }
+
+            // set vsock name
+            firecracker_config.vsock.guest_cid = defaults::VM_CID;
+            firecracker_config.vsock.uds_path = format!("/run/sci_cmd_{}.sock", get_meta_name(program_name));
+
+            // set the FIFOs of the log collector, unless the template has its own
+            if !console::create_fifos(&get_meta_name(program_name)) {
+                panic!("Failed to create the log FIFOs of the VM")
+            }
+            if firecracker_config.logger.is_none() {
+                firecracker_config.logger = Some(FireCrackerLogger {
+                    log_path: vmlog::log_fifo(&get_meta_name(program_name)).display().to_string(),
+                    other: serde_json::Map::from_iter([("level".to_string(), json!("Info"))]),
+                });
+            }
+            if firecracker_config.metrics.is_none() {
+                firecracker_config.metrics = Some(FireCrackerMetrics {
+                    metrics_path: vmlog::metrics_fifo(&get_meta_name(program_name)).display().to_string(),
+                });
+            }
+
+            // move the files of the VM into the jail
+            if let Some(jailer) = &engine_section.jailer {
+                if !jail_config(&mut firecracker_config, jailer, &get_meta_name(program_name)) {
+                    panic!("Failed to setup the jail of the VM")
+                }
+            }
+
+            // set mem_size_mib
+            if let Some(mem_size_mib) = engine_section.mem_size_mib {
+                firecracker_config.machine_config.mem_size_mib = mem_size_mib
+            } else if let Some(memory) = resources.memory() {
+                firecracker_config.machine_config.mem_size_mib = (memory >> 20).max(1) as i64
+            }
+
+            // set vcpu_count
+            if let Some(vcpu_count) = engine_section.vcpu_count {
+                firecracker_config.machine_config.vcpu_count = vcpu_count;
+            } else if let Some(cpus) = resources.cpus() {
+                firecracker_config.machine_config.vcpu_count = cpus.ceil() as i64;
+            }
+
+            // set CPU options and extra devices
+            if engine_section.smt.is_some() {
+                firecracker_config.machine_config.smt = engine_section.smt;
+            }
+            if engine_section.cpu_template.is_some() {
+                firecracker_config.machine_config.cpu_template = engine_section.cpu_template;
+            }
+            if engine_section.balloon.is_some() {
+                firecracker_config.balloon = engine_section.balloon.clone();
+            }
+            if engine_section.entropy.is_some() {
+                firecracker_config.entropy = engine_section.entropy.clone();
+            }
+
+            debug(&serde_json::to_string(&firecracker_config).unwrap());
+            serde_json::to_writer(config_file, &firecracker_config).unwrap();
+
+            // write the flake metadata for MMDS
+            mmds_address.map(|_| {
+                let metadata = get_metadata(program_name, resume);
+                debug(&metadata.to_string());
+                match NamedTempFile::new() {
+                    Ok(metadata_file) => {
+                        serde_json::to_writer(&metadata_file, &metadata).unwrap();
+                        metadata_file
+                    }
+                    Err(error) => {
+                        panic!("Failed to create temporary file: {}", error)
+                    }
+                }
+            })
         }
         Err(error) => {
-            panic!("Failed to open {}: {}", defaults::FIRECRACKER_TEMPLATE, error)
+            panic!("{}", error)
         }
     }
 }
 
+/// Check if the setup section can be provided by a VM
+///
+/// Firecracker can not share host directories, sockets or
+/// devices with the guest. Only disk image files can be
+/// attached to the VM as extra drives. Host trust data is
+/// copied into the VM overlay, which therefore must exist.
+pub fn check_setup() -> bool {
+    let setup = &config().setup;
+    let mut supported = true;
+    if setup.home().is_some() || setup.cwd().is_some() {
+        error!("Sharing the home or current working directory is not supported by firecracker VMs");
+        supported = false;
+    }
+    if !setup.sockets().is_empty() || setup.dbus() {
+        error!("Passing host sockets or the D-Bus session bus is not supported by firecracker VMs");
+        supported = false;
+    }
+    if !setup.devices().is_empty() {
+        error!("Passing host device nodes is not supported by firecracker VMs");
+        supported = false;
+    }
+    let network = config().runtime().network;
+    if *network.mode() == NetworkMode::Host {
+        error!("Sharing the host network is not supported by firecracker VMs");
+        supported = false;
+    }
+    if !network.publish().is_empty() {
+        error!("Publishing ports is not supported by firecracker VMs");
+        supported = false;
+    }
+    let runtime = config().runtime();
+    if runtime.firecracker.jailer.is_some() && runtime.runas.name().is_some_and(|name| name != "root" && name != "#0") {
+        error!("The jailer must be run as root, not as {}", runtime.runas.name().unwrap());
+        supported = false;
+    }
+    for drive in config().runtime().firecracker.drives {
+        let mount = drive.mount.to_string_lossy();
+        if !drive.mount.is_absolute() || mount.contains([',', ':']) || mount.contains(char::is_whitespace) {
+            error!("Drive mount point {} must be an absolute path without commas, colons or spaces", mount);
+            supported = false;
+        }
+        match (&drive.path, &drive.dir) {
+            (Some(_), None) if !get_drive_source(&drive).is_file() => {
+                error!("Drive {} is not a disk image", get_drive_source(&drive).display());
+                supported = false;
+            }
+            (None, Some(_)) if !get_drive_source(&drive).is_dir() => {
+                error!("Drive {} is not a directory", get_drive_source(&drive).display());
+                supported = false;
+            }
+            (Some(_), None) | (None, Some(_)) => {}
+            _ => {
+                error!("Drive for {} needs either a path or a dir", mount);
+                supported = false;
+            }
+        }
+    }
+    for mount in setup.mounts() {
+        let target = mount.target().to_string_lossy();
+        if !mount.target().is_absolute() || target.contains([',', ':']) || target.contains(char::is_whitespace) {
+            error!("Setup mount point {} must be an absolute path without commas, colons or spaces", target);
+            supported = false;
+        }
+        if !mount.source().is_file() {
+            error!(
+                "Setup mount {} is not a disk image, only image files can be attached to firecracker VMs",
+                mount.source().display()
+            );
+            supported = false;
+        }
+    }
+    supported
+}
+
 /// setup application command path name
 ///
 /// This is either the program name specified at registration
 /// time or the configured target application from the flake
 /// configuration file
 pub fn get_target_app_path(program_name: &str) -> String {
-    config().vm.target_app_path.unwrap_or(program_name).to_owned()
+    config().expand(config().vm.target_app_path.unwrap_or(program_name))
 }
 
 pub fn init_meta_dirs() {
     let mut meta_dirs: Vec<&str> = Vec::new();
     meta_dirs.push(defaults::FIRECRACKER_OVERLAY_DIR);
     meta_dirs.push(defaults::FIRECRACKER_VMID_DIR);
+    meta_dirs.push(defaults::FIRECRACKER_FORWARD_DIR);
+    meta_dirs.push(defaults::FIRECRACKER_EXEC_DIR);
+    meta_dirs.push(vmlog::LOG_DIR);
     for meta_dir in meta_dirs {
         if !Path::new(meta_dir).is_dir() && !mkdir(meta_dir, "777", User::ROOT) {
             panic!("Failed to create {}", meta_dir);
@@ -650,25 +1387,86 @@ pub fn init_meta_dirs() {
     }
 }
 
-/// setup run commandline for the command call
-pub fn get_run_cmdline(program_name: &str, quote_for_kernel_cmdline: bool) -> Vec<String> {
+/// Get the flake metadata, published to the guest through MMDS
+///
+/// sci takes the command, its environment, user and mounts from
+/// it in place of the kernel cmdline. In resume mode the commands
+/// are sent through the vsock, hence the command is "vsock".
+pub fn get_metadata(program_name: &String, resume: bool) -> serde_json::Value {
+    let RuntimeSection { identity, .. } = config().runtime();
+    let command = if resume { vec!["vsock".to_string()] } else { get_app_cmdline(program_name) };
+    let env: serde_json::Map<String, serde_json::Value> =
+        config().env().into_iter().map(|(name, value)| (name, json!(value))).collect();
+    let user = identity.caller_ids().map(|(uid, gid)| json!({ "uid": uid, "gid": gid }));
+    let mounts: Vec<serde_json::Value> = get_guest_mounts()
+        .into_iter()
+        .map(|(drive, target, read_only)| json!({ "drive": drive, "target": target, "read_only": read_only }))
+        .collect();
+    json!({
+        "flake": {
+            "name": program_name,
+            "instance": get_meta_name(program_name),
+            "command": command,
+            "env": env,
+            "user": user,
+            "mounts": mounts,
+        }
+    })
+}
+
+/// Get the drives sci mounts in the guest, as drive id, mount
+/// point and read-only flag: the disk images of the setup
+/// followed by the extra data drives
+pub fn get_guest_mounts() -> Vec<(String, PathBuf, bool)> {
+    let setup = config()
+        .setup
+        .mounts()
+        .iter()
+        .enumerate()
+        .map(|(index, mount)| (format!("setup{}", index), mount.target().clone(), mount.mode() == AccessMode::ReadOnly));
+    let data = config()
+        .runtime()
+        .firecracker
+        .drives
+        .into_iter()
+        .enumerate()
+        .map(|(index, drive)| (format!("data{}", index), drive.mount, drive.read_only));
+    setup.chain(data).collect()
+}
+
+/// setup the command of the app with the caller arguments
+pub fn get_app_cmdline(program_name: &str) -> Vec<String> {
     let args: Vec<String> = env::args().collect();
-    let mut run: Vec<String> = Vec::new();
-    let target_app_path = get_target_app_path(program_name);
-    run.push(target_app_path);
+    let mut app: Vec<String> = vec![get_target_app_path(program_name)];
     for arg in &args[1..] {
         debug(&format!("Got Argument: {}", arg));
         if !arg.starts_with('@') {
-            if quote_for_kernel_cmdline {
-                run.push(arg.replace('-', "\\-").to_string());
-            } else {
-                run.push(arg.to_string());
-            }
+            app.push(arg.to_string());
         }
     }
+    app
+}
+
+/// setup run commandline for the command call
+pub fn get_run_cmdline(program_name: &str) -> Vec<String> {
+    // environment assignments preceding the command, picked up by sci
+    let mut run: Vec<String> = config().env().into_iter().map(|(name, value)| format!("{}={}", name, value)).collect();
+    run.extend(get_app_cmdline(program_name));
     run
 }
 
+/// Encode the run commandline for the kernel cmdline and exec
+/// requests, as base64 of the NUL terminated arguments. This keeps quotes, newlines
+/// and any other text of the arguments intact.
+pub fn encode_run(run: &[String]) -> String {
+    let mut argv: Vec<u8> = Vec::new();
+    for arg in run {
+        argv.extend_from_slice(arg.as_bytes());
+        argv.push(0);
+    }
+    general_purpose::STANDARD.encode(argv)
+}
+
 /// Check if VM with specified vmid is running
 pub fn vm_running(vmid: &String, user: User) -> bool {
     let mut running_status = false;
@@ -728,11 +1526,22 @@ pub fn gc_meta_files(vm_id_file: &String, user: User, program_name: &String, res
                         error!("Failed to remove VMID: {:?}", error)
                     }
                 }
-                let vsock_uds_path = format!("/run/sci_cmd_{}.sock", get_meta_name(program_name));
+                let vsock_uds_path = get_vsock_uds_path(program_name);
                 if Path::new(&vsock_uds_path).exists() {
                     debug(&format!("Deleting {}", vsock_uds_path));
                     delete_file(&vsock_uds_path, user);
                 }
+                let _ = fs::remove_file(Path::new(vm_id_file).with_extension("uds"));
+                let instance = Path::new(&vm_id_file).file_stem().unwrap().to_string_lossy();
+                if instance == get_meta_name(program_name) {
+                    // the drives are only known for the instance of this program
+                    sync_drives(program_name);
+                }
+                remove_jail(&instance);
+                stop_forwards(&instance, user);
+                if let Err(error) = vmnet::teardown(&instance) {
+                    error!("Failed to remove VM network: {}", error)
+                }
                 let vm_overlay_file = format!(
                     "{}/{}",
                     defaults::FIRECRACKER_OVERLAY_DIR,
@@ -747,6 +1556,13 @@ pub fn gc_meta_files(vm_id_file: &String, user: User, program_name: &String, res
                         }
                     }
                 }
+                let vm_include_file = vm_overlay_file.replace(".ext2", ".include");
+                if Path::new(&vm_include_file).exists() && !resume {
+                    debug(&format!("Deleting {}", vm_include_file));
+                    if let Err(error) = fs::remove_file(&vm_include_file) {
+                        error!("Failed to remove include image: {:?}", error)
+                    }
+                }
             } else {
                 vmid_status = true
             }
@@ -794,145 +1610,475 @@ pub fn delete_file(filename: &String, user: User) -> bool {
     true
 }
 
-/// Sync custom include data to target path
-pub fn sync_includes(target: &str, user: User) -> bool {
-    let tar_includes = config().tars();
-    let mut status_code = 0;
-    for tar in tar_includes {
-        debug(&format!("Adding tar include: [{}]", tar));
-        let mut call = user.run("tar");
-        call.arg("-C").arg(target).arg("-xf").arg(tar);
+/// Check if the VM gets the read-only include drive, which
+/// carries the static data in place of an overlay
+pub fn has_include_drive() -> bool {
+    config().runtime().firecracker.overlay_size.is_none()
+        && (!config().tars().is_empty() || !config().setup.host_trust().is_empty())
+}
+
+/// Stage the content of the VM overlay below the given directory
+///
+/// The layout matches the one sci expects on the overlay device:
+/// the upper and work directories of the root overlay, with the
+/// includes and the host trust data in the upper one.
+pub fn stage_overlay(staging: &Path, rootfs_image_path: &str, has_includes: bool) -> bool {
+    let upper = staging.join(defaults::OVERLAY_UPPER_DIR);
+    for dir in [&upper, &staging.join(defaults::OVERLAY_WORK_DIR)] {
+        if let Err(error) = fs::create_dir_all(dir) {
+            error!("Error creating directory {}: {}", dir.display(), error);
+            return false;
+        }
+    }
+    stage_root(&upper, rootfs_image_path, has_includes)
+}
+
+/// Stage the includes and the host trust data below the given
+/// directory, which is stacked on top of the VM root filesystem
+///
+/// Staging runs in the user namespace of staging_command, the
+/// directories which also exist in the rootfs image get their
+/// mode and owner from there.
+pub fn stage_root(root: &Path, rootfs_image_path: &str, has_includes: bool) -> bool {
+    if has_includes {
+        debug("Staging includes...");
+        if !stage_includes(root) {
+            return false;
+        }
+    }
+    if !config().setup.host_trust().is_empty() {
+        debug("Staging host trust...");
+        if !stage_host_trust(root, rootfs_image_path) {
+            return false;
+        }
+    }
+    copy_rootfs_dirs(root, rootfs_image_path)
+}
+
+/// Get a command run in the user namespace the VM root is staged in
+///
+/// The caller is root in the namespace and its subordinate IDs, if
+/// any, are mapped from ID 1 on. This way an unprivileged caller can
+/// stage files of any owner, which keep their owner in the image.
+/// Run as root there is no need for a namespace.
+pub fn staging_command(program: &str) -> Command {
+    if geteuid().is_root() {
+        return Command::new(program);
+    }
+    let mut unshare = Command::new(defaults::UNSHARE);
+    unshare.arg("--map-root-user");
+    if let (Some(uids), Some(gids)) = (subordinate_ids(defaults::SUBUID), subordinate_ids(defaults::SUBGID)) {
+        unshare.arg(format!("--map-users={},1,{}", uids.0, uids.1));
+        unshare.arg(format!("--map-groups={},1,{}", gids.0, gids.1));
+    }
+    unshare.arg(program);
+    unshare
+}
+
+/// Check if files of any owner can be staged
+fn staging_keeps_owners() -> bool {
+    geteuid().is_root() || (subordinate_ids(defaults::SUBUID).is_some() && subordinate_ids(defaults::SUBGID).is_some())
+}
+
+/// Get the first range of subordinate IDs of the caller from
+/// the given subuid or subgid file, as start and count
+fn subordinate_ids(file: &str) -> Option<(u32, u32)> {
+    let uid = geteuid();
+    let name = SystemUser::from_uid(uid).ok().flatten().map(|user| user.name);
+    fs::read_to_string(file).ok()?.lines().find_map(|line| match line.split(':').collect::<Vec<&str>>()[..] {
+        [owner, start, count] if Some(owner) == name.as_deref() || owner == uid.to_string() => {
+            Some((start.parse().ok()?, count.parse().ok()?))
+        }
+        _ => None,
+    })
+}
+
+/// Remove the staged tree, which might hold files of
+/// other owners, from within the staging namespace
+fn remove_staging(staging: &Path) {
+    let mut call = staging_command("rm");
+    call.arg("-rf").arg("--one-file-system").arg(staging);
+    debug(&format!("{:?}", call.get_args()));
+    match call.output() {
+        Ok(output) if !output.status.success() => {
+            error!("Failed to remove {}: {}", staging.display(), String::from_utf8_lossy(&output.stderr))
+        }
+        Ok(_) => {}
+        Err(error) => error!("Failed to execute rm: {:?}", error),
+    }
+}
+
+/// Give the staged directories which also exist in the rootfs image
+/// the mode and owner they have there
+///
+/// The staged tree is stacked on top of the rootfs, such that its
+/// directories hide the mode and owner of the rootfs directories,
+/// e.g. of /tmp or /root. Directories created while staging get them
+/// from the umask and the caller otherwise.
+pub fn copy_rootfs_dirs(root: &Path, rootfs_image_path: &str) -> bool {
+    copy_rootfs_dir(root, Path::new("/"), rootfs_image_path)
+}
+
+fn copy_rootfs_dir(staged: &Path, dir: &Path, rootfs_image_path: &str) -> bool {
+    let Some(stat) = rootfs_dir_stat(rootfs_image_path, dir) else {
+        // below a directory missing in the rootfs nothing exists there
+        return true;
+    };
+    // descend first, the mode of the rootfs might lock us out
+    if let Ok(entries) = fs::read_dir(staged) {
+        for entry in entries.flatten() {
+            if entry.file_type().is_ok_and(|file_type| file_type.is_dir())
+                && !copy_rootfs_dir(&entry.path(), &dir.join(entry.file_name()), rootfs_image_path)
+            {
+                return false;
+            }
+        }
+    }
+    let mut chown = staging_command("chown");
+    chown.arg(format!("{}:{}", stat.uid, stat.gid)).arg(staged);
+    let mut chmod = staging_command("chmod");
+    chmod.arg(&stat.mode).arg(staged);
+    for mut call in [chown, chmod] {
         debug(&format!("{:?}", call.get_args()));
         match call.output() {
-            Ok(output) => {
-                debug(&String::from_utf8_lossy(&output.stdout));
-                debug(&String::from_utf8_lossy(&output.stderr));
-                status_code = output.status.code().unwrap();
+            Ok(output) if !output.status.success() => {
+                error!("Failed to set up {}: {}", staged.display(), String::from_utf8_lossy(&output.stderr));
+                return false;
             }
+            Ok(_) => {}
             Err(error) => {
-                panic!("Failed to execute tar: {:?}", error)
+                error!("Failed to execute {:?}: {:?}", call.get_program(), error);
+                return false;
             }
         }
     }
-    if status_code == 0 {
-        return true;
-    }
-    false
+    true
 }
 
-/// Mount VM with overlay below given sub_dir
-pub fn mount_vm(sub_dir: &str, rootfs_image_path: &str, overlay_path: &str, user: User) -> String {
-    let failed = "".to_string();
-    // 1. create overlay image mount structure
-    for image_dir in [defaults::IMAGE_ROOT, defaults::IMAGE_OVERLAY].iter() {
-        let dir_path = format!("{}/{}", sub_dir, image_dir);
-        if !Path::new(&dir_path).exists() {
-            match fs::create_dir_all(&dir_path) {
-                Ok(_) => {}
-                Err(error) => {
-                    error!("Error creating directory {}: {}", dir_path, error);
-                    return failed;
+/// Create the read-only include image from the staged tree,
+/// sized to its content
+pub fn create_include_image(vm_include_file: &str, staging: &Path) -> bool {
+    let (bytes, entries) = tree_usage(staging);
+    // leave room for the filesystem metadata
+    let size = bytes + bytes / 10 + (defaults::INCLUDE_IMAGE_RESERVE_MIB << 20);
+    create_image(vm_include_file, size, Some(entries * 2 + 64), staging)
+}
+
+/// Get the disk usage in blocks of 4k and the number of entries below a directory
+fn tree_usage(dir: &Path) -> (u64, u64) {
+    let mut usage = (0, 0);
+    if let Ok(entries) = fs::read_dir(dir) {
+        for entry in entries.flatten() {
+            if let Ok(metadata) = entry.path().symlink_metadata() {
+                usage.0 += metadata.len().div_ceil(4096) * 4096;
+                usage.1 += 1;
+                if metadata.is_dir() {
+                    let (bytes, entries) = tree_usage(&entry.path());
+                    usage.0 += bytes;
+                    usage.1 += entries;
                 }
             }
         }
     }
-    // 2. mount VM image
-    let image_mount_point = format!("{}/{}", sub_dir, defaults::IMAGE_ROOT);
-    let mut mount_image = user.run("mount");
-    mount_image.arg(rootfs_image_path).arg(&image_mount_point);
-    debug(&format!("{:?}", mount_image.get_args()));
-    match mount_image.output() {
-        Ok(output) => {
-            if !output.status.success() {
-                error!("Failed to mount VM image: {}", String::from_utf8_lossy(&output.stderr));
-                return failed;
+    usage
+}
+
+/// Create an ext2 image of the given size from the staged tree
+///
+/// mkfs runs in the staging namespace, such that the staged files
+/// keep their owners and the files of the caller are owned by root.
+pub fn create_image(image_file: &str, size: u64, inodes: Option<u64>, staging: &Path) -> bool {
+    make_filesystem(image_file, size, inodes, staging, true)
+}
+
+/// Create an ext2 image of the given size from a directory, with
+/// the owners of the files in the directory (map_root: false) or
+/// the owners in the staging namespace (map_root: true)
+fn make_filesystem(image_file: &str, size: u64, inodes: Option<u64>, source: &Path, map_root: bool) -> bool {
+    match std::fs::File::create(image_file) {
+        Ok(image_file_fd) => {
+            if let Err(error) = image_file_fd.set_len(size) {
+                error!("Failed to size image {}: {}", image_file, error);
+                return false;
             }
         }
         Err(error) => {
-            error!("Failed to execute mount: {:?}", error);
-            return failed;
+            error!("Failed to create image {}: {}", image_file, error);
+            return false;
         }
     }
-    // 3. mount Overlay image
-    let overlay_mount_point = format!("{}/{}", sub_dir, defaults::IMAGE_OVERLAY);
-    let mut mount_overlay = user.run("mount");
-    mount_overlay.arg(overlay_path).arg(&overlay_mount_point);
-    debug(&format!("{:?}", mount_overlay.get_args()));
-    match mount_overlay.output() {
+    let mut mkfs = if map_root { staging_command("mkfs.ext2") } else { Command::new("mkfs.ext2") };
+    mkfs.arg("-F").arg("-q");
+    if let Some(inodes) = inodes {
+        mkfs.arg("-N").arg(inodes.to_string());
+    }
+    mkfs.arg("-d").arg(source).arg(image_file);
+    debug(&format!("{:?}", mkfs.get_args()));
+    match mkfs.output() {
         Ok(output) => {
             if !output.status.success() {
-                error!("Failed to mount VM image: {}", String::from_utf8_lossy(&output.stderr));
-                return failed;
+                error!("Failed to create filesystem: {}", String::from_utf8_lossy(&output.stderr));
+                return false;
             }
         }
         Err(error) => {
-            error!("Failed to execute mount: {:?}", error);
-            return failed;
-        }
-    }
-    // 4. mount as overlay
-    for overlay_dir in [defaults::OVERLAY_ROOT, defaults::OVERLAY_UPPER, defaults::OVERLAY_WORK].iter() {
-        let dir_path = format!("{}/{}", sub_dir, overlay_dir);
-        if !Path::new(&dir_path).exists() && !mkdir(&dir_path, "755", User::ROOT) {
-            return failed;
-        }
-    }
-    let root_mount_point = format!("{}/{}", sub_dir, defaults::OVERLAY_ROOT);
-    let mut mount_overlay = user.run("mount");
-    mount_overlay
-        .arg("-t")
-        .arg("overlay")
-        .arg("overlayfs")
-        .arg("-o")
-        .arg(format!(
-            "lowerdir={},upperdir={}/{},workdir={}/{}",
-            &image_mount_point,
-            sub_dir,
-            defaults::OVERLAY_UPPER,
-            sub_dir,
-            defaults::OVERLAY_WORK
-        ))
-        .arg(&root_mount_point);
-    debug(&format!("{:?}", mount_overlay.get_args()));
-    match mount_overlay.output() {
+            error!("Failed to execute mkfs {:?}", error);
+            return false;
+        }
+    }
+    true
+}
+
+/// Get the host path of a data drive, a disk image or a directory
+pub fn get_drive_source(drive: &Drive) -> PathBuf {
+    let source = drive.path.as_ref().or(drive.dir.as_ref()).map(|p| p.to_string_lossy().to_string()).unwrap_or_default();
+    PathBuf::from(config().expand(&source))
+}
+
+/// Get the disk image attached for a data drive. Host
+/// directories are packed into a per instance image
+pub fn get_drive_image(program_name: &String, index: usize, drive: &Drive) -> PathBuf {
+    if drive.dir.is_some() {
+        PathBuf::from(get_meta_file_name(program_name, defaults::FIRECRACKER_OVERLAY_DIR, &format!("data{}", index)))
+    } else {
+        get_drive_source(drive)
+    }
+}
+
+/// Pack the host directories of the data drives into disk images
+///
+/// The images keep the owners of the files in the directories. Unless
+/// a size is configured, an image is sized to the directory content plus
+/// a reserve for the data written by read-write drives.
+pub fn create_drive_images(program_name: &String) -> bool {
+    for (index, drive) in config().runtime().firecracker.drives.iter().enumerate() {
+        if drive.dir.is_none() {
+            continue;
+        }
+        let dir = get_drive_source(drive);
+        let image = get_drive_image(program_name, index, drive);
+        let (bytes, entries) = tree_usage(&dir);
+        let size = match &drive.size {
+            Some(size) => match size.parse::<ByteUnit>() {
+                Ok(size) => size.as_u64(),
+                Err(error) => {
+                    error!("Invalid size {} of drive {}: {}", size, dir.display(), error);
+                    return false;
+                }
+            },
+            None if drive.read_only => bytes + bytes / 10 + (defaults::INCLUDE_IMAGE_RESERVE_MIB << 20),
+            None => bytes + bytes / 10 + (defaults::DRIVE_IMAGE_RESERVE_MIB << 20),
+        };
+        debug(&format!("Packing {} into {}", dir.display(), image.display()));
+        if !make_filesystem(&image.to_string_lossy(), size, Some(entries * 2 + 1024), &dir, false) {
+            return false;
+        }
+    }
+    true
+}
+
+/// Sync the images of read-write host directory drives back to
+/// their directories and delete the images of all host directories
+///
+/// Called once the VM has exited, the images are created
+/// from the directories again at the next launch.
+pub fn sync_drives(program_name: &String) {
+    for (index, drive) in config().runtime().firecracker.drives.iter().enumerate() {
+        let image = get_drive_image(program_name, index, drive);
+        if drive.dir.is_none() || !image.exists() {
+            continue;
+        }
+        if !drive.read_only && !sync_image(&image, &get_drive_source(drive)) {
+            // keep the image, such that no data gets lost
+            error!("Changes of drive {} are kept in {}", get_drive_source(drive).display(), image.display());
+            continue;
+        }
+        debug(&format!("Deleting {}", image.display()));
+        if let Err(error) = fs::remove_file(&image) {
+            error!("Failed to remove drive image: {:?}", error)
+        }
+    }
+}
+
+/// Sync the content of an ext2 image to a directory, without mounting it
+///
+/// The image content is dumped by debugfs next to the directory and
+/// then synced by rsync, which also removes the deleted files.
+fn sync_image(image: &Path, dir: &Path) -> bool {
+    let dump = match tempfile::Builder::new().prefix(".flake-sync").tempdir_in(dir.parent().unwrap_or(Path::new("/"))) {
+        Ok(dump) => dump,
+        Err(error) => {
+            error!("Failed to create temporary directory: {}", error);
+            return false;
+        }
+    };
+    debug(&format!("Syncing {} to {}", image.display(), dir.display()));
+
+    // debugfs can not dump the root directory itself, only its entries
+    let mut list = Command::new(defaults::DEBUGFS);
+    list.arg("-R").arg("ls -p /").arg(image);
+    debug(&format!("{:?}", list.get_args()));
+    let entries: Vec<String> = match list.output() {
+        Ok(output) if output.status.success() => String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .filter_map(|line| line.split('/').nth(5).map(|name| name.to_string()))
+            .filter(|name| ![".", "..", "lost+found", ""].contains(&name.as_str()))
+            .collect(),
         Ok(output) => {
-            if !output.status.success() {
-                error!("Failed to overlay mount VM image: {}", String::from_utf8_lossy(&output.stderr));
-                return failed;
+            error!("Failed to list {}: {}", image.display(), String::from_utf8_lossy(&output.stderr));
+            return false;
+        }
+        Err(error) => {
+            error!("Failed to execute debugfs: {:?}", error);
+            return false;
+        }
+    };
+    if !entries.is_empty() {
+        let sources: Vec<String> = entries.iter().map(|name| format!("\"/{}\"", name)).collect();
+        let mut rdump = Command::new(defaults::DEBUGFS);
+        rdump.arg("-R").arg(format!("rdump {} \"{}\"", sources.join(" "), dump.path().display())).arg(image);
+        debug(&format!("{:?}", rdump.get_args()));
+        match rdump.output() {
+            Ok(output) => {
+                // debugfs reports errors of commands on stderr only. Files
+                // owned by others can not be chowned without root, which is fine
+                let stderr = String::from_utf8_lossy(&output.stderr);
+                let errors: Vec<&str> =
+                    stderr.lines().filter(|line| line.contains(": ") && !line.contains("while changing ownership")).collect();
+                if !output.status.success() || !errors.is_empty() {
+                    error!("Failed to dump {}: {}", image.display(), errors.join("\n"));
+                    return false;
+                }
+            }
+            Err(error) => {
+                error!("Failed to execute debugfs: {:?}", error);
+                return false;
             }
         }
+    }
+
+    let mut rsync = Command::new(defaults::RSYNC);
+    rsync.arg("-rlpt").arg("--delete").arg(format!("{}/", dump.path().display())).arg(format!("{}/", dir.display()));
+    debug(&format!("{:?}", rsync.get_args()));
+    match rsync.output() {
+        Ok(output) if output.status.success() => true,
+        Ok(output) => {
+            error!("Failed to sync {}: {}", dir.display(), String::from_utf8_lossy(&output.stderr));
+            false
+        }
         Err(error) => {
-            error!("Failed to execute mount: {:?}", error);
-            return failed;
+            error!("Failed to execute rsync: {:?}", error);
+            false
         }
     }
-    root_mount_point
 }
 
-/// Umount VM image
-pub fn umount_vm(sub_dir: &str, user: User) -> bool {
-    let mut status_code = 0;
-    for mount_point in [defaults::OVERLAY_ROOT, defaults::IMAGE_OVERLAY, defaults::IMAGE_ROOT].iter() {
-        let mut umount = user.run("umount");
-        umount.stderr(Stdio::null());
-        umount.stdout(Stdio::null());
-        umount.arg(format!("{}/{}", &sub_dir, &mount_point));
-        debug(&format!("{:?}", umount.get_args()));
-        match umount.status() {
-            Ok(status) => {
-                status_code += status.code().unwrap();
+/// Unpack custom include data to target path, keeping
+/// the owners of the files if the staging namespace allows
+pub fn stage_includes(target: &Path) -> bool {
+    for tar in config().tars() {
+        debug(&format!("Adding tar include: [{}]", tar));
+        let mut call = staging_command("tar");
+        call.arg("-C").arg(target);
+        if staging_keeps_owners() {
+            call.arg("--same-owner").arg("--numeric-owner");
+        } else {
+            debug("No subordinate IDs, the include files are owned by root");
+            call.arg("--no-same-owner");
+        }
+        call.arg("--preserve-permissions").arg("-xf").arg(&tar);
+        debug(&format!("{:?}", call.get_args()));
+        match call.output() {
+            Ok(output) => {
+                debug(&String::from_utf8_lossy(&output.stdout));
+                debug(&String::from_utf8_lossy(&output.stderr));
+                if !output.status.success() {
+                    error!("Failed to unpack {}: {}", tar, String::from_utf8_lossy(&output.stderr));
+                    return false;
+                }
             }
             Err(error) => {
-                error!("Failed to execute umount: {:?}", error)
+                panic!("Failed to execute tar: {:?}", error)
             }
         }
     }
-    if status_code > 0 {
-        return false;
+    true
+}
+
+/// Copy trust data of the host to the target path
+///
+/// Targets below a directory which does not exist in the
+/// rootfs image of the VM are skipped.
+pub fn stage_host_trust(target: &Path, rootfs_image_path: &str) -> bool {
+    for integration in config().setup.trust_integrations() {
+        if let HostIntegration::Mount(mount) = integration {
+            let parent = mount.target().parent().unwrap_or(Path::new("/"));
+            if !rootfs_has_dir(rootfs_image_path, parent) {
+                debug(&format!("Skipping host trust file {}", mount.target().display()));
+                continue;
+            }
+            let dest = target.join(mount.target().strip_prefix("/").unwrap_or(mount.target()));
+            // the includes might have left directories of other owners
+            let mut install = staging_command("install");
+            install.arg("-D").arg("-m").arg("0644").arg(mount.source()).arg(&dest);
+            debug(&format!("{:?}", install.get_args()));
+            match install.output() {
+                Ok(output) if !output.status.success() => {
+                    error!("Failed to copy {}: {}", mount.source().display(), String::from_utf8_lossy(&output.stderr));
+                    return false;
+                }
+                Ok(_) => {}
+                Err(error) => {
+                    error!("Failed to execute install: {:?}", error);
+                    return false;
+                }
+            }
+        }
     }
     true
 }
 
+/// Check for a directory in the rootfs image, without mounting it
+pub fn rootfs_has_dir(rootfs_image_path: &str, dir: &Path) -> bool {
+    rootfs_dir_stat(rootfs_image_path, dir).is_some()
+}
+
+/// Mode and owner of a directory in the rootfs image
+#[derive(Debug, PartialEq)]
+pub struct RootfsDir {
+    pub mode: String,
+    pub uid: String,
+    pub gid: String,
+}
+
+/// Get the mode and owner of a directory in the rootfs image, without mounting it
+pub fn rootfs_dir_stat(rootfs_image_path: &str, dir: &Path) -> Option<RootfsDir> {
+    let mut call = Command::new(defaults::DEBUGFS);
+    call.arg("-R").arg(format!("stat {}", dir.display())).arg(rootfs_image_path);
+    debug(&format!("{:?}", call.get_args()));
+    match call.output() {
+        Ok(output) => parse_dir_stat(&String::from_utf8_lossy(&output.stdout)),
+        Err(error) => {
+            error!("Failed to execute debugfs: {:?}", error);
+            None
+        }
+    }
+}
+
+/// Parse the output of debugfs stat, e.g.
+///
+/// Inode: 12   Type: directory    Mode:  1777   Flags: 0x80000
+/// User:     0   Group:     0   Project:     0   Size: 4096
+fn parse_dir_stat(stat: &str) -> Option<RootfsDir> {
+    if !stat.contains("Type: directory") {
+        return None;
+    }
+    let words: Vec<&str> = stat.split_whitespace().collect();
+    let field =
+        |name: &str| words.iter().position(|word| *word == name).and_then(|at| words.get(at + 1)).map(|value| value.to_string());
+    Some(RootfsDir { mode: field("Mode:")?, uid: field("User:")?, gid: field("Group:")? })
+}
+
 /// Make directory via sudo
 pub fn mkdir(dirname: &str, mode: &str, user: User) -> bool {
     let mut call = user.run("mkdir");
@@ -946,3 +2092,86 @@ pub fn mkdir(dirname: &str, mode: &str, user: User) -> bool {
     }
     true
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_exec_request() {
+        let run = vec!["GREETING=it's a \"test\"".to_string(), "echo".to_string(), "hello world".to_string()];
+        let request = exec_request(&run, 50000);
+        assert!(!request.contains('\n'));
+        let fields: Vec<&str> = request.split(' ').collect();
+        assert_eq!(fields[..2], ["exec", "50000"]);
+        assert_eq!(fields.len(), 3);
+        let argv = general_purpose::STANDARD.decode(fields[2]).unwrap();
+        let args: Vec<String> = argv
+            .strip_suffix(&[0])
+            .unwrap()
+            .split(|byte| *byte == 0)
+            .map(|arg| String::from_utf8(arg.to_vec()).unwrap())
+            .collect();
+        assert_eq!(args, run);
+    }
+
+    #[test]
+    fn test_valid_instance_name() {
+        assert!(valid_instance_name("python3.11"));
+        assert!(valid_instance_name("my-app@work_1"));
+        assert!(!valid_instance_name("app@"));
+        assert!(!valid_instance_name("app@x'; rm -rf /"));
+        assert!(!valid_instance_name("app@a b"));
+        assert!(!valid_instance_name("app@../x"));
+    }
+
+    #[test]
+    fn test_copy_rootfs_dirs() {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = |path: &Path| fs::metadata(path).unwrap().permissions().mode() & 0o7777;
+        let set_mode = |path: &Path, mode: u32| fs::set_permissions(path, fs::Permissions::from_mode(mode)).unwrap();
+
+        // rootfs with the typical modes of /tmp and /root
+        let rootfs = tempdir().unwrap();
+        set_mode(rootfs.path(), 0o755);
+        for (dir, dir_mode) in [("tmp", 0o1777), ("root", 0o700), ("etc", 0o755)] {
+            fs::create_dir(rootfs.path().join(dir)).unwrap();
+            set_mode(&rootfs.path().join(dir), dir_mode);
+        }
+        if geteuid().is_root() {
+            std::os::unix::fs::chown(rootfs.path().join("root"), Some(1000), Some(1000)).unwrap();
+        }
+        let images = tempdir().unwrap();
+        let image = images.path().join("rootfs.ext2").to_string_lossy().to_string();
+        assert!(make_filesystem(&image, 8 << 20, None, rootfs.path(), false));
+
+        // staged tree with directories created by the caller
+        let staging = tempdir().unwrap();
+        for dir in ["tmp/cache", "root/.config", "opt/app"] {
+            fs::create_dir_all(staging.path().join(dir)).unwrap();
+        }
+        set_mode(&staging.path().join("opt"), 0o750);
+        assert!(copy_rootfs_dirs(staging.path(), &image));
+
+        assert_eq!(mode(staging.path()), 0o755);
+        assert_eq!(mode(&staging.path().join("tmp")), 0o1777);
+        assert_eq!(mode(&staging.path().join("root")), 0o700);
+        // directories which do not exist in the rootfs are kept
+        assert_eq!(mode(&staging.path().join("opt")), 0o750);
+        let owner = fs::metadata(rootfs.path().join("root")).unwrap();
+        let staged = fs::metadata(staging.path().join("root")).unwrap();
+        assert_eq!((staged.uid(), staged.gid()), (owner.uid(), owner.gid()));
+    }
+
+    #[test]
+    fn test_parse_dir_stat() {
+        let stat = "Inode: 12   Type: directory    Mode:  1777   Flags: 0x80000\n\
+            Generation: 0    Version: 0x00000000\n\
+            User:  1000   Group:   100   Project:     0   Size: 4096\n";
+        assert_eq!(
+            parse_dir_stat(stat),
+            Some(RootfsDir { mode: "1777".to_string(), uid: "1000".to_string(), gid: "100".to_string() })
+        );
+        assert_eq!(parse_dir_stat(&stat.replace("directory", "regular")), None);
+    }
+}
@@ -28,13 +28,23 @@ pub const PIVOT_ROOT: &str = "/sbin/pivot_root";
 pub const OVERLAY_ROOT: &str = "/overlayroot/rootfs";
 pub const OVERLAY_UPPER: &str = "/overlayroot/rootfs_upper";
 pub const OVERLAY_WORK: &str = "/overlayroot/rootfs_work";
+pub const INCLUDE_ROOT: &str = "/overlayroot/include";
 pub const PROBE_MODULE: &str = "/sbin/modprobe";
 pub const SYSTEMD_NETWORK_RESOLV_CONF: &str = "/run/systemd/resolve/resolv.conf";
 pub const VM_QUIT: &str = "sci_quit";
+pub const VM_SESSIONS: &str = "sci_sessions";
 pub const VHOST_TRANSPORT: &str = "vmw_vsock_virtio_transport";
 pub const SOCAT: &str = "/usr/bin/socat";
+pub const SCI: &str = "/usr/sbin/sci";
+pub const SCI_EXEC: &str = "SCI_EXEC";
 pub const VM_PORT: u32 = 52;
 pub const GUEST_CID: u32 = 3;
+pub const KERNEL_CMDLINE_MAX: usize = 2048;
+pub const MMDS_RETRIES: u32 = 50;
+pub const MMDS_RETRY_MSEC: u64 = 100;
+pub const MMDS_TIMEOUT_MSEC: u64 = 1000;
+pub const SESSION_TERM_MSEC: u64 = 5000;
+pub const SESSION_WAIT_MSEC: u64 = 100;
 
 pub fn debug(message: &str) {
     if env::var("PILOT_DEBUG").is_ok() {
@@ -26,39 +26,54 @@ extern crate log;
 extern crate shell_words;
 
 pub mod defaults;
+pub mod mmds;
+pub mod session;
 
 use std::env;
 use std::os::unix::fs::symlink;
 use std::path::Path;
-use std::process::Command;
+use std::process::{exit, Command};
 use std::os::unix::process::CommandExt;
 use system_shutdown::force_reboot;
 use std::fs;
-use sys_mount::Mount;
+use sys_mount::{Mount, MountFlags};
 use env_logger::Env;
 use std::{thread, time};
 use vsock::{VsockListener};
-use std::io::Read;
+use std::io::{Read, Write};
 use std::net::Shutdown;
+use base64::{Engine as _, engine::general_purpose};
 
 use crate::defaults::debug;
+use crate::mmds::{FlakeMetadata, FlakeMount};
+use crate::session::Sessions;
 
 fn main() {
     /*!
     Simple Command Init (sci) is a tool which executes the provided
-    command in the run=... cmdline variable or through a vsock
+    command in the run_b64=... or run=... cmdline variable or through a vsock
     after preparation of an execution environment for the purpose to
     run a command inside of a firecracker instance.
 
     if provided via the overlay_root=/dev/block_device kernel boot
     parameter, sci also prepares the root filesystem as an overlay
     using the given block device for writing.
+
+    if provided via the sci_mmds=ADDRESS kernel boot parameter, the
+    command, its environment, user and mounts are read from the flake
+    metadata in the MMDS data store instead of the kernel cmdline.
     !*/
     setup_logger();
 
+    // command of a resume mode session, started through socat
+    if let Ok(encoded) = env::var(defaults::SCI_EXEC) {
+        exec_session(&encoded)
+    }
+
     let mut args: Vec<String> = vec![];
     let mut call: Command;
     let mut do_exec = false;
+    let mut new_root = false;
     let mut ok = true;
 
     // print user space env
@@ -66,9 +81,29 @@ fn main() {
         debug(&format!("{}: {}", key, value));
     }
 
-    // parse commandline from run environment variable
-    match env::var("run").ok() {
-        Some(call_cmd) => {
+    // read flake metadata, if published through MMDS
+    let metadata = get_metadata();
+
+    // parse commandline from metadata or run environment variables
+    match (&metadata, env::var("run_b64").ok(), env::var("run").ok()) {
+        (Some(flake), _, _) => {
+            debug(&format!(
+                "Running flake {} as {}", flake.name, flake.instance
+            ));
+            args = flake.command.clone()
+        },
+        (None, Some(encoded), _) => {
+            match decode_run(&encoded) {
+                Ok(call_params) => {
+                    args = call_params
+                },
+                Err(error) => {
+                    error!("{}", error);
+                    do_reboot(false)
+                }
+            }
+        },
+        (None, None, Some(call_cmd)) => {
             match shell_words::split(&call_cmd) {
                 Ok(call_params) => {
                     args = call_params
@@ -79,12 +114,18 @@ fn main() {
                 }
             }
         },
-        None => {
-            debug("No run=... cmdline parameter in env");
+        (None, None, None) => {
+            debug("No run_b64=... or run=... cmdline parameter in env");
             do_reboot(false)
         }
     }
 
+    // take environment assignments preceding the command
+    let (mut app_env, args) = split_env(args);
+    if let Some(flake) = &metadata {
+        app_env.extend(flake.env.clone());
+    }
+
     // sanity check on command to call
     if args[0].is_empty() {
         debug("No command to execute specified");
@@ -103,17 +144,7 @@ fn main() {
         Some(overlay) => {
             // overlay device is specified, mount the device and
             // prepare the folder structure
-            let mut modprobe = Command::new(defaults::PROBE_MODULE);
-            modprobe.arg("overlay");
-            debug(&format!(
-                "CALL: {} -> {:?}", defaults::PROBE_MODULE, modprobe.get_args()
-            ));
-            match modprobe.status() {
-                Ok(_) => { },
-                Err(error) => {
-                    debug(&format!("Loading overlay module failed: {}", error));
-                }
-            }
+            load_module("overlay");
             debug(&format!("Mounting overlayfs RW({})", overlay.as_str()));
             match Mount::builder()
                 .fstype("ext2").mount(overlay.as_str(), "/overlayroot")
@@ -171,86 +202,110 @@ fn main() {
                     }
                 }
             }
-            // Call specified command through switch root into the overlay
+            new_root = true;
+        },
+        None => {
+            // stack the read-only include device on top of the root
+            if let Ok(include) = env::var("include_root") {
+                ok = mount_include_root(&include);
+                new_root = true;
+            }
+        }
+    };
+
+    // Mount the extra data drives into the root of the command
+    if ok {
+        let drives = match &metadata {
+            Some(flake) => flake.mounts.clone(),
+            None => get_drives()
+        };
+        ok = mount_drives(
+            if new_root { defaults::OVERLAY_ROOT } else { "" }, &drives
+        );
+    }
+
+    if new_root {
+        // Call specified command through switch root into the overlay
+        if ok {
+            move_mounts(defaults::OVERLAY_ROOT);
+            let root = Path::new(defaults::OVERLAY_ROOT);
+            match env::set_current_dir(root) {
+                Ok(_) => {
+                    debug(&format!(
+                        "Changed working directory to {}", root.display()
+                    ));
+                    ok = true;
+                },
+                Err(error) => {
+                    debug(&format!(
+                        "Failed to change working directory: {}", error
+                    ));
+                    ok = false;
+                }
+            }
+        }
+        if do_exec {
+            call = Command::new(defaults::SWITCH_ROOT);
+            call.arg(".").arg(&args[0]);
+        } else {
+            call = Command::new(&args[0]);
             if ok {
-                move_mounts(defaults::OVERLAY_ROOT);
-                let root = Path::new(defaults::OVERLAY_ROOT);
-                match env::set_current_dir(root) {
+                let mut pivot = Command::new(defaults::PIVOT_ROOT);
+                pivot.arg(".").arg("mnt");
+                debug(&format!(
+                    "CALL: {} -> {:?}",
+                    defaults::PIVOT_ROOT, pivot.get_args()
+                ));
+                match pivot.status() {
                     Ok(_) => {
                         debug(&format!(
-                            "Changed working directory to {}", root.display()
+                            "{} is now the new root", defaults::OVERLAY_ROOT
                         ));
                         ok = true;
                     },
                     Err(error) => {
-                        debug(&format!(
-                            "Failed to change working directory: {}", error
-                        ));
+                        debug(&format!("Failed to pivot_root: {}", error));
                         ok = false;
                     }
                 }
+                mount_basic_fs();
+                setup_resolver_link();
             }
-            if do_exec {
-                call = Command::new(defaults::SWITCH_ROOT);
-                call.arg(".").arg(&args[0]);
-            } else {
-                call = Command::new(&args[0]);
-                if ok {
-                    let mut pivot = Command::new(defaults::PIVOT_ROOT);
-                    pivot.arg(".").arg("mnt");
-                    debug(&format!(
-                        "CALL: {} -> {:?}",
-                        defaults::PIVOT_ROOT, pivot.get_args()
-                    ));
-                    match pivot.status() {
-                        Ok(_) => {
-                            debug(&format!(
-                                "{} is now the new root", defaults::OVERLAY_ROOT
-                            ));
-                            ok = true;
-                        },
-                        Err(error) => {
-                            debug(&format!("Failed to pivot_root: {}", error));
-                            ok = false;
-                        }
-                    }
-                    mount_basic_fs();
-                    setup_resolver_link();
-                }
-            }
-        },
-        None => {
-            // Call command in current environment
-            call = Command::new(&args[0]);
         }
-    };
+    } else {
+        // Call command in current environment
+        call = Command::new(&args[0]);
+    }
 
     // Setup command call parameters
     for arg in &args[1..] {
         call.arg(arg);
     }
 
+    // Setup command environment
+    call.envs(app_env);
+
+    // Run the command with the identity of the calling user, if requested
+    let identity = match &metadata {
+        Some(flake) => flake.user.map(|user| (user.uid, user.gid)),
+        None => get_identity()
+    };
+    if let Some((uid, gid)) = identity {
+        if ! do_exec {
+            debug(&format!("Running command as {}:{}", uid, gid));
+            call.uid(uid).gid(gid);
+        }
+    }
+
     // Perform execution tasks
     if ! ok {
         do_reboot(ok)
     }
+    start_forwards();
     match env::var("sci_resume").ok() {
         Some(_) => {
             // resume mode; check if vhost transport is loaded
-            let mut modprobe = Command::new(defaults::PROBE_MODULE);
-            modprobe.arg(defaults::VHOST_TRANSPORT);
-            debug(&format!(
-                "CALL: {} -> {:?}", defaults::PROBE_MODULE, modprobe.get_args()
-            ));
-            match modprobe.status() {
-                Ok(_) => { },
-                Err(error) => {
-                    debug(&format!(
-                        "Loading {} module failed: {}",
-                        defaults::VHOST_TRANSPORT, error
-                    ));
-                }
-            }
+            load_module(defaults::VHOST_TRANSPORT);
             // start vsock listener on VM_PORT, wait for command(s) in a loop
             // A received command turns into an socat process calling
             // the command with an expected listener
@@ -258,14 +313,20 @@ fn main() {
             //
             // sudo socat UNIX-CONNECT:/run/sci_cmd_XXX.sock -
             // CONNECT defaults::VM_PORT(52)
+            // exec exec_port BASE64_ARGV
             // --> send the command to call and quit
             //
-            // sudo socat VSOCK-CONNECT:2:exec_port EXEC:cmd
+            // sudo socat VSOCK-CONNECT:2:exec_port EXEC:sci
+            // --> sci runs the decoded command in place of itself
             // --> connects to the listener instance on the host (pilot)
             //
             // The above procedure needs to be implemeted as
             // part of the firecracker-pilot resume code
             //
+            // Instead of a command the connection can carry
+            // VM_SESSIONS to list the running commands or VM_QUIT
+            // to terminate them and shutdown the VM
+            //
             debug(&format!(
                 "Binding vsock CID={} on port={}",
                 defaults::GUEST_CID, defaults::VM_PORT
@@ -274,10 +335,15 @@ fn main() {
                 defaults::GUEST_CID, defaults::VM_PORT
             ) {
                 Ok(listener) => {
+                    let mut sessions = Sessions::default();
                     // Enter main loop
                     loop {
                         match listener.accept() {
                             Ok((mut stream, addr)) => {
+                                // collect the socat children of finished
+                                // sessions, such that they do not pile
+                                // up as zombies
+                                sessions.reap();
                                 // read command string from incoming connection
                                 debug(&format!(
                                     "Accepted incoming connection from: {}:{}",
@@ -301,36 +367,94 @@ fn main() {
                                         ));
                                     }
                                 };
-                                stream.shutdown(Shutdown::Both).unwrap();
                                 debug(&format!(
                                     "CALL RAW BUF: {}", call_str
                                 ));
-                                let mut call_stack: Vec<&str> =
-                                    call_str.split(' ').collect();
-                                let exec_port = call_stack.pop().unwrap();
-                                let exec_cmd = call_stack.join(" ");
+                                let reply = match call_str.as_str() {
+                                    // connection check of the pilot
+                                    "" => Some(String::new()),
+                                    defaults::VM_SESSIONS => {
+                                        Some(sessions.report())
+                                    },
+                                    defaults::VM_QUIT => {
+                                        Some("OK\n".to_string())
+                                    },
+                                    _ => None
+                                };
+                                if let Some(reply) = &reply {
+                                    if let Err(error) =
+                                        stream.write_all(reply.as_bytes())
+                                    {
+                                        debug(&format!(
+                                            "Failed to reply: {}", error
+                                        ));
+                                    }
+                                }
+                                let _ = stream.shutdown(Shutdown::Both);
+                                if call_str == defaults::VM_QUIT {
+                                    sessions.terminate();
+                                    break
+                                }
+                                if reply.is_some() {
+                                    continue
+                                }
+                                // exec PORT BASE64_ARGV
+                                let (exec_port, encoded) = match call_str
+                                    .split(' ')
+                                    .collect::<Vec<&str>>()
+                                    .as_slice()
+                                {
+                                    ["exec", port, encoded] => {
+                                        (port.to_string(), encoded.to_string())
+                                    },
+                                    _ => {
+                                        error!("Invalid request: {}", call_str);
+                                        continue
+                                    }
+                                };
+                                let exec_cmd = match decode_run(&encoded) {
+                                    Ok(args) => shell_words::join(args),
+                                    Err(error) => {
+                                        error!("{}", error);
+                                        continue
+                                    }
+                                };
+                                let mut exec_opts = vec![
+                                    "pty".to_string(),
+                                    "stderr".to_string(),
+                                    "setsid".to_string(),
+                                    "sigint".to_string(),
+                                    "sane".to_string(),
+                                    "ctty".to_string(),
+                                    "echo=0".to_string()
+                                ];
+                                if let Some((uid, gid)) = identity {
+                                    exec_opts.push(format!("setgid={}", gid));
+                                    exec_opts.push(format!("setuid={}", uid));
+                                }
+                                // the command is passed on to sci encoded,
+                                // such that socat does not parse it
                                 call = Command::new(defaults::SOCAT);
+                                call.env(defaults::SCI_EXEC, &encoded);
                                 call
                                     .arg(&format!(
                                         "VSOCK-CONNECT:2:{}", exec_port
                                     ))
                                     .arg(&format!(
-                                        "EXEC:'{}',{},{},{},{},{},{},{}",
-                                        exec_cmd,
-                                        "pty",
-                                        "stderr",
-                                        "setsid",
-                                        "sigint",
-                                        "sane",
-                                        "ctty",
-                                        "echo=0"
+                                        "EXEC:{},{}",
+                                        defaults::SCI,
+                                        exec_opts.join(",")
                                     ));
                                 debug(&format!(
                                     "CALL: {} -> {:?}",
                                     defaults::SOCAT, call.get_args()
                                 ));
                                 match call.spawn() {
-                                    Ok(_) => { },
+                                    Ok(child) => {
+                                        sessions.add(
+                                            child, &exec_port, &exec_cmd
+                                        );
+                                    },
                                     Err(error) => {
                                         debug(&format!(
                                             "VSOCK-CONNECT failed with: {}",
@@ -376,6 +500,10 @@ fn main() {
 }
 
 fn do_reboot(ok: bool) {
+    // write back the overlay and the data drives
+    unsafe {
+        libc::sync();
+    }
     debug("Rebooting...");
     if ! ok {
         // give potential error messages some time to settle
@@ -390,6 +518,314 @@ fn do_reboot(ok: bool) {
     }
 }
 
+fn load_module(module: &str) {
+    /*!
+    Load the given kernel module
+    !*/
+    let mut modprobe = Command::new(defaults::PROBE_MODULE);
+    modprobe.arg(module);
+    debug(&format!(
+        "CALL: {} -> {:?}", defaults::PROBE_MODULE, modprobe.get_args()
+    ));
+    match modprobe.status() {
+        Ok(_) => { },
+        Err(error) => {
+            debug(&format!("Loading {} module failed: {}", module, error));
+        }
+    }
+}
+
+fn start_forwards() {
+    /*!
+    Start the guest side relays of the forwards given by the
+    sci_forward=TO:VSOCK_PORT:GUEST_PORT,... kernel boot parameter.
+    For TO=host a TCP port on the loopback is relayed to the
+    host, for TO=guest connections from the host are relayed
+    to the TCP port on the loopback
+    !*/
+    let forwards = match env::var("sci_forward").ok() {
+        Some(forwards) => forwards,
+        None => return
+    };
+    load_module(defaults::VHOST_TRANSPORT);
+    for forward in forwards.split(',') {
+        let spec: Vec<&str> = forward.split(':').collect();
+        let mut relay = Command::new(defaults::SOCAT);
+        match spec[..] {
+            ["host", vsock_port, guest_port] => {
+                relay
+                    .arg(format!(
+                        "TCP-LISTEN:{},bind=127.0.0.1,reuseaddr,fork",
+                        guest_port
+                    ))
+                    .arg(format!("VSOCK-CONNECT:2:{}", vsock_port));
+            },
+            ["guest", vsock_port, guest_port] => {
+                relay
+                    .arg(format!("VSOCK-LISTEN:{},fork", vsock_port))
+                    .arg(format!("TCP:127.0.0.1:{}", guest_port));
+            },
+            _ => {
+                debug(&format!("Invalid sci_forward entry: {}", forward));
+                continue
+            }
+        }
+        debug(&format!(
+            "CALL: {} -> {:?}", defaults::SOCAT, relay.get_args()
+        ));
+        match relay.spawn() {
+            Ok(_) => { },
+            Err(error) => {
+                debug(&format!("Failed to start relay: {}", error));
+            }
+        }
+    }
+}
+
+fn decode_run(encoded: &str) -> Result<Vec<String>, String> {
+    /*!
+    Decode the command from the run_b64= kernel boot parameter,
+    the base64 encoding of the NUL terminated arguments. A value
+    cut off by the kernel cmdline limit misses the final NUL
+    !*/
+    let truncated = format!(
+        "run_b64= of {} bytes is truncated, the command exceeds \
+        the kernel cmdline limit of {} bytes",
+        encoded.len(), defaults::KERNEL_CMDLINE_MAX
+    );
+    if encoded.len() % 4 != 0 {
+        return Err(truncated)
+    }
+    let decoded = general_purpose::STANDARD.decode(encoded).map_err(
+        |error| format!("Failed to decode run_b64=: {}", error)
+    )?;
+    match decoded.strip_suffix(&[0]) {
+        Some(args) => args
+            .split(|byte| *byte == 0)
+            .map(|arg| String::from_utf8(arg.to_vec()).map_err(
+                |error| format!("Invalid argument in run_b64=: {}", error)
+            ))
+            .collect(),
+        None => Err(truncated)
+    }
+}
+
+fn exec_session(encoded: &str) -> ! {
+    /*!
+    Replace ourselves by the command of a session, given as
+    the base64 encoding of the NUL terminated environment
+    assignments, command and arguments
+    !*/
+    let (exec_env, args) = match decode_run(encoded) {
+        Ok(args) => split_env(args),
+        Err(error) => {
+            eprintln!("{}", error);
+            exit(1)
+        }
+    };
+    if args.is_empty() {
+        eprintln!("No command to run");
+        exit(1)
+    }
+    let error = Command::new(&args[0])
+        .args(&args[1..])
+        .env_remove(defaults::SCI_EXEC)
+        .envs(exec_env)
+        .exec();
+    eprintln!("Failed to run {}: {}", args[0], error);
+    exit(1)
+}
+
+fn split_env(args: Vec<String>) -> (Vec<(String, String)>, Vec<String>) {
+    /*!
+    Split leading NAME=VALUE assignments from the command
+    and its arguments. The command itself is an absolute
+    path and therefore never taken as an assignment
+    !*/
+    let mut env: Vec<(String, String)> = Vec::new();
+    let mut args = args.into_iter().peekable();
+    while let Some(arg) = args.peek() {
+        match arg.split_once('=') {
+            Some((name, value)) if is_var_name(name) => {
+                env.push((name.to_string(), value.to_string()));
+                args.next();
+            },
+            _ => break
+        }
+    }
+    (env, args.collect())
+}
+
+fn is_var_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().map(|c| c.is_ascii_alphabetic() || c == '_').unwrap_or(false)
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn get_identity() -> Option<(u32, u32)> {
+    /*!
+    Get uid and gid to run the command with from the
+    sci_identity=UID:GID kernel boot parameter
+    !*/
+    let identity = env::var("sci_identity").ok()?;
+    let (uid, gid) = identity.split_once(':')?;
+    match (uid.parse::<u32>(), gid.parse::<u32>()) {
+        (Ok(uid), Ok(gid)) => Some((uid, gid)),
+        _ => {
+            debug(&format!("Invalid sci_identity={}", identity));
+            None
+        }
+    }
+}
+
+fn mount_include_root(include: &str) -> bool {
+    /*!
+    Stack the read-only include device as lowerdir on top of
+    the root filesystem. There is no overlay device to write
+    to, the mount points are therefore placed on a tmpfs
+    !*/
+    load_module("overlay");
+    match Mount::builder().fstype("tmpfs").mount("tmpfs", "/overlayroot") {
+        Ok(_) => debug("Mounted tmpfs on /overlayroot"),
+        Err(error) => {
+            debug(&format!("Failed to mount tmpfs on /overlayroot: {}", error));
+            return false
+        }
+    }
+    for dir in [defaults::OVERLAY_ROOT, defaults::INCLUDE_ROOT].iter() {
+        if let Err(error) = fs::create_dir_all(dir) {
+            debug(&format!("Error creating directory {}: {}", dir, error));
+            return false
+        }
+    }
+    debug(&format!("Mounting include drive RO({})", include));
+    match Mount::builder()
+        .fstype("ext2")
+        .flags(MountFlags::RDONLY)
+        .mount(include, defaults::INCLUDE_ROOT)
+    {
+        Ok(_) => debug(&format!(
+            "Mounted {} on {}", include, defaults::INCLUDE_ROOT
+        )),
+        Err(error) => {
+            debug(&format!("Failed to mount include drive: {}", error));
+            return false
+        }
+    }
+    match Mount::builder()
+        .fstype("overlay")
+        .data(&format!("lowerdir={}:/", defaults::INCLUDE_ROOT))
+        .mount("overlay", defaults::OVERLAY_ROOT)
+    {
+        Ok(_) => {
+            debug(&format!("Mounted overlay on {}", defaults::OVERLAY_ROOT));
+            true
+        },
+        Err(error) => {
+            debug(&format!("Failed to mount overlayroot: {}", error));
+            false
+        }
+    }
+}
+
+fn get_metadata() -> Option<FlakeMetadata> {
+    /*!
+    Get the flake metadata from the MMDS data store given by the
+    sci_mmds=ADDRESS kernel boot parameter. The metadata is the
+    authoritative source, failing to read it is fatal
+    !*/
+    let address = env::var("sci_mmds").ok()?;
+    match mmds::fetch(&address) {
+        Ok(flake) => Some(flake),
+        Err(error) => {
+            debug(&format!("Failed to read flake metadata: {}", error));
+            do_reboot(false);
+            None
+        }
+    }
+}
+
+fn get_drives() -> Vec<FlakeMount> {
+    /*!
+    Get the extra data drives from the
+    sci_drives=DRIVE_ID:MOUNT_POINT:ro|rw,... kernel boot parameter
+    !*/
+    let drives = match env::var("sci_drives").ok() {
+        Some(drives) => drives,
+        None => return vec![]
+    };
+    let mut mounts: Vec<FlakeMount> = Vec::new();
+    for drive in drives.split(',') {
+        let spec: Vec<&str> = drive.split(':').collect();
+        match spec[..] {
+            [drive_id, mount_point, mode] if mode == "ro" || mode == "rw" => {
+                mounts.push(FlakeMount {
+                    drive: drive_id.to_string(),
+                    target: mount_point.to_string(),
+                    read_only: mode == "ro"
+                });
+            },
+            _ => {
+                debug(&format!("Invalid sci_drives entry: {}", drive));
+            }
+        }
+    }
+    mounts
+}
+
+fn mount_drives(root: &str, drives: &[FlakeMount]) -> bool {
+    /*!
+    Mount the extra data drives below the given root. The
+    block device of a drive is found through its serial,
+    which firecracker sets to the drive id
+    !*/
+    for drive in drives {
+        let flags = if drive.read_only {
+            MountFlags::RDONLY
+        } else {
+            MountFlags::empty()
+        };
+        let device = match find_drive(&drive.drive) {
+            Some(device) => device,
+            None => {
+                debug(&format!("No block device for drive {}", drive.drive));
+                return false
+            }
+        };
+        let target = format!("{}{}", root, drive.target);
+        if let Err(error) = fs::create_dir_all(&target) {
+            debug(&format!("Error creating directory {}: {}", target, error));
+        }
+        match Mount::builder().flags(flags).mount(&device, &target) {
+            Ok(_) => debug(&format!("Mounted {} on {}", device, target)),
+            Err(error) => {
+                debug(&format!(
+                    "Failed to mount drive {}: {}", drive.drive, error
+                ));
+                return false
+            }
+        }
+    }
+    true
+}
+
+fn find_drive(drive_id: &str) -> Option<String> {
+    /*!
+    Lookup the block device of the given drive id
+    !*/
+    for entry in fs::read_dir("/sys/block").ok()?.flatten() {
+        let serial = fs::read_to_string(
+            entry.path().join("serial")
+        ).unwrap_or_default();
+        if serial.trim_end_matches(char::from(0)).trim() == drive_id {
+            return Some(
+                format!("/dev/{}", entry.file_name().to_string_lossy())
+            )
+        }
+    }
+    None
+}
+
 fn setup_resolver_link() {
     if Path::new(defaults::SYSTEMD_NETWORK_RESOLV_CONF).exists() {
         match symlink(
@@ -471,3 +907,56 @@ fn setup_logger() {
 
     env_logger::init_from_env(env);
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn encode_run(run: &[&str]) -> String {
+        // same encoding as firecracker-pilot
+        let mut argv: Vec<u8> = Vec::new();
+        for arg in run {
+            argv.extend_from_slice(arg.as_bytes());
+            argv.push(0);
+        }
+        general_purpose::STANDARD.encode(argv)
+    }
+
+    #[test]
+    fn test_decode_run() {
+        let run = [
+            "NAME=it's \"quoted\"", "A=b=c", "bash", "-c",
+            "echo 'one'\necho \"two\"", "", "grüße ✓", " spaced arg "
+        ];
+        assert_eq!(decode_run(&encode_run(&run)).unwrap(), run);
+    }
+
+    #[test]
+    fn test_decode_run_truncated() {
+        let encoded = encode_run(&["ls", "-l", "/tmp"]);
+        let error = decode_run(&encoded[..encoded.len() - 1]).unwrap_err();
+        assert!(error.contains("truncated"));
+        // cut off at a multiple of four, without the final NUL
+        let error = decode_run(&encoded[..12]).unwrap_err();
+        assert!(error.contains("truncated"));
+    }
+
+    #[test]
+    fn test_decode_run_missing_nul() {
+        let encoded = general_purpose::STANDARD.encode("ls\0-l");
+        let error = decode_run(&encoded).unwrap_err();
+        assert!(error.contains("truncated"));
+    }
+
+    #[test]
+    fn test_split_env() {
+        let run = decode_run(&encode_run(
+            &["NAME=it's a \"value\"", "1X=no", "env"]
+        )).unwrap();
+        let (env, args) = split_env(run);
+        assert_eq!(
+            env, vec![("NAME".to_string(), "it's a \"value\"".to_string())]
+        );
+        assert_eq!(args, vec!["1X=no", "env"]);
+    }
+}
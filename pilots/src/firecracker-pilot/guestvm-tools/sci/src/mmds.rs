@@ -0,0 +1,163 @@
+//
+// Copyright (c) 2022 Elektrobit Automotive GmbH
+//
+// This file is part of flake-pilot
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+//
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
+use std::net::{IpAddr, SocketAddr, TcpStream};
+use std::{thread, time};
+use serde::Deserialize;
+
+use crate::defaults;
+use crate::defaults::debug;
+
+#[derive(Debug, Deserialize)]
+struct Metadata {
+    flake: FlakeMetadata,
+}
+
+/// Flake metadata published by firecracker-pilot
+#[derive(Debug, Clone, Deserialize)]
+pub struct FlakeMetadata {
+    pub name: String,
+    pub instance: String,
+    pub command: Vec<String>,
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+    pub user: Option<FlakeUser>,
+    #[serde(default)]
+    pub mounts: Vec<FlakeMount>,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct FlakeUser {
+    pub uid: u32,
+    pub gid: u32,
+}
+
+/// Extra data drive, mounted at target
+#[derive(Debug, Clone, Deserialize)]
+pub struct FlakeMount {
+    pub drive: String,
+    pub target: String,
+    #[serde(default)]
+    pub read_only: bool,
+}
+
+pub fn fetch(address: &str) -> Result<FlakeMetadata, String> {
+    /*!
+    Read the flake metadata from the MMDS data store at the
+    given address, using a session token. The network of the
+    guest might not be ready yet, thus the retries
+    !*/
+    let address: IpAddr = address.parse().map_err(
+        |error| format!("Invalid MMDS address {}: {}", address, error)
+    )?;
+    let mut result = Err("No MMDS request sent".to_string());
+    for _ in 0..defaults::MMDS_RETRIES {
+        result = request(
+            address, "PUT", "/latest/api/token",
+            &[("X-metadata-token-ttl-seconds", "60")]
+        ).and_then(|token| request(
+            address, "GET", "/",
+            &[
+                ("X-metadata-token", token.trim()),
+                ("Accept", "application/json")
+            ]
+        ));
+        if result.is_ok() {
+            break
+        }
+        thread::sleep(
+            time::Duration::from_millis(defaults::MMDS_RETRY_MSEC)
+        );
+    }
+    let body = result?;
+    debug(&format!("MMDS: {}", body));
+    serde_json::from_str::<Metadata>(&body)
+        .map(|metadata| metadata.flake)
+        .map_err(|error| format!("Invalid flake metadata: {}", error))
+}
+
+fn request(
+    address: IpAddr, method: &str, path: &str, headers: &[(&str, &str)]
+) -> Result<String, String> {
+    /*!
+    Send a HTTP/1.1 request to the MMDS and return the body
+    of a successful response
+    !*/
+    let timeout = time::Duration::from_millis(defaults::MMDS_TIMEOUT_MSEC);
+    let mut stream = TcpStream::connect_timeout(
+        &SocketAddr::new(address, 80), timeout
+    ).map_err(|error| format!("Failed to connect MMDS: {}", error))?;
+    stream.set_read_timeout(Some(timeout)).map_err(|e| e.to_string())?;
+
+    let mut request = format!(
+        "{} {} HTTP/1.1\r\nHost: {}\r\nContent-Length: 0\r\n",
+        method, path, address
+    );
+    for (name, value) in headers {
+        request.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    request.push_str("\r\n");
+    stream.write_all(request.as_bytes())
+        .map_err(|error| format!("Failed to send MMDS request: {}", error))?;
+
+    // read until the body is complete, the connection may stay open
+    let mut response: Vec<u8> = Vec::new();
+    let mut buffer = [0; 4096];
+    loop {
+        let count = stream.read(&mut buffer)
+            .map_err(|error| format!("Failed to read MMDS: {}", error))?;
+        response.extend_from_slice(&buffer[..count]);
+        if count == 0 || body_complete(&response) {
+            break
+        }
+    }
+    let response = String::from_utf8_lossy(&response).to_string();
+    let (head, body) = response.split_once("\r\n\r\n")
+        .ok_or_else(|| "Incomplete MMDS response".to_string())?;
+    let status = head.lines().next().unwrap_or_default();
+    if status.split(' ').nth(1) != Some("200") {
+        return Err(format!("MMDS {} {}: {}", method, path, status))
+    }
+    Ok(body.to_string())
+}
+
+fn body_complete(response: &[u8]) -> bool {
+    /*!
+    Check if the response holds the full body as given
+    by its Content-Length header
+    !*/
+    let response = String::from_utf8_lossy(response);
+    match response.split_once("\r\n\r\n") {
+        Some((head, body)) => {
+            let length = head.lines()
+                .filter_map(|line| line.split_once(':'))
+                .find(|(name, _)| name.eq_ignore_ascii_case("content-length"))
+                .and_then(|(_, value)| value.trim().parse::<usize>().ok())
+                .unwrap_or(0);
+            body.len() >= length
+        },
+        None => false
+    }
+}
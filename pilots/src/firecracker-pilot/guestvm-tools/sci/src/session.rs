@@ -0,0 +1,104 @@
+//
+// Copyright (c) 2022 Elektrobit Automotive GmbH
+//
+// This file is part of flake-pilot
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+//
+use std::process::Child;
+use std::{thread, time};
+
+use crate::defaults;
+use crate::defaults::debug;
+
+/// A command of a resume VM, run by socat with
+/// its input and output at the exec port of the host
+pub struct Session {
+    child: Child,
+    port: String,
+    command: String,
+}
+
+#[derive(Default)]
+pub struct Sessions {
+    sessions: Vec<Session>,
+}
+
+impl Sessions {
+    pub fn add(&mut self, child: Child, port: &str, command: &str) {
+        /*!
+        Track the session of a started command
+        !*/
+        debug(&format!("Session {} on port {}", child.id(), port));
+        self.sessions.push(Session {
+            child, port: port.to_string(), command: command.to_string()
+        });
+    }
+
+    pub fn reap(&mut self) {
+        /*!
+        Forget about sessions whose command has finished
+        !*/
+        self.sessions.retain_mut(
+            |session| matches!(session.child.try_wait(), Ok(None))
+        );
+    }
+
+    pub fn report(&mut self) -> String {
+        /*!
+        List the active sessions, one line each with
+        the PID, the exec port and the command
+        !*/
+        self.reap();
+        self.sessions.iter()
+            .map(|session| format!(
+                "{} {} {}\n",
+                session.child.id(), session.port, session.command
+            ))
+            .collect()
+    }
+
+    pub fn terminate(&mut self) {
+        /*!
+        Terminate all sessions, killing the ones
+        which are still running after a grace period
+        !*/
+        self.reap();
+        for session in &self.sessions {
+            debug(&format!("Terminating session {}", session.child.id()));
+            unsafe {
+                libc::kill(session.child.id() as libc::pid_t, libc::SIGTERM);
+            }
+        }
+        let wait = time::Duration::from_millis(defaults::SESSION_WAIT_MSEC);
+        for _ in 0..defaults::SESSION_TERM_MSEC / defaults::SESSION_WAIT_MSEC {
+            self.reap();
+            if self.sessions.is_empty() {
+                return
+            }
+            thread::sleep(wait);
+        }
+        for session in self.sessions.iter_mut() {
+            debug(&format!("Killing session {}", session.child.id()));
+            let _ = session.child.kill();
+            let _ = session.child.wait();
+        }
+        self.sessions.clear();
+    }
+}
@@ -0,0 +1,186 @@
+//
+// Copyright (c) 2022 Elektrobit Automotive GmbH
+//
+// This file is part of flake-pilot
+//
+// Permission is hereby granted, free of charge, to any person obtaining a copy
+// of this software and associated documentation files (the "Software"), to deal
+// in the Software without restriction, including without limitation the rights
+// to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+// copies of the Software, and to permit persons to whom the Software is
+// furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+// IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+// OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+// SOFTWARE.
+//
+use crate::config::{Jailer, Seccomp};
+use crate::defaults::{self, debug};
+use flakes::user::User;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Jail ID of an instance
+///
+/// The jailer only allows alphanumeric characters and hyphens, up to
+/// 64 of them. Other characters of the instance name become hyphens,
+/// the hash of the full name keeps the IDs of instances apart.
+pub fn jail_id(instance: &str) -> String {
+    let hash = instance.bytes().fold(0x811c9dc5u32, |h, b| (h ^ b as u32).wrapping_mul(0x01000193));
+    let name: String = instance.chars().take(55).map(|c| if c.is_ascii_alphanumeric() { c } else { '-' }).collect();
+    format!("{}-{:08x}", name, hash)
+}
+
+/// Directory of the jail of an instance
+pub fn jail_dir(jailer: &Jailer, instance: &str) -> PathBuf {
+    let base = jailer.chroot_base_dir.clone().unwrap_or_else(|| PathBuf::from(defaults::JAILER_BASE_DIR));
+    let exec_file = Path::new(defaults::FIRECRACKER).file_name().unwrap();
+    base.join(exec_file).join(jail_id(instance))
+}
+
+/// Host path of a path inside of the jail root
+pub fn host_path(jailer: &Jailer, instance: &str, path: &str) -> PathBuf {
+    jail_dir(jailer, instance).join("root").join(path.trim_start_matches('/'))
+}
+
+/// Create the jail root of an instance, with the run directory
+/// of the vsock UDS owned by the jail user. A stale jail of the
+/// same instance is removed first.
+pub fn create_jail(jailer: &Jailer, instance: &str) -> bool {
+    remove_jail(jailer, instance);
+    let run_dir = host_path(jailer, instance, "/run");
+    let mut mkdir = User::ROOT.run("mkdir");
+    mkdir.arg("-p").arg(&run_dir);
+    run(mkdir) && chown(jailer, &run_dir)
+}
+
+/// Place a file into the jail root by a hard link or, across
+/// filesystems, by a bind mount. Files firecracker writes to are
+/// handed over to the jail user. Returns the path in the jail.
+pub fn link_file(jailer: &Jailer, instance: &str, source: &Path, name: &str, writable: bool) -> Option<String> {
+    let target = host_path(jailer, instance, name);
+    let mut link = User::ROOT.run("ln");
+    link.arg(source).arg(&target);
+    if !run(link) {
+        debug(&format!("Binding {} into the jail", source.display()));
+        let mut touch = User::ROOT.run("touch");
+        touch.arg(&target);
+        let mut bind = User::ROOT.run("mount");
+        bind.arg("--bind").arg(source).arg(&target);
+        if !run(touch) || !run(bind) {
+            return None;
+        }
+    }
+    if writable && !chown(jailer, &target) {
+        return None;
+    }
+    Some(format!("/{}", name))
+}
+
+/// Copy a file into the jail root, readable by the jail user
+/// only. Returns the path in the jail.
+pub fn install_file(jailer: &Jailer, instance: &str, source: &Path, name: &str) -> Option<String> {
+    let mut install = User::ROOT.run("install");
+    install
+        .arg("-m")
+        .arg("0400")
+        .arg("-o")
+        .arg(jailer.uid.to_string())
+        .arg("-g")
+        .arg(jailer.gid.to_string())
+        .arg(source)
+        .arg(host_path(jailer, instance, name));
+    run(install).then(|| format!("/{}", name))
+}
+
+/// Remove the jail of an instance, after releasing its bind mounts
+pub fn remove_jail(jailer: &Jailer, instance: &str) {
+    let jail = jail_dir(jailer, instance);
+    if !jail.exists() {
+        return;
+    }
+    let mounts = fs::read_to_string("/proc/mounts").unwrap_or_default();
+    for mount_point in mounts.lines().rev().filter_map(|line| line.split(' ').nth(1)) {
+        if Path::new(mount_point).starts_with(&jail) {
+            let mut umount = User::ROOT.run("umount");
+            umount.arg(mount_point);
+            run(umount);
+        }
+    }
+    let mut remove = User::ROOT.run("rm");
+    remove.arg("-rf").arg(&jail);
+    run(remove);
+}
+
+/// Arguments of the jailer, the ones of firecracker follow after "--"
+pub fn jailer_args(jailer: &Jailer, instance: &str) -> Vec<String> {
+    let mut args: Vec<String> = vec![
+        "--id".to_string(),
+        jail_id(instance),
+        "--exec-file".to_string(),
+        defaults::FIRECRACKER.to_string(),
+        "--uid".to_string(),
+        jailer.uid.to_string(),
+        "--gid".to_string(),
+        jailer.gid.to_string(),
+        "--chroot-base-dir".to_string(),
+        jailer.chroot_base_dir.clone().unwrap_or_else(|| PathBuf::from(defaults::JAILER_BASE_DIR)).display().to_string(),
+    ];
+    if let Some(version) = jailer.cgroup_version {
+        args.push("--cgroup-version".to_string());
+        args.push(version.to_string());
+    }
+    if let Some(parent_cgroup) = &jailer.parent_cgroup {
+        args.push("--parent-cgroup".to_string());
+        args.push(parent_cgroup.to_string());
+    }
+    for (name, value) in &jailer.cgroups {
+        args.push("--cgroup".to_string());
+        args.push(format!("{}={}", name, value));
+    }
+    args
+}
+
+/// Seccomp arguments of firecracker, a filter file is
+/// placed into the jail root to be found by firecracker
+pub fn seccomp_args(jailer: &Jailer, instance: &str) -> Option<Vec<String>> {
+    match &jailer.seccomp {
+        Seccomp::Default => Some(vec![]),
+        Seccomp::None => Some(vec!["--no-seccomp".to_string()]),
+        Seccomp::Filter(filter) => {
+            let filter = link_file(jailer, instance, filter, "seccomp.bpf", false)?;
+            Some(vec!["--seccomp-filter".to_string(), filter])
+        }
+    }
+}
+
+fn chown(jailer: &Jailer, path: &Path) -> bool {
+    let mut chown = User::ROOT.run("chown");
+    chown.arg(format!("{}:{}", jailer.uid, jailer.gid)).arg(path);
+    run(chown)
+}
+
+/// Run a command, reporting failures in debug mode
+fn run(mut command: Command) -> bool {
+    debug(&format!("sudo {:?}", command.get_args()));
+    match command.output() {
+        Ok(output) => {
+            if !output.status.success() {
+                debug(&String::from_utf8_lossy(&output.stderr));
+            }
+            output.status.success()
+        }
+        Err(error) => {
+            error!("Failed to execute {:?}: {:?}", command.get_args(), error);
+            false
+        }
+    }
+}
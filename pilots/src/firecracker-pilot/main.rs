@@ -33,13 +33,33 @@ pub mod app_path;
 pub mod firecracker;
 pub mod defaults;
 pub mod config;
+pub mod jailer;
+pub mod console;
 
 fn main() {
     setup_logger();
 
+    // the pilot also collects the console of its VMs
+    if let Ok(instance) = std::env::var(defaults::LOG_COLLECTOR) {
+        std::process::exit(console::collect(&instance));
+    }
+
+    // and relays the connections of forwards into its VMs
+    if let Some((vsock_uds_path, port)) = firecracker::relay_args() {
+        std::process::exit(firecracker::relay_to_guest(&vsock_uds_path, &port));
+    }
+
     let program_path = app_path::program_abs_path();
     let program_name = app_path::basename(&program_path);
 
+    if !firecracker::check_instance_name(&program_name) {
+        std::process::exit(1);
+    }
+
+    if !firecracker::check_setup() {
+        std::process::exit(1);
+    }
+
     let vm = firecracker::create(&program_name);
     firecracker::start(&program_name, vm);
 }
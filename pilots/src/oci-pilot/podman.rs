@@ -1,5 +1,5 @@
 use crate::prunner::PodmanRunner;
-use flakes::config::itf::InstanceMode;
+use flakes::config::{itf::InstanceMode, placeholders::Placeholders};
 use std::{io::Error, path::PathBuf};
 
 /// Podman runtime
@@ -14,11 +14,17 @@ impl PodmanPilot {
     /// Constructor of a new Podman Pilot instance
     pub(crate) fn new(debug: bool) -> Result<Self, Error> {
         let appdir = flakes::config::app_path()?;
-        Ok(PodmanPilot {
-            appdir: appdir.to_owned(),
-            runner: PodmanRunner::new(appdir.file_name().unwrap().to_str().unwrap().to_string(), flakes::config::get(), debug),
-            debug,
-        })
+        let app = appdir.file_name().unwrap().to_str().unwrap().to_string();
+        let cfg = flakes::config::get().expanded(&Placeholders::resolve(&app, &Self::instance_name(&app)))?;
+        Ok(PodmanPilot { appdir: appdir.to_owned(), runner: PodmanRunner::new(app, cfg, debug), debug })
+    }
+
+    /// Name of the instance, which is the app name followed by "@NAME" if given
+    fn instance_name(app: &str) -> String {
+        match std::env::args().skip(1).find(|arg| arg.starts_with('@') && !arg.starts_with("@@")) {
+            Some(name) => format!("{app}{name}"),
+            None => app.to_string(),
+        }
     }
 
     /// Start Podman Pilot instance
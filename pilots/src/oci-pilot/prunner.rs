@@ -1,5 +1,8 @@
 use crate::fgc::CidGarbageCollector;
-use flakes::config::itf::{FlakeConfig, InstanceMode};
+use flakes::config::{
+    itf::{FlakeCfgPathProperties, FlakeConfig, Identity, InstanceMode, NetworkMode},
+    setup::{translate_host_paths, HostIntegration},
+};
 use std::path::PathBuf;
 use std::process::Command;
 use std::{fs, thread};
@@ -111,22 +114,153 @@ impl PodmanRunner {
         &self.cfg
     }
 
-    /// Return target app from the config
-    fn get_target_app(&self) -> Result<String, Error> {
+    /// Return path properties of the called app from the config
+    fn get_path_props(&self) -> Result<&FlakeCfgPathProperties, Error> {
         let app_path = flakes::config::app_path()?;
         if self.debug {
             log::debug!("Host path: {:?}", app_path);
         }
 
-        let target = self.get_cfg().runtime().paths().get(&app_path);
-        if target.is_none() {
+        let props = self.get_cfg().runtime().paths().get_by_path(app_path);
+        if props.is_none() {
             if self.debug {
                 log::debug!("Unable to find specified target path by the host path. Configuration wrong?");
             }
             return Err(Error::new(std::io::ErrorKind::NotFound, "Target path not found"));
         }
 
-        Ok(target.unwrap().exports().as_os_str().to_str().to_owned().unwrap().to_string())
+        Ok(props.unwrap())
+    }
+
+    /// Return target app from the config
+    fn get_target_app(&self) -> Result<String, Error> {
+        Ok(self.get_path_props()?.exports().as_os_str().to_str().to_owned().unwrap().to_string())
+    }
+
+    /// Translate host integrations of the setup section into podman args
+    fn get_setup_args(&self) -> Result<Vec<String>, Error> {
+        Ok(self.get_integration_args(self.get_cfg().setup().integrations()?))
+    }
+
+    /// Translate host integrations into podman args
+    fn get_integration_args(&self, integrations: Vec<HostIntegration>) -> Vec<String> {
+        let mut args: Vec<String> = vec![];
+        for integration in integrations {
+            if self.debug {
+                log::debug!("Host integration: {:?}", integration);
+            }
+
+            match integration {
+                HostIntegration::Mount(m) => {
+                    args.push("--volume".to_string());
+                    args.push(format!("{}:{}:{}", m.source().display(), m.target().display(), m.mode().as_str()));
+                }
+                HostIntegration::Device(device) => {
+                    args.push("--device".to_string());
+                    args.push(device.display().to_string());
+                }
+                HostIntegration::Env(name, value) => {
+                    args.push("--env".to_string());
+                    args.push(format!("{name}={value}"));
+                }
+                HostIntegration::Workdir(dir) => {
+                    args.push("--workdir".to_string());
+                    args.push(dir.display().to_string());
+                }
+            }
+        }
+
+        args
+    }
+
+    /// Translate the runtime identity into podman args
+    fn get_identity_args(&self) -> Vec<String> {
+        let identity = self.get_cfg().runtime().identity();
+        let mut args: Vec<String> = vec![];
+        if identity == Identity::Host {
+            args.push("--userns=keep-id".to_string());
+        }
+        if let Some((uid, gid)) = identity.caller_ids() {
+            args.push("--user".to_string());
+            args.push(format!("{uid}:{gid}"));
+        }
+
+        args
+    }
+
+    /// Translate the environment policy into podman args
+    fn get_env_args(&self) -> Vec<String> {
+        let mut args: Vec<String> = vec![];
+        for (name, value) in self.get_cfg().runtime().env().resolve(std::env::vars()) {
+            args.push("--env".to_string());
+            args.push(format!("{name}={value}"));
+        }
+
+        args
+    }
+
+    /// Translate the resource limits into podman args
+    fn get_resource_args(&self) -> Vec<String> {
+        let resources = self.get_cfg().runtime().resources();
+        let mut args: Vec<String> = vec![];
+        if let Some(cpus) = resources.cpus() {
+            args.push(format!("--cpus={cpus}"));
+        }
+        if let Some(shares) = resources.cpu_shares() {
+            args.push(format!("--cpu-shares={shares}"));
+        }
+        if let Some(memory) = resources.memory() {
+            args.push(format!("--memory={memory}b"));
+        }
+        if let Some(pids) = resources.pids() {
+            args.push(format!("--pids-limit={pids}"));
+        }
+        if let Some(weight) = resources.io_weight() {
+            args.push(format!("--blkio-weight={weight}"));
+        }
+
+        args
+    }
+
+    /// Get network args of the container: the network mode and the published ports
+    fn get_network_args(&self) -> Vec<String> {
+        let network = self.get_cfg().runtime().network();
+        let mut args: Vec<String> = vec![];
+        match network.mode() {
+            NetworkMode::Default => {}
+            NetworkMode::Bridge(name) => args.push(format!("--network={name}")),
+            mode => args.push(format!("--network={mode}")),
+        }
+        for port in network.publish() {
+            args.push(format!("--publish={port}"));
+        }
+
+        args
+    }
+
+    /// Get arguments of the caller, which are passed to the app.
+    ///
+    /// If host path translation is enabled for the app, host paths
+    /// among them are mounted into the container, which adds more podman args.
+    fn get_app_args(&self) -> Result<(Vec<String>, Vec<String>), Error> {
+        let mut app_args: Vec<String> = vec![];
+        for arg in std::env::args().collect::<Vec<String>>().iter().skip(1) {
+            // "@blah" are escaped by adding an extra "@" so it becomes "@@blah".
+            // Here we unescape that.
+            if arg.starts_with("@@") {
+                app_args.push(arg[1..].to_string());
+            } else if !arg.starts_with('@') {
+                app_args.push(arg.to_string());
+            }
+        }
+
+        match self.get_path_props().ok().and_then(|props| props.host_paths()) {
+            Some(mode) => {
+                let (integrations, app_args) = translate_host_paths(&app_args, &std::env::current_dir()?, mode);
+                Ok((self.get_integration_args(integrations), app_args))
+            }
+            None => Ok((vec![], app_args)),
+        }
     }
 
     /// Create a CLI arguments for preparing the container
@@ -163,6 +297,14 @@ impl PodmanRunner {
             }
             args.extend(arg.split(' ').filter(|x| !x.is_empty()).map(|s| s.to_string()).collect::<Vec<String>>());
         }
+        args.extend(self.get_identity_args());
+        args.extend(self.get_env_args());
+        args.extend(self.get_resource_args());
+        args.extend(self.get_network_args());
+        args.extend(self.get_setup_args()?);
+
+        let (host_path_args, app_args) = self.get_app_args()?;
+        args.extend(host_path_args);
 
         // Container name or base name
         args.push(self.get_cfg().runtime().image_name().to_string());
@@ -175,15 +317,7 @@ impl PodmanRunner {
         }
 
         // Pass the rest of the stuff to the app
-        for arg in std::env::args().collect::<Vec<String>>().iter().skip(1) {
-            // "@blah" are escaped by adding an extra "@" so it becomes "@@blah".
-            // Here we unescape that.
-            if arg.starts_with("@@") {
-                args.push(arg[1..].to_string());
-            } else if !arg.starts_with('@') {
-                args.push(arg.to_string());
-            }
-        }
+        args.extend(app_args);
 
         let out = self.command().args(args).output()?;
         self.set_cid(String::from_utf8_lossy(&out.stdout).to_string());